@@ -0,0 +1,334 @@
+// A small language server to try the LSP client with, e.g. with
+// `[language.rust] lsp = "target/debug/examples/mock_lsp"` in config.toml:
+//
+// - `TODO` and `FIXME` are reported as a warning and an error
+// - hovering a word tells how often it appears
+// - the definition of a word is its first occurrence, its references are all of them
+// - renaming replaces whole words
+// - formatting trims trailing whitespace
+// - the code actions replace `TODO` with `DONE`, upper-case the line through a command
+//   and add a comment through `codeAction/resolve`
+// - completions are the words of the document and `mock_function`, also after a `.`
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+fn read(input: &mut impl BufRead) -> Option<Value> {
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            length = n.trim().parse().ok()?;
+        }
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn send(message: Value) {
+    let body = message.to_string();
+    let mut stdout = io::stdout();
+    write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    stdout.flush().unwrap();
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// The byte index of a position counted in UTF-16 code units.
+fn index(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let start = match text.split_inclusive('\n').take(line).map(str::len).sum() {
+        start if start <= text.len() => start,
+        _ => return text.len(),
+    };
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn position(text: &str, index: usize) -> Value {
+    let before = &text[..index];
+    let line = before.matches('\n').count();
+    let head = before.rfind('\n').map_or(0, |i| i + 1);
+    let character: usize = before[head..].chars().map(char::len_utf16).sum();
+    json!({ "line": line, "character": character })
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position(text, start), "end": position(text, end) })
+}
+
+// The word at a position.
+fn word_at(text: &str, position: &Value) -> Option<String> {
+    let i = index(text, position);
+    let start = text[..i]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word(*c))
+        .last()
+        .map_or(i, |(j, _)| j);
+    let end = text[i..]
+        .find(|c| !is_word(c))
+        .map_or(text.len(), |j| i + j);
+    Some(text[start..end].to_string()).filter(|w| !w.is_empty())
+}
+
+// The byte ranges of `word` as a whole word.
+fn occurrences(text: &str, word: &str) -> Vec<(usize, usize)> {
+    text.match_indices(word)
+        .filter(|(i, _)| {
+            let before = text[..*i].chars().next_back();
+            let after = text[i + word.len()..].chars().next();
+            !before.is_some_and(is_word) && !after.is_some_and(is_word)
+        })
+        .map(|(i, _)| (i, i + word.len()))
+        .collect()
+}
+
+fn line_of(text: &str, line: usize) -> (usize, &str) {
+    let start: usize = text.split_inclusive('\n').take(line).map(str::len).sum();
+    let rest = &text[start.min(text.len())..];
+    (start, rest.lines().next().unwrap_or_default())
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, String>,
+    next_id: u64,
+}
+
+impl Server {
+    fn publish(&self, uri: &str) {
+        let text = &self.documents[uri];
+        let mut diagnostics = Vec::new();
+        for (word, severity, message) in [
+            ("TODO", 2, "TODO left in the code"),
+            ("FIXME", 1, "FIXME left in the code"),
+        ] {
+            for (start, end) in occurrences(text, word) {
+                diagnostics.push(json!({
+                    "range": range(text, start, end),
+                    "severity": severity,
+                    "source": "mock",
+                    "message": message,
+                }));
+            }
+        }
+        send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }));
+    }
+
+    fn change(&mut self, uri: &str, changes: &[Value]) {
+        let text = self.documents.entry(uri.to_string()).or_default();
+        for change in changes {
+            let new = change["text"].as_str().unwrap_or_default();
+            if change["range"].is_null() {
+                *text = new.to_string();
+                continue;
+            }
+            let start = index(text, &change["range"]["start"]);
+            let end = index(text, &change["range"]["end"]).max(start);
+            text.replace_range(start..end, new);
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+                self.publish(uri);
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array().cloned();
+                self.change(uri, &changes.unwrap_or_default());
+                self.publish(uri);
+            }
+            "exit" => std::process::exit(0),
+            _ => {}
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = self.documents.get(uri).cloned().unwrap_or_default();
+        let word = word_at(&text, &params["position"]);
+        let locations = |word: &Option<String>| -> Vec<Value> {
+            let word = match word {
+                Some(word) => word,
+                None => return Vec::new(),
+            };
+            occurrences(&text, word)
+                .into_iter()
+                .map(|(start, end)| json!({ "uri": uri, "range": range(&text, start, end) }))
+                .collect()
+        };
+        match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 2, "save": {} },
+                    "completionProvider": { "triggerCharacters": ["."] },
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "renameProvider": true,
+                    "documentFormattingProvider": true,
+                    "codeActionProvider": { "resolveProvider": true },
+                    "executeCommandProvider": { "commands": ["mock.uppercase"] },
+                },
+                "serverInfo": { "name": "mock_lsp" },
+            }),
+            "textDocument/hover" => match &word {
+                Some(word) => json!({
+                    "contents": {
+                        "kind": "markdown",
+                        "value": format!(
+                            "```\n{}\n```\n\n`{}` appears {} times",
+                            word,
+                            word,
+                            occurrences(&text, word).len()
+                        ),
+                    },
+                }),
+                None => Value::Null,
+            },
+            "textDocument/completion" => {
+                let mut items = vec![json!({ "label": "mock_function", "kind": 3 })];
+                let mut words: Vec<_> = text
+                    .split(|c| !is_word(c))
+                    .filter(|w| w.len() > 2)
+                    .collect();
+                words.sort_unstable();
+                words.dedup();
+                items.extend(words.iter().map(|w| json!({ "label": w, "kind": 1 })));
+                json!({ "isIncomplete": false, "items": items })
+            }
+            "textDocument/definition" => locations(&word).into_iter().next().unwrap_or(Value::Null),
+            "textDocument/references" => Value::Array(locations(&word)),
+            "textDocument/rename" => {
+                let new_name = params["newName"].as_str().unwrap_or_default();
+                let edits: Vec<_> = locations(&word)
+                    .into_iter()
+                    .map(|l| json!({ "range": l["range"], "newText": new_name }))
+                    .collect();
+                match edits.is_empty() {
+                    true => Value::Null,
+                    false => json!({ "changes": { uri: edits } }),
+                }
+            }
+            "textDocument/formatting" => {
+                let mut edits = Vec::new();
+                let mut start = 0;
+                for line in text.split_inclusive('\n') {
+                    let content = line.strip_suffix('\n').unwrap_or(line);
+                    let trimmed = content.trim_end().len();
+                    if trimmed < content.len() {
+                        edits.push(json!({
+                            "range": range(&text, start + trimmed, start + content.len()),
+                            "newText": "",
+                        }));
+                    }
+                    start += line.len();
+                }
+                Value::Array(edits)
+            }
+            "textDocument/codeAction" => {
+                let row = params["range"]["start"]["line"].as_u64().unwrap_or(0) as usize;
+                let (head, line) = line_of(&text, row);
+                let mut actions = Vec::new();
+                if let Some(i) = line.find("TODO") {
+                    actions.push(json!({
+                        "title": "Replace TODO with DONE",
+                        "kind": "quickfix",
+                        "edit": { "changes": { uri: [{
+                            "range": range(&text, head + i, head + i + 4),
+                            "newText": "DONE",
+                        }] } },
+                    }));
+                }
+                actions.push(json!({
+                    "title": "Uppercase line",
+                    "command": "mock.uppercase",
+                    "arguments": [uri, row],
+                }));
+                actions.push(json!({
+                    "title": "Add a comment above",
+                    "kind": "refactor",
+                    "data": { "uri": uri, "line": row },
+                }));
+                Value::Array(actions)
+            }
+            "codeAction/resolve" => {
+                let uri = params["data"]["uri"].as_str().unwrap_or_default();
+                let row = params["data"]["line"].as_u64().unwrap_or(0) as usize;
+                let text = self.documents.get(uri).cloned().unwrap_or_default();
+                let (head, line) = line_of(&text, row);
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let mut action = params.clone();
+                action["edit"] = json!({ "changes": { uri: [{
+                    "range": range(&text, head, head),
+                    "newText": format!("{}// added by mock_lsp\n", indent),
+                }] } });
+                action
+            }
+            "workspace/executeCommand" => {
+                let uri = params["arguments"][0].as_str().unwrap_or_default();
+                let row = params["arguments"][1].as_u64().unwrap_or(0) as usize;
+                let text = self.documents.get(uri).cloned().unwrap_or_default();
+                let (head, line) = line_of(&text, row);
+                self.next_id += 1;
+                send(json!({
+                    "jsonrpc": "2.0",
+                    "id": format!("mock-{}", self.next_id),
+                    "method": "workspace/applyEdit",
+                    "params": { "edit": { "changes": { uri: [{
+                        "range": range(&text, head, head + line.len()),
+                        "newText": line.to_uppercase(),
+                    }] } } },
+                }));
+                Value::Null
+            }
+            _ => Value::Null,
+        }
+    }
+}
+
+fn main() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = Server::default();
+    while let Some(message) = read(&mut input) {
+        let method = match message["method"].as_str() {
+            Some(method) => method.to_string(),
+            // Answers to the requests of the server.
+            None => continue,
+        };
+        match message.get("id") {
+            Some(id) => {
+                let result = server.request(&method, &message["params"]);
+                send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+            }
+            None => server.notification(&method, &message["params"]),
+        }
+    }
+}
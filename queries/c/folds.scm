@@ -0,0 +1,9 @@
+[
+  (function_definition)
+  (compound_statement)
+  (struct_specifier)
+  (enum_specifier)
+  (preproc_if)
+  (preproc_ifdef)
+  (comment)
+] @fold
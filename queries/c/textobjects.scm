@@ -0,0 +1,9 @@
+(function_definition) @function.outer
+
+[
+  (struct_specifier body: (_))
+  (union_specifier body: (_))
+  (enum_specifier body: (_))
+] @class.outer
+
+(comment) @comment.outer
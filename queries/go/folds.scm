@@ -0,0 +1,8 @@
+[
+  (function_declaration)
+  (method_declaration)
+  (type_declaration)
+  (block)
+  (literal_value)
+  (comment)
+] @fold
@@ -0,0 +1,9 @@
+[
+  (function_declaration)
+  (method_declaration)
+  (func_literal)
+] @function.outer
+
+(type_declaration) @class.outer
+
+(comment) @comment.outer
@@ -0,0 +1,8 @@
+[
+  (class_body)
+  (statement_block)
+  (switch_statement)
+  (object)
+  (array)
+  (comment)
+] @fold
@@ -0,0 +1,26 @@
+[
+  (function_declaration
+    name: (identifier) @name)
+  (generator_function_declaration
+    name: (identifier) @name)
+] @definition.function
+
+(lexical_declaration
+  (variable_declarator
+    name: (identifier) @name
+    value: [(arrow_function) (function)]) @definition.function)
+
+(variable_declaration
+  (variable_declarator
+    name: (identifier) @name
+    value: [(arrow_function) (function)]) @definition.function)
+
+[
+  (class
+    name: (_) @name)
+  (class_declaration
+    name: (_) @name)
+] @definition.class
+
+(method_definition
+  name: (property_identifier) @name) @definition.method
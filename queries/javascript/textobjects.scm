@@ -0,0 +1,14 @@
+[
+  (function_declaration)
+  (generator_function_declaration)
+  (function)
+  (arrow_function)
+  (method_definition)
+] @function.outer
+
+[
+  (class_declaration)
+  (class)
+] @class.outer
+
+(comment) @comment.outer
@@ -0,0 +1,6 @@
+[
+  (function_definition)
+  (class_definition)
+  (dictionary)
+  (list)
+] @fold
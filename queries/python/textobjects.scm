@@ -0,0 +1,5 @@
+(function_definition) @function.outer
+
+(class_definition) @class.outer
+
+(comment) @comment.outer
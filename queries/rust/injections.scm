@@ -0,0 +1,21 @@
+((macro_invocation
+  (token_tree) @injection.content)
+ (#set! injection.language "rust")
+ (#set! injection.include-children))
+
+((macro_rule
+  (token_tree) @injection.content)
+ (#set! injection.language "rust")
+ (#set! injection.include-children))
+
+; Doc comments are Markdown once `///` or `//!` is stripped.
+((line_comment) @injection.content
+ (#match? @injection.content "^//[/!]")
+ (#offset! @injection.content 0 3 0 1)
+ (#set! injection.language "markdown")
+ (#set! injection.combined))
+
+; SQL in string literals, highlighted when a `sql` grammar is installed.
+((string_literal) @injection.content
+ (#match? @injection.content "^\"\\s*(SELECT|INSERT|UPDATE|DELETE|CREATE|ALTER|DROP|WITH)\\s")
+ (#set! injection.language "sql"))
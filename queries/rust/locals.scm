@@ -0,0 +1,53 @@
+; Scopes
+
+[
+  (function_item)
+  (closure_expression)
+  (block)
+  (for_expression)
+  (if_let_expression)
+  (while_let_expression)
+  (match_arm)
+] @local.scope
+
+; Definitions
+
+(parameter
+  pattern: (identifier) @local.definition)
+
+(parameter
+  pattern: (mut_pattern (identifier) @local.definition))
+
+(closure_parameters
+  (identifier) @local.definition)
+
+(let_declaration
+  pattern: (identifier) @local.definition)
+
+(for_expression
+  pattern: (identifier) @local.definition)
+
+(tuple_struct_pattern
+  type: (_)
+  (identifier) @local.definition)
+
+(field_pattern
+  name: (shorthand_field_identifier) @local.definition)
+
+(field_pattern
+  pattern: (identifier) @local.definition)
+
+([
+  (tuple_pattern (identifier) @local.definition)
+  (slice_pattern (identifier) @local.definition)
+  (mut_pattern (identifier) @local.definition)
+  (ref_pattern (identifier) @local.definition)
+  (captured_pattern (identifier) @local.definition)
+  (or_pattern (identifier) @local.definition)
+  (match_pattern (identifier) @local.definition)
+ ]
+ (#match? @local.definition "^[a-z_]"))
+
+; References
+
+(identifier) @local.reference
@@ -0,0 +1,23 @@
+(function_item
+  name: (identifier) @name) @definition.function
+
+(function_signature_item
+  name: (identifier) @name) @definition.function
+
+(struct_item
+  name: (type_identifier) @name) @definition.struct
+
+(enum_item
+  name: (type_identifier) @name) @definition.enum
+
+(union_item
+  name: (type_identifier) @name) @definition.union
+
+(trait_item
+  name: (type_identifier) @name) @definition.trait
+
+(impl_item
+  type: (_) @name) @definition.impl
+
+(mod_item
+  name: (identifier) @name) @definition.module
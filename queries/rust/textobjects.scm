@@ -0,0 +1,18 @@
+[
+  (function_item)
+  (function_signature_item)
+] @function.outer
+
+[
+  (struct_item)
+  (enum_item)
+  (union_item)
+  (trait_item)
+  (impl_item)
+  (mod_item)
+] @class.outer
+
+[
+  (line_comment)
+  (block_comment)
+] @comment.outer
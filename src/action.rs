@@ -1,16 +1,46 @@
+use crate::cmdline::CmdRange;
+use crate::keymap::MapMode;
 use crate::selection::Selection;
+use crate::snippet::SnippetKind;
 use crate::state::{SearchDirection, State};
 use flume::Sender;
 
+use crate::completion::CompletionKind;
 use crate::edit::EditKind;
+use crate::fold::FoldKind;
+use crate::lsp::LspKind;
 use crate::movement::MovementKind;
 
+impl From<CompletionKind> for ActionKind {
+    fn from(c: CompletionKind) -> Self {
+        Self::Completion(c)
+    }
+}
+
 impl From<EditKind> for ActionKind {
     fn from(e: EditKind) -> Self {
         Self::Edit(e)
     }
 }
 
+impl From<FoldKind> for ActionKind {
+    fn from(f: FoldKind) -> Self {
+        Self::Fold(f)
+    }
+}
+
+impl From<LspKind> for ActionKind {
+    fn from(l: LspKind) -> Self {
+        Self::Lsp(l)
+    }
+}
+
+impl From<SnippetKind> for ActionKind {
+    fn from(s: SnippetKind) -> Self {
+        Self::Snippet(s)
+    }
+}
+
 impl From<MovementKind> for ActionKind {
     fn from(m: MovementKind) -> Self {
         Self::Movement(m)
@@ -27,11 +57,23 @@ pub(super) struct Action {
 pub(super) enum ActionKind {
     Movement(MovementKind),
     Edit(EditKind),
+    Fold(FoldKind),
+    Lsp(LspKind),
+    Completion(CompletionKind),
+    Snippet(SnippetKind),
     IntoInsertMode(Option<MovementKind>),
     IntoNormalMode,
+    // <C-o> in insert mode.
+    IntoOneShotMode,
+    // Moving in insert mode, which starts a new insert.
+    InsertMovement(MovementKind),
     IntoCmdLineMode,
     IntoSearchMode(SearchDirection),
     IntoEditMode(Selection),
+    IntoVisualMode,
+    SelectNode,
+    ExpandSelection,
+    ShrinkSelection,
     SetYank(String),
     PushCmd(char),
     PushCmdStr(String),
@@ -41,13 +83,36 @@ pub(super) enum ActionKind {
     Repeat,
     Save,
     WriteOut(String),
+    WriteQuit,
+    Exit(i32),
+    Fail(String),
+    Substitute(CmdRange, String, String, bool),
+    NextBuffer,
+    PrevBuffer,
+    SelectBuffer(usize),
     Quit,
     GetState(Sender<State>),
+    Resize(u16, u16),
+    ColorScheme(Option<String>),
+    Map(Vec<MapMode>, String, String, bool),
+    Unmap(Vec<MapMode>, String),
+    ListMaps(Vec<MapMode>),
+    Set(Vec<String>, bool),
+    Format,
+    Grammars,
+    Rename(String),
     Undo,
     Redo,
     PushSearch(char),
     PopSearch,
     ClearSearch,
+    Symbols,
+    InspectTree,
+    Inspect,
+    PushPicker(char),
+    PopPicker,
+    SelectPickerItem(bool),
+    Pick,
 }
 
 impl ActionKind {
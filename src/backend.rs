@@ -0,0 +1,581 @@
+use std::cell::RefCell;
+use std::env;
+use std::fmt::Write as _;
+use std::io::{stdout, BufWriter, Write};
+use std::rc::Rc;
+
+use termion::color;
+use termion::raw::IntoRawMode;
+use termion::terminal_size;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub(super) enum Color {
+    #[default]
+    Reset,
+    Ansi(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(super) enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColorDepth::TrueColor;
+        }
+        if env::var("TERM").unwrap_or_default().contains("256") {
+            return ColorDepth::Ansi256;
+        }
+        ColorDepth::Ansi16
+    }
+}
+
+// xterm's default palette for the first 16 colors.
+const ANSI16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+fn ansi_to_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => ANSI16[n as usize],
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { v * 40 + 55 };
+            let n = n - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let v = (n - 232) * 10 + 8;
+            (v, v, v)
+        }
+    }
+}
+
+fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            249..=255 => 231,
+            // The nearest of 8, 18, ..., 238.
+            v => 232 + ((v - 3) / 10).min(23),
+        };
+    }
+    // The nearest of the levels 0, 95, 135, 175, 215 and 255 of the color cube.
+    let level = |v: u8| match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        v => (v - 35) / 40,
+    };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+fn rgb_to_ansi16(r: u8, g: u8, b: u8) -> u8 {
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, r2) + d(g, g2) + d(b, b2)
+    };
+    (0..16)
+        .min_by_key(|i| distance(ANSI16[*i as usize]))
+        .unwrap()
+}
+
+impl Color {
+    fn downgrade(&self, depth: ColorDepth) -> Color {
+        match (*self, depth) {
+            (Color::Rgb(r, g, b), ColorDepth::Ansi256) => Color::Ansi(rgb_to_ansi256(r, g, b)),
+            (Color::Rgb(r, g, b), ColorDepth::Ansi16) => Color::Ansi(rgb_to_ansi16(r, g, b)),
+            (Color::Ansi(n), ColorDepth::Ansi16) if n >= 16 => {
+                let (r, g, b) = ansi_to_rgb(n);
+                Color::Ansi(rgb_to_ansi16(r, g, b))
+            }
+            (c, _) => c,
+        }
+    }
+
+    fn write_fg(&self, s: &mut String, depth: ColorDepth) {
+        match self.downgrade(depth) {
+            Color::Reset => write!(s, "{}", color::Fg(color::Reset)),
+            Color::Ansi(n) if depth == ColorDepth::Ansi16 && n < 8 => write!(s, "\x1b[{}m", 30 + n),
+            Color::Ansi(n) if depth == ColorDepth::Ansi16 => write!(s, "\x1b[{}m", 82 + n),
+            Color::Ansi(n) => write!(s, "{}", color::Fg(color::AnsiValue(n))),
+            Color::Rgb(r, g, b) => write!(s, "{}", color::Fg(color::Rgb(r, g, b))),
+        }
+        .unwrap();
+    }
+
+    fn write_bg(&self, s: &mut String, depth: ColorDepth) {
+        match self.downgrade(depth) {
+            Color::Reset => write!(s, "{}", color::Bg(color::Reset)),
+            Color::Ansi(n) if depth == ColorDepth::Ansi16 && n < 8 => write!(s, "\x1b[{}m", 40 + n),
+            Color::Ansi(n) if depth == ColorDepth::Ansi16 => write!(s, "\x1b[{}m", 92 + n),
+            Color::Ansi(n) => write!(s, "{}", color::Bg(color::AnsiValue(n))),
+            Color::Rgb(r, g, b) => write!(s, "{}", color::Bg(color::Rgb(r, g, b))),
+        }
+        .unwrap();
+    }
+}
+
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub(super) struct Style {
+    pub(super) fg: Color,
+    pub(super) bg: Color,
+    pub(super) bold: bool,
+    pub(super) italic: bool,
+    pub(super) underline: bool,
+}
+
+impl Style {
+    pub(super) fn fg(fg: Color) -> Self {
+        Self {
+            fg,
+            ..Default::default()
+        }
+    }
+
+    // `other` drawn over `self`: its colors where set, and the modifiers of both.
+    pub(super) fn patch(self, other: Style) -> Self {
+        let color = |a, b| match b {
+            Color::Reset => a,
+            b => b,
+        };
+        Self {
+            fg: color(self.fg, other.fg),
+            bg: color(self.bg, other.bg),
+            bold: self.bold || other.bold,
+            italic: self.italic || other.italic,
+            underline: self.underline || other.underline,
+        }
+    }
+
+    fn write(&self, s: &mut String, prev: &Style, depth: ColorDepth) {
+        let modifiers = |style: &Style| (style.bold, style.italic, style.underline);
+        let reset = modifiers(self) != modifiers(prev);
+        if reset {
+            write!(s, "{}", termion::style::Reset).unwrap();
+            if self.bold {
+                write!(s, "{}", termion::style::Bold).unwrap();
+            }
+            if self.italic {
+                write!(s, "{}", termion::style::Italic).unwrap();
+            }
+            if self.underline {
+                write!(s, "{}", termion::style::Underline).unwrap();
+            }
+        }
+        if reset || self.fg != prev.fg {
+            self.fg.write_fg(s, depth);
+        }
+        if reset || self.bg != prev.bg {
+            self.bg.write_bg(s, depth);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Cell {
+    // Empty for the trailing cells of a wide character.
+    pub(super) symbol: String,
+    pub(super) style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: " ".to_string(),
+            style: Style::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(super) enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
+#[derive(Default, Clone, Debug, PartialEq)]
+pub(super) struct Grid {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+}
+
+impl Grid {
+    pub(super) fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width as usize * height as usize],
+        }
+    }
+
+    pub(super) fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    #[cfg(test)]
+    pub(super) fn get(&self, x: u16, y: u16) -> Option<&Cell> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.cells
+            .get(y as usize * self.width as usize + x as usize)
+    }
+
+    fn get_mut(&mut self, x: u16, y: u16) -> Option<&mut Cell> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.cells
+            .get_mut(y as usize * self.width as usize + x as usize)
+    }
+
+    pub(super) fn clear_region(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        for j in y..y.saturating_add(height) {
+            for i in x..x.saturating_add(width) {
+                if let Some(cell) = self.get_mut(i, j) {
+                    *cell = Cell::default();
+                }
+            }
+        }
+    }
+
+    // Cells of `self` that differ from `prev`, which must have the same size.
+    pub(super) fn diff<'a>(&'a self, prev: &Grid) -> Vec<(u16, u16, &'a Cell)> {
+        let mut result = Vec::new();
+        for (i, (cell, prev)) in self.cells.iter().zip(&prev.cells).enumerate() {
+            if cell != prev {
+                let x = (i % self.width as usize) as u16;
+                let y = (i / self.width as usize) as u16;
+                result.push((x, y, cell));
+            }
+        }
+        result
+    }
+
+    // Writes `s` from (x, y) until the end of the line or a newline, and returns the column
+    // after the last written cell.
+    pub(super) fn set_str(&mut self, x: u16, y: u16, s: &str, style: Style) -> u16 {
+        let mut x = x;
+        for c in s.chars() {
+            if c == '\n' {
+                break;
+            }
+            let width = c.width().unwrap_or(0) as u16;
+            if width == 0 {
+                continue;
+            }
+            if x + width > self.width {
+                break;
+            }
+            if let Some(cell) = self.get_mut(x, y) {
+                cell.symbol = c.to_string();
+                cell.style = style;
+            }
+            for i in 1..width {
+                if let Some(cell) = self.get_mut(x + i, y) {
+                    cell.symbol = String::new();
+                    cell.style = style;
+                }
+            }
+            x += width;
+        }
+        x
+    }
+
+    pub(super) fn set_style(&mut self, x: u16, y: u16, width: u16, style: Style) {
+        for i in x..x + width {
+            if let Some(cell) = self.get_mut(i, y) {
+                cell.style = style;
+            }
+        }
+    }
+
+    // Adds `style` to the cells, keeping what they have.
+    pub(super) fn patch_style(&mut self, x: u16, y: u16, width: u16, style: Style) {
+        for i in x..x + width {
+            if let Some(cell) = self.get_mut(i, y) {
+                cell.style = cell.style.patch(style);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn line(&self, y: u16) -> String {
+        (0..self.width)
+            .filter_map(|x| self.get(x, y))
+            .map(|cell| cell.symbol.as_str())
+            .collect()
+    }
+}
+
+pub(super) trait Backend {
+    fn size(&self) -> (u16, u16);
+    // Drops whatever is on screen, e.g. after the terminal has been resized.
+    fn resize(&mut self, width: u16, height: u16);
+    fn draw(&mut self, cells: Vec<(u16, u16, &Cell)>);
+    fn set_cursor(&mut self, x: u16, y: u16, shape: CursorShape);
+    fn flush(&mut self);
+}
+
+pub(super) struct TermionBackend {
+    stdout: BufWriter<Box<dyn Write>>,
+    depth: ColorDepth,
+}
+
+impl TermionBackend {
+    pub(super) fn new() -> Self {
+        let raw: Box<dyn Write> = Box::new(stdout().into_raw_mode().unwrap());
+        let mut stdout = BufWriter::new(raw);
+        write!(stdout, "{}", termion::screen::ToAlternateScreen).unwrap();
+        write!(stdout, "{}", termion::clear::All).unwrap();
+        stdout.flush().unwrap();
+        Self {
+            stdout,
+            depth: ColorDepth::detect(),
+        }
+    }
+}
+
+impl Backend for TermionBackend {
+    fn size(&self) -> (u16, u16) {
+        terminal_size().unwrap_or((80, 24))
+    }
+
+    fn resize(&mut self, _width: u16, _height: u16) {
+        write!(self.stdout, "{}", termion::clear::All).unwrap();
+    }
+
+    fn draw(&mut self, cells: Vec<(u16, u16, &Cell)>) {
+        if cells.is_empty() {
+            return;
+        }
+        let mut s = String::new();
+        let mut style = Style::default();
+        let mut next = None;
+        for (x, y, cell) in cells {
+            if cell.symbol.is_empty() {
+                continue;
+            }
+            if next != Some((x, y)) {
+                write!(s, "{}", termion::cursor::Goto(x + 1, y + 1)).unwrap();
+            }
+            cell.style.write(&mut s, &style, self.depth);
+            style = cell.style;
+            s.push_str(&cell.symbol);
+            next = Some((x + cell.symbol.width() as u16, y));
+        }
+        write!(s, "{}", termion::style::Reset).unwrap();
+        write!(self.stdout, "{}", s).unwrap();
+    }
+
+    fn set_cursor(&mut self, x: u16, y: u16, shape: CursorShape) {
+        match shape {
+            CursorShape::Block => write!(self.stdout, "{}", termion::cursor::SteadyBlock),
+            CursorShape::Underline => write!(self.stdout, "{}", termion::cursor::SteadyUnderline),
+            CursorShape::Bar => write!(self.stdout, "{}", termion::cursor::SteadyBar),
+        }
+        .unwrap();
+        write!(self.stdout, "{}", termion::cursor::Goto(x + 1, y + 1)).unwrap();
+    }
+
+    fn flush(&mut self) {
+        self.stdout.flush().unwrap();
+    }
+}
+
+impl Drop for TermionBackend {
+    fn drop(&mut self) {
+        write!(
+            self.stdout,
+            "{}{}",
+            termion::clear::All,
+            termion::screen::ToMainScreen
+        )
+        .unwrap();
+        self.stdout.flush().unwrap();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Screen {
+    pub(super) grid: Grid,
+    pub(super) cursor: (u16, u16),
+    pub(super) cursor_shape: CursorShape,
+    // How many cells have been written, to tell how much each frame redraws.
+    pub(super) drawn: usize,
+}
+
+// Keeps the last drawn frame in memory. Clones share the same screen, so a clone can be
+// inspected after the original has been handed to a `Renderer`.
+#[derive(Clone)]
+pub(super) struct MemoryBackend {
+    size: (u16, u16),
+    screen: Rc<RefCell<Screen>>,
+}
+
+impl MemoryBackend {
+    pub(super) fn new(width: u16, height: u16) -> Self {
+        Self {
+            size: (width, height),
+            screen: Rc::new(RefCell::new(Screen {
+                grid: Grid::new(width, height),
+                cursor: (0, 0),
+                cursor_shape: CursorShape::Block,
+                drawn: 0,
+            })),
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn screen(&self) -> Screen {
+        self.screen.borrow().clone()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    fn resize(&mut self, width: u16, height: u16) {
+        self.size = (width, height);
+        self.screen.borrow_mut().grid = Grid::new(width, height);
+    }
+
+    fn draw(&mut self, cells: Vec<(u16, u16, &Cell)>) {
+        let mut screen = self.screen.borrow_mut();
+        screen.drawn += cells.len();
+        for (x, y, cell) in cells {
+            if let Some(c) = screen.grid.get_mut(x, y) {
+                *c = cell.clone();
+            }
+        }
+    }
+
+    fn set_cursor(&mut self, x: u16, y: u16, shape: CursorShape) {
+        let mut screen = self.screen.borrow_mut();
+        screen.cursor = (x, y);
+        screen.cursor_shape = shape;
+    }
+
+    fn flush(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downgrades_true_color_to_256_and_16_colors() {
+        use ColorDepth::*;
+        let orange = Color::Rgb(255, 135, 0);
+        assert_eq!(orange.downgrade(TrueColor), orange);
+        assert_eq!(orange.downgrade(Ansi256), Color::Ansi(208));
+        assert_eq!(orange.downgrade(Ansi16), Color::Ansi(3));
+        // Grays go to the gray ramp, its ends to black and white.
+        assert_eq!(
+            Color::Rgb(128, 128, 128).downgrade(Ansi256),
+            Color::Ansi(244)
+        );
+        assert_eq!(Color::Rgb(3, 3, 3).downgrade(Ansi256), Color::Ansi(16));
+        assert_eq!(
+            Color::Rgb(255, 255, 255).downgrade(Ansi256),
+            Color::Ansi(231)
+        );
+        assert_eq!(Color::Rgb(0, 0, 238).downgrade(Ansi16), Color::Ansi(4));
+    }
+
+    #[test]
+    fn downgrades_256_colors_to_16() {
+        use ColorDepth::*;
+        assert_eq!(Color::Ansi(196).downgrade(Ansi16), Color::Ansi(9));
+        assert_eq!(Color::Ansi(232).downgrade(Ansi16), Color::Ansi(0));
+        assert_eq!(Color::Ansi(255).downgrade(Ansi16), Color::Ansi(7));
+        assert_eq!(Color::Ansi(196).downgrade(Ansi256), Color::Ansi(196));
+        for n in 0..16 {
+            assert_eq!(Color::Ansi(n).downgrade(Ansi16), Color::Ansi(n));
+        }
+        assert_eq!(Color::Reset.downgrade(Ansi16), Color::Reset);
+    }
+
+    #[test]
+    fn writes_16_colors_as_sgr_codes() {
+        let mut s = String::new();
+        Color::Rgb(255, 0, 0).write_fg(&mut s, ColorDepth::Ansi16);
+        Color::Ansi(1).write_bg(&mut s, ColorDepth::Ansi16);
+        assert_eq!(s, "\x1b[91m\x1b[41m");
+    }
+
+    #[test]
+    fn wide_chars_take_two_cells() {
+        let mut grid = Grid::new(6, 1);
+        let x = grid.set_str(0, 0, "a日b", Style::default());
+        assert_eq!(x, 4);
+        assert_eq!(grid.get(1, 0).unwrap().symbol, "日");
+        assert_eq!(grid.get(2, 0).unwrap().symbol, "");
+        assert_eq!(grid.line(0), "a日b  ");
+    }
+
+    #[test]
+    fn set_str_stops_at_the_edge_and_at_newlines() {
+        let mut grid = Grid::new(4, 2);
+        assert_eq!(grid.set_str(2, 0, "日本", Style::default()), 4);
+        assert_eq!(grid.line(0), "  日");
+        assert_eq!(grid.set_str(0, 1, "ab\ncd", Style::default()), 2);
+        assert_eq!(grid.line(1), "ab  ");
+    }
+
+    #[test]
+    fn diff_lists_the_changed_cells() {
+        let prev = Grid::new(3, 2);
+        let mut grid = prev.clone();
+        assert!(grid.diff(&prev).is_empty());
+
+        grid.set_str(1, 1, "x", Style::default());
+        grid.set_style(0, 0, 1, Style::fg(Color::Ansi(1)));
+        let cells: Vec<_> = grid
+            .diff(&prev)
+            .into_iter()
+            .map(|(x, y, cell)| (x, y, cell.symbol.clone()))
+            .collect();
+        assert_eq!(cells, [(0, 0, " ".to_string()), (1, 1, "x".to_string())]);
+    }
+
+    #[test]
+    fn patch_style_keeps_what_is_not_set() {
+        let mut grid = Grid::new(2, 1);
+        grid.set_style(0, 0, 2, Style::fg(Color::Ansi(2)));
+        let underline = Style {
+            underline: true,
+            ..Default::default()
+        };
+        grid.patch_style(1, 0, 1, underline);
+        let style = grid.get(1, 0).unwrap().style;
+        assert_eq!(style.fg, Color::Ansi(2));
+        assert!(style.underline);
+        assert!(!grid.get(0, 0).unwrap().style.underline);
+    }
+}
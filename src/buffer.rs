@@ -1,8 +1,10 @@
 use crate::position::Position;
+use core::cmp::min;
 use core::ops::{Range, RangeBounds};
 use std::borrow::Cow;
 
 use ropey::{iter::Chunks, Rope, RopeSlice};
+use tree_sitter::Point;
 
 #[derive(PartialEq, Clone, Debug)]
 enum CharKind {
@@ -45,14 +47,10 @@ impl Buffer {
         (end - start).saturating_sub(1)
     }
 
-    pub(super) fn line(&self, i: usize) -> BufferSlice {
+    pub(super) fn line(&self, i: usize) -> BufferSlice<'_> {
         self.0.line(i).into()
     }
 
-    pub(super) fn lines_at(&self, i: usize) -> impl Iterator<Item = BufferSlice> {
-        self.0.lines_at(i).map(BufferSlice::from)
-    }
-
     pub(super) fn count_lines(&self) -> usize {
         self.0.len_lines().saturating_sub(1)
     }
@@ -67,7 +65,7 @@ impl Buffer {
         let mut chars = self.0.chars_at(start);
 
         let c = chars.next();
-        if c == None {
+        if c.is_none() {
             return 0;
         }
         let c = c.unwrap();
@@ -181,11 +179,11 @@ impl Buffer {
         seq
     }
 
-    pub(super) fn slice<I: RangeBounds<usize> + Clone>(&self, range: I) -> BufferSlice {
+    pub(super) fn slice<I: RangeBounds<usize> + Clone>(&self, range: I) -> BufferSlice<'_> {
         self.0.slice(range).into()
     }
 
-    pub(super) fn as_str(&self) -> Cow<str> {
+    pub(super) fn as_str(&self) -> Cow<'_, str> {
         (&self.0).into()
     }
 
@@ -198,20 +196,32 @@ impl Buffer {
         }
     }
 
-    pub(super) fn get_cursor_by_byte(&self, i: usize) -> Position {
+    // Tree-sitter points are rows and byte columns.
+    pub(super) fn get_point_by_byte(&self, i: usize) -> Point {
         let row = self.0.byte_to_line(i);
         let row_byte = self.0.line_to_byte(row);
-        Position {
-            row,
-            col: i - row_byte,
-        }
+        Point::new(row, i - row_byte)
+    }
+
+    pub(super) fn get_byte_by_point(&self, p: Point) -> usize {
+        let byte = self.0.line_to_byte(min(p.row, self.0.len_lines())) + p.column;
+        min(byte, self.0.len_bytes())
+    }
+
+    pub(super) fn get_byte_by_position(&self, pos: Position) -> usize {
+        let offset = min(self.get_offset_by_position(pos), self.0.len_chars());
+        self.0.char_to_byte(offset)
+    }
+
+    pub(super) fn get_position_by_byte(&self, i: usize) -> Position {
+        self.get_position_by_offset(self.0.byte_to_char(i))
     }
 
     pub(super) fn get_chunk_at_byte(&self, i: usize) -> Option<(&str, usize, usize, usize)> {
         self.0.get_chunk_at_byte(i)
     }
 
-    pub(super) fn get_chunks_at_byte(&self, i: usize) -> Option<(Chunks, usize, usize, usize)> {
+    pub(super) fn get_chunks_at_byte(&self, i: usize) -> Option<(Chunks<'_>, usize, usize, usize)> {
         self.0.get_chunks_at_byte(i)
     }
 
@@ -252,7 +262,7 @@ impl<'a> BufferSlice<'a> {
         self.0.bytes()
     }
 
-    pub(super) fn as_str(&self) -> Cow<str> {
+    pub(super) fn as_str(&self) -> Cow<'_, str> {
         (self.0).into()
     }
 
@@ -10,6 +10,8 @@ use nom::{
 
 use crate::action::{Action, ActionKind};
 use crate::edit::EditKind;
+use crate::fold::FoldKind;
+use crate::lsp::LspKind;
 use crate::movement::MovementKind;
 use crate::selection::{Selection, SelectionKind};
 use crate::state::SearchDirection;
@@ -57,6 +59,7 @@ fn movement_kind(input: &str) -> IResult<&str, MovementKind> {
         map(tag("$"), |_| MovementKind::LineTail),
         map(tag("0"), |_| MovementKind::LineHead),
         map(tag("gg"), |_| MovementKind::Line),
+        map(tag("gd"), |_| MovementKind::Definition),
         map(tag("G"), |_| MovementKind::Tail),
         map(alt((tag("h"), tag("<Left>"))), |_| MovementKind::Left),
         map(alt((tag("j"), tag("<Down>"))), |_| MovementKind::Down),
@@ -66,15 +69,69 @@ fn movement_kind(input: &str) -> IResult<&str, MovementKind> {
         map(tag("b"), |_| MovementKind::BackWord),
         map(tag("n"), |_| MovementKind::NextMatch),
         map(tag("N"), |_| MovementKind::PrevMatch),
+        map(tag("<C-o>"), |_| MovementKind::JumpBack),
+        map(alt((tag("<C-i>"), tag("\t"))), |_| {
+            MovementKind::JumpForward
+        }),
+        syntax_movement,
     ))(input)
 }
 
-fn action_kind(input: &str) -> IResult<&str, ActionKind> {
+fn syntax_movement(input: &str) -> IResult<&str, MovementKind> {
+    alt((
+        map(tag("]n"), |_| MovementKind::NextSibling),
+        map(tag("[n"), |_| MovementKind::PrevSibling),
+        map(tag("]m"), |_| MovementKind::NextFunctionStart),
+        map(tag("[m"), |_| MovementKind::PrevFunctionStart),
+        map(tag("]M"), |_| MovementKind::NextFunctionEnd),
+        map(tag("[M"), |_| MovementKind::PrevFunctionEnd),
+        map(tag("]]"), |_| MovementKind::NextItem),
+        map(tag("[["), |_| MovementKind::PrevItem),
+        map(tag("]c"), |_| MovementKind::NextComment),
+        map(tag("[c"), |_| MovementKind::PrevComment),
+        map(tag("]d"), |_| MovementKind::NextDiagnostic),
+        map(tag("[d"), |_| MovementKind::PrevDiagnostic),
+    ))(input)
+}
+
+fn visual(input: &str) -> IResult<&str, ActionKind> {
+    alt((
+        map(tag("v"), |_| ActionKind::IntoVisualMode),
+        map(tag("gnn"), |_| ActionKind::SelectNode),
+    ))(input)
+}
+
+fn fold(input: &str) -> IResult<&str, ActionKind> {
+    alt((
+        map(pair(tag("zf"), selection), |(_, s)| {
+            FoldKind::Create(s).into()
+        }),
+        map(tag("zo"), |_| FoldKind::Open.into()),
+        map(tag("zc"), |_| FoldKind::Close.into()),
+        map(tag("za"), |_| FoldKind::Toggle.into()),
+        map(tag("zR"), |_| FoldKind::OpenAll.into()),
+        map(tag("zM"), |_| FoldKind::CloseAll.into()),
+    ))(input)
+}
+
+fn swap(input: &str) -> IResult<&str, ActionKind> {
+    alt((
+        map(tag(">n"), |_| EditKind::SwapNextSibling.into()),
+        map(tag("<n"), |_| EditKind::SwapPrevSibling.into()),
+    ))(input)
+}
+
+// `gd` goes through the language server too when there is one.
+fn lsp(input: &str) -> IResult<&str, ActionKind> {
+    alt((
+        map(tag("K"), |_| LspKind::Hover.into()),
+        map(tag("grr"), |_| LspKind::References.into()),
+        map(tag("gra"), |_| LspKind::CodeActions.into()),
+    ))(input)
+}
+
+fn insert(input: &str) -> IResult<&str, ActionKind> {
     alt((
-        map(movement_kind, |k| k.into()),
-        map(tag("x"), |_| EditKind::RemoveChar.into()),
-        map(tag("u"), |_| ActionKind::Undo),
-        map(tag("<C-r>"), |_| ActionKind::Redo),
         map(tag("i"), |_| ActionKind::IntoInsertMode(None)),
         map(tag("a"), |_| {
             ActionKind::IntoInsertMode(Some(MovementKind::Right))
@@ -85,6 +142,20 @@ fn action_kind(input: &str) -> IResult<&str, ActionKind> {
         map(tag("I"), |_| {
             ActionKind::IntoInsertMode(Some(MovementKind::IndentHead))
         }),
+    ))(input)
+}
+
+fn action_kind(input: &str) -> IResult<&str, ActionKind> {
+    alt((
+        map(movement_kind, |k| k.into()),
+        map(tag("x"), |_| EditKind::RemoveChar.into()),
+        visual,
+        fold,
+        swap,
+        lsp,
+        map(tag("u"), |_| ActionKind::Undo),
+        map(tag("<C-r>"), |_| ActionKind::Redo),
+        insert,
         map(tag(":"), |_| ActionKind::IntoCmdLineMode),
         map(tag("/"), |_| {
             ActionKind::IntoSearchMode(SearchDirection::Forward)
@@ -119,6 +190,49 @@ pub(super) fn parse(input: &str) -> IResult<&str, Action> {
     cmd(input)
 }
 
+// These act on the selection once and end visual mode.
+fn visual_operator(input: &str) -> IResult<&str, ActionKind> {
+    use SelectionKind::Visual;
+    alt((
+        map(alt((tag("d"), tag("x"))), |_| {
+            EditKind::RemoveSelection(Visual.once()).into()
+        }),
+        map(tag("y"), |_| ActionKind::Yank(Visual.once())),
+        map(tag("c"), |_| ActionKind::IntoEditMode(Visual.once())),
+        map(tag(":"), |_| ActionKind::IntoCmdLineMode),
+        map(tag("zf"), |_| FoldKind::Create(Visual.once()).into()),
+        map(tag("gra"), |_| LspKind::CodeActions.into()),
+    ))(input)
+}
+
+fn visual_action_kind(input: &str) -> IResult<&str, ActionKind> {
+    alt((
+        map(movement_kind, |k| k.into()),
+        map(tag("grn"), |_| ActionKind::ExpandSelection),
+        map(tag("grm"), |_| ActionKind::ShrinkSelection),
+        swap,
+    ))(input)
+}
+
+fn visual_cmd(input: &str) -> IResult<&str, Action> {
+    alt((
+        map(visual_operator, ActionKind::once),
+        map(visual_action_kind, |kind| Action { count: 1, kind }),
+        map(pair(digit1, visual_action_kind), |(n, kind)| {
+            let count = n.parse().unwrap_or(1);
+            Action { count, kind }
+        }),
+        map(
+            many_till(anychar, alt((tag("v"), tag("<C-c>"), tag("<Esc>")))),
+            |_| ActionKind::IntoNormalMode.once(),
+        ),
+    ))(input)
+}
+
+pub(super) fn parse_visual(input: &str) -> IResult<&str, Action> {
+    visual_cmd(input)
+}
+
 fn selection_kind(input: &str) -> IResult<&str, SelectionKind> {
     use SelectionKind::*;
     alt((
@@ -129,6 +243,7 @@ fn selection_kind(input: &str) -> IResult<&str, SelectionKind> {
         map(tag("w"), |_| ForwardWord),
         map(tag("b"), |_| BackWord),
         map(tag("iw"), |_| Word),
+        map(syntax_movement, Motion),
     ))(input)
 }
 
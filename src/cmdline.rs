@@ -1,26 +1,189 @@
 use crate::action::{Action, ActionKind};
+use crate::keymap::MapMode;
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{anychar, space1},
-    combinator::map,
-    multi::many1,
-    sequence::separated_pair,
+    bytes::complete::{is_not, tag},
+    character::complete::{alpha1, anychar, digit1, space0, space1},
+    combinator::{all_consuming, map, map_opt, opt, rest},
+    multi::{many0, many1},
+    sequence::{pair, preceded, separated_pair, tuple},
     IResult,
 };
 
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum CmdRange {
+    Current,
+    All,
+    Lines(usize, usize),
+}
+
+fn range(input: &str) -> IResult<&str, CmdRange> {
+    alt((
+        map(tag("%"), |_| CmdRange::All),
+        map(
+            separated_pair(digit1, tag(","), digit1),
+            |(start, end): (&str, &str)| {
+                CmdRange::Lines(start.parse().unwrap_or(1), end.parse().unwrap_or(1))
+            },
+        ),
+        map(digit1, |n: &str| {
+            let n = n.parse().unwrap_or(1);
+            CmdRange::Lines(n, n)
+        }),
+        map(tag(""), |_| CmdRange::Current),
+    ))(input)
+}
+
+// Reads up to the next unescaped `/`, unescaping `\/`.
+fn delimited_part(input: &str) -> IResult<&str, String> {
+    let mut result = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '/' => return Ok((&input[i + 1..], result)),
+            '\\' => match chars.next() {
+                Some((_, '/')) => result.push('/'),
+                Some((_, c)) => {
+                    result.push('\\');
+                    result.push(c);
+                }
+                None => result.push('\\'),
+            },
+            c => result.push(c),
+        }
+    }
+    Ok(("", result))
+}
+
+// Translates vi style `&` and `\1` into the `regex` crate syntax.
+fn replacement(s: String) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => result.push_str("${0}"),
+            '$' => result.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_digit() => {
+                    result.push_str(&format!("${{{}}}", c));
+                }
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(c) => result.push(c),
+                None => result.push('\\'),
+            },
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn substitute(input: &str) -> IResult<&str, ActionKind> {
+    map(
+        tuple((
+            range,
+            tag("s/"),
+            delimited_part,
+            delimited_part,
+            opt(tag("g")),
+        )),
+        |(range, _, pattern, rep, global)| {
+            ActionKind::Substitute(range, pattern, replacement(rep), global.is_some())
+        },
+    )(input)
+}
+
+// `[nivc](nore)map`, `[nivc]unmap`, where no prefix means normal and visual.
+fn map_command(name: &str) -> Option<(Vec<MapMode>, &str)> {
+    let is_map = |s: &str| ["map", "noremap", "unmap"].contains(&s);
+    if is_map(name) {
+        return Some((vec![MapMode::Normal, MapMode::Visual], name));
+    }
+    let mode = match name.chars().next()? {
+        'n' => MapMode::Normal,
+        'i' => MapMode::Insert,
+        'v' => MapMode::Visual,
+        'c' => MapMode::CmdLine,
+        _ => return None,
+    };
+    Some((vec![mode], &name[1..])).filter(|(_, s)| is_map(s))
+}
+
+fn keymap(input: &str) -> IResult<&str, ActionKind> {
+    map_opt(
+        tuple((
+            alpha1,
+            opt(preceded(space1, is_not(" "))),
+            opt(preceded(space1, rest)),
+        )),
+        |(name, lhs, rhs): (&str, Option<&str>, Option<&str>)| {
+            let (modes, kind) = map_command(name)?;
+            let kind = match (kind, lhs, rhs) {
+                ("unmap", Some(lhs), None) => ActionKind::Unmap(modes, lhs.to_string()),
+                ("unmap", _, _) => return None,
+                (_, None, _) => ActionKind::ListMaps(modes),
+                (_, Some(_), None) => return None,
+                (kind, Some(lhs), Some(rhs)) => {
+                    ActionKind::Map(modes, lhs.to_string(), rhs.to_string(), kind == "map")
+                }
+            };
+            Some(kind)
+        },
+    )(input)
+}
+
+fn set(input: &str) -> IResult<&str, ActionKind> {
+    map(
+        pair(
+            alt((tag("setlocal"), tag("setl"), tag("set"), tag("se"))),
+            many0(preceded(space1, is_not(" "))),
+        ),
+        |(name, args): (&str, Vec<&str>)| {
+            let args = args.into_iter().map(String::from).collect();
+            ActionKind::Set(args, name.starts_with("setl"))
+        },
+    )(input)
+}
+
 fn cmdline(input: &str) -> IResult<&str, Action> {
     use ActionKind::*;
     alt((
-        map(tag("w"), |_| Save.once()),
         map(
             separated_pair(tag("w"), space1, many1(anychar)),
             |(_, arg)| WriteOut(arg.iter().collect()).once(),
         ),
-        map(tag("q"), |_| Quit.once()),
+        map(alt((tag("wq"), tag("x"))), |_| WriteQuit.once()),
+        map(tag("w"), |_| Save.once()),
+        map(tag("cq"), |_| Exit(1).once()),
+        map(alt((tag("q!"), tag("q"))), |_| Quit.once()),
+        map(tag("bn"), |_| NextBuffer.once()),
+        map(tag("bp"), |_| PrevBuffer.once()),
+        map(
+            separated_pair(tag("b"), space0, digit1),
+            |(_, n): (_, &str)| SelectBuffer(n.parse().unwrap_or(1)).once(),
+        ),
+        map(
+            preceded(
+                alt((tag("colorscheme"), tag("colo"))),
+                opt(preceded(space1, many1(anychar))),
+            ),
+            |name| ColorScheme(name.map(|s| s.iter().collect())).once(),
+        ),
+        map(tag("Format"), |_| Format.once()),
+        map(tag("grammars"), |_| Grammars.once()),
+        map(
+            preceded(pair(tag("Rename"), space1), is_not(" ")),
+            |name: &str| Rename(name.to_string()).once(),
+        ),
+        map(tag("symbols"), |_| Symbols.once()),
+        map(tag("InspectTree"), |_| InspectTree.once()),
+        map(tag("Inspect"), |_| Inspect.once()),
+        map(set, |kind| kind.once()),
+        map(keymap, |kind| kind.once()),
+        map(substitute, |kind| kind.once()),
     ))(input)
 }
 
 pub(super) fn parse(input: &str) -> IResult<&str, Action> {
-    cmdline(input)
+    all_consuming(cmdline)(input)
 }
@@ -0,0 +1,8 @@
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum CompletionKind {
+    // Open the popup first when it is closed.
+    Next,
+    Prev,
+    Accept,
+    Cancel,
+}
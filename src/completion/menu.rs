@@ -0,0 +1,210 @@
+use core::cmp::Reverse;
+use hashbrown::HashSet;
+
+// Where a candidate comes from, in the order they rank on a tie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Source {
+    Local,
+    Lsp,
+    Snippet,
+    Buffer,
+    Path,
+}
+
+impl Source {
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::Lsp => "lsp",
+            Self::Snippet => "snippet",
+            Self::Buffer => "buffer",
+            Self::Path => "path",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Candidate {
+    pub(crate) word: String,
+    pub(crate) source: Source,
+    // Shown after the word, like the kind of an LSP item.
+    pub(crate) detail: String,
+    // Expanded instead of inserting the word.
+    pub(crate) snippet: Option<String>,
+}
+
+// The insert-mode completion popup.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Completion {
+    // The char offset of the word being completed.
+    pub(crate) start: usize,
+    pub(crate) prefix: String,
+    pub(crate) candidates: Vec<Candidate>,
+    // Index into the matches. Nothing is selected in a popup that opened by itself.
+    pub(crate) selected: Option<usize>,
+}
+
+fn is_boundary(prev: char, c: char) -> bool {
+    !prev.is_alphanumeric() || (prev.is_lowercase() && c.is_uppercase())
+}
+
+// How well `word` matches the chars of `query` in order, ignoring case. Matches at the
+// start, at word boundaries and in runs count more, gaps count less.
+pub(crate) fn score(word: &str, query: &str) -> Option<i64> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last: Option<usize> = None;
+    for q in query.chars() {
+        let lower = q.to_lowercase().next().unwrap_or(q);
+        let i = (next..chars.len()).find(|&i| chars[i].to_lowercase().eq(q.to_lowercase()))?;
+        score += match i {
+            0 => 8,
+            i if is_boundary(chars[i - 1], chars[i]) => 6,
+            _ => 0,
+        };
+        score += match last {
+            Some(last) if last + 1 == i => 4,
+            Some(last) => -(min_gap(i - last - 1)),
+            None => 0,
+        };
+        if chars[i] == q && q != lower {
+            score += 1;
+        }
+        last = Some(i);
+        next = i + 1;
+    }
+    Some(score - (chars.len() - next) as i64 / 4)
+}
+
+fn min_gap(gap: usize) -> i64 {
+    gap.min(3) as i64
+}
+
+impl Completion {
+    // The candidates matching the prefix, best first, each word once.
+    pub(crate) fn matches(&self) -> Vec<&Candidate> {
+        let mut scored: Vec<_> = self
+            .candidates
+            .iter()
+            .filter(|c| c.word != self.prefix)
+            .filter_map(|c| Some((score(&c.word, &self.prefix)?, c)))
+            .collect();
+        scored.sort_by_key(|(score, c)| (Reverse(*score), c.source, c.word.len(), c.word.clone()));
+        let mut seen = HashSet::new();
+        scored
+            .into_iter()
+            .map(|(_, c)| c)
+            .filter(|c| seen.insert(c.word.as_str()))
+            .collect()
+    }
+
+    pub(crate) fn current(&self) -> Option<&Candidate> {
+        self.matches().get(self.selected?).copied()
+    }
+
+    // Moves the selection by `count` matches, wrapping around. Going back from nothing
+    // selected starts at the last one.
+    pub(crate) fn select(&mut self, forward: bool, count: usize) {
+        let len = self.matches().len();
+        if len == 0 {
+            return;
+        }
+        let count = count % len;
+        self.selected = Some(match (self.selected, forward) {
+            (None, true) => (count + len - 1) % len,
+            (None, false) => (len - count) % len,
+            (Some(selected), true) => (selected + count) % len,
+            (Some(selected), false) => (selected + len - count) % len,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completion(prefix: &str, candidates: &[(&str, Source)]) -> Completion {
+        let candidates = candidates
+            .iter()
+            .map(|(word, source)| Candidate {
+                word: word.to_string(),
+                source: *source,
+                detail: String::new(),
+                snippet: None,
+            })
+            .collect();
+        Completion {
+            prefix: prefix.to_string(),
+            candidates,
+            ..Default::default()
+        }
+    }
+
+    fn words(completion: &Completion) -> Vec<(&str, Source)> {
+        let matches = completion.matches();
+        matches
+            .iter()
+            .map(|c| (c.word.as_str(), c.source))
+            .collect()
+    }
+
+    #[test]
+    fn scores_starts_boundaries_and_runs() {
+        assert_eq!(score("foo_bar", "fb"), Some(11));
+        assert_eq!(score("fooBar", "fb"), Some(12));
+        assert_eq!(score("fizzbuzz", "fb"), Some(5));
+        assert_eq!(score("foobar", "foo"), Some(16));
+        assert_eq!(score("xyz", "fb"), None);
+        assert_eq!(score("bf", "fb"), None);
+        // An uppercase char in the query counts more where it matches exactly.
+        assert!(score("Foo", "F") > score("foo", "F"));
+        assert_eq!(score("FOO", "foo"), Some(16));
+    }
+
+    #[test]
+    fn filters_and_ranks_the_candidates() {
+        use Source::*;
+        let c = completion(
+            "fb",
+            &[
+                ("fizzbuzz", Buffer),
+                ("foo_bar", Buffer),
+                ("xyz", Local),
+                ("fb", Local),
+                ("foo_bar", Lsp),
+            ],
+        );
+        // The word typed already is left out, and each word shows once.
+        assert_eq!(words(&c), vec![("foo_bar", Lsp), ("fizzbuzz", Buffer)]);
+
+        // On a tie the source counts, then the length.
+        let c = completion("fb", &[("fab", Buffer), ("fob", Local), ("fb_", Buffer)]);
+        assert_eq!(score("fab", "fb"), score("fob", "fb"));
+        assert_eq!(words(&c)[1..], [("fob", Local), ("fab", Buffer)]);
+
+        let c = completion("", &[("b", Path), ("a", Path)]);
+        assert_eq!(words(&c), vec![("a", Path), ("b", Path)]);
+    }
+
+    #[test]
+    fn selects_around_the_matches() {
+        use Source::*;
+        let mut c = completion("", &[("a", Buffer), ("b", Buffer), ("c", Buffer)]);
+        assert_eq!(c.current(), None);
+        c.select(true, 1);
+        assert_eq!(c.current().unwrap().word, "a");
+        c.select(false, 2);
+        assert_eq!(c.current().unwrap().word, "b");
+        c.select(true, 4);
+        assert_eq!(c.current().unwrap().word, "c");
+
+        c.selected = None;
+        c.select(false, 1);
+        assert_eq!(c.current().unwrap().word, "c");
+
+        let mut c = completion("x", &[("a", Buffer)]);
+        c.select(true, 1);
+        assert_eq!(c.selected, None);
+    }
+}
@@ -0,0 +1,7 @@
+mod action;
+mod menu;
+mod store;
+
+pub(crate) use action::CompletionKind;
+pub(crate) use menu::{Candidate, Completion, Source};
+pub(super) use store::CompletionStore;
@@ -0,0 +1,249 @@
+use super::action::CompletionKind;
+use super::menu::{Candidate, Completion, Source};
+use crate::buffer::Buffer;
+use crate::store::{RootStore, Store};
+use dirs::home_dir;
+use hashbrown::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+// The popup opens by itself once a word is this long.
+const AUTO_LEN: usize = 3;
+
+pub(crate) struct CompletionStore<'a> {
+    root: &'a mut RootStore,
+}
+
+impl<'a> Store for CompletionStore<'a> {
+    fn root(&self) -> &RootStore {
+        self.root
+    }
+
+    fn root_mut(&mut self) -> &mut RootStore {
+        self.root
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Chars that end a file name being typed, like the quotes around it.
+fn is_path(c: char) -> bool {
+    !c.is_whitespace() && !"\"'`()[]{}<>,;=".contains(c)
+}
+
+// The words of at least two chars in `b`, each once.
+fn words(b: &Buffer, seen: &mut HashSet<String>) -> Vec<Candidate> {
+    let mut result = Vec::new();
+    let mut word = String::new();
+    for c in b.0.chars() {
+        if is_word(c) {
+            word.push(c);
+            continue;
+        }
+        if word.chars().nth(1).is_some() && seen.insert(word.clone()) {
+            result.push(Candidate {
+                word: word.clone(),
+                source: Source::Buffer,
+                detail: String::new(),
+                snippet: None,
+            });
+        }
+        word.clear();
+    }
+    result
+}
+
+// The entries of `dir` as typed, relative to the working directory or to the home one
+// with `~/`. Directories end with a `/` to go on typing into them.
+fn paths(dir: &str) -> Vec<Candidate> {
+    let path = match dir.strip_prefix("~/") {
+        Some(rest) => home_dir().unwrap_or_default().join(rest),
+        None => PathBuf::from(dir),
+    };
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .map(|entry| {
+            let mut word = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                word.push('/');
+            }
+            Candidate {
+                word,
+                source: Source::Path,
+                detail: String::new(),
+                snippet: None,
+            }
+        })
+        .collect()
+}
+
+impl<'a> CompletionStore<'a> {
+    pub(crate) fn new(root: &'a mut RootStore) -> Self {
+        Self { root }
+    }
+
+    // Where the word before the cursor starts, or the file name when a path is typed,
+    // along with the directory the file name is in.
+    fn context(&self) -> (usize, Option<String>) {
+        let state = self.state();
+        let cursor = state.get_cursor_offset();
+        let head = state.buffer.line_range(state.cursor.row).start;
+        let before: Vec<char> = state.buffer.slice(head..cursor).chars().collect();
+        let word = before.iter().rev().take_while(|c| is_word(**c)).count();
+        let token = before.iter().rev().take_while(|c| is_path(**c)).count();
+        let token: String = before[before.len() - token..].iter().collect();
+        match token.rfind('/') {
+            // Not a lone `/` or a `//` comment.
+            Some(i) if !token[..i].trim_end_matches('/').is_empty() => {
+                let name = token[i + 1..].chars().count();
+                (cursor - name, Some(token[..=i].to_string()))
+            }
+            _ => (cursor - word, None),
+        }
+    }
+
+    // Local identifiers defined before the cursor in the scopes around it.
+    fn locals(&mut self) -> Vec<Candidate> {
+        let buffer = self.state().buffer.clone();
+        let at = buffer.get_byte_by_position(self.state().cursor);
+        let symbols = self.highlighter_mut().symbols(&buffer, at);
+        symbols
+            .into_iter()
+            .filter(|symbol| symbol.definition.end < at)
+            .map(|symbol| Candidate {
+                word: symbol.name,
+                source: Source::Local,
+                detail: String::new(),
+                snippet: None,
+            })
+            .collect()
+    }
+
+    // The words of the current buffer first, then of the others.
+    fn buffer_words(&self) -> Vec<Candidate> {
+        let mut seen = HashSet::new();
+        let mut result = words(&self.state().buffer, &mut seen);
+        for buffer in self.root().other_buffers() {
+            result.extend(words(buffer, &mut seen));
+        }
+        result
+    }
+
+    // Opens the popup for the word before the cursor. After a trigger char of the
+    // language server only the server has something to say.
+    fn open(&mut self, trigger: Option<char>) {
+        let cursor = self.state().get_cursor_offset();
+        let (start, dir) = match trigger {
+            Some(_) => (cursor, None),
+            None => self.context(),
+        };
+        let candidates = match (&dir, trigger) {
+            (Some(dir), _) => paths(dir),
+            (None, Some(_)) => Vec::new(),
+            (None, None) => {
+                let mut candidates = self.locals();
+                candidates.extend(self.root_mut().snippet().candidates());
+                candidates.extend(self.buffer_words());
+                candidates
+            }
+        };
+        let prefix = self
+            .state()
+            .buffer
+            .slice(start..cursor)
+            .as_str()
+            .to_string();
+        self.state_mut().completion = Some(Completion {
+            start,
+            prefix,
+            candidates,
+            selected: None,
+        });
+        if dir.is_none() {
+            self.root_mut().lsp().complete(start, trigger);
+        }
+    }
+
+    // Narrows the popup down to what is typed, or opens it when a word gets long enough
+    // or a path goes into a directory.
+    pub(crate) fn typed(&mut self, c: char) {
+        let (start, dir) = self.context();
+        let cursor = self.state().get_cursor_offset();
+        let state = self.state_mut();
+        match &mut state.completion {
+            Some(completion) if completion.start == start => {
+                completion.prefix = state.buffer.slice(start..cursor).as_str().to_string();
+                completion.selected = None;
+                return;
+            }
+            _ => state.completion = None,
+        }
+        let len = cursor - start;
+        if (dir.is_some() && c == '/') || (dir.is_none() && len == AUTO_LEN) {
+            self.open(None);
+        } else if !is_word(c) && self.root_mut().lsp().is_trigger(c) {
+            self.open(Some(c));
+        }
+    }
+
+    // Adds the answer of the language server to the popup it was asked for, keeping the
+    // selected word selected.
+    pub(crate) fn extend(&mut self, start: usize, candidates: Vec<Candidate>) {
+        let completion = match &mut self.state_mut().completion {
+            Some(completion) if completion.start == start => completion,
+            _ => return,
+        };
+        let selected = completion.current().map(|c| c.word.clone());
+        completion.candidates.extend(candidates);
+        if let Some(word) = selected {
+            completion.selected = completion.matches().iter().position(|c| c.word == word);
+        }
+    }
+
+    // Takes the selected match, or the best one.
+    fn accept(&mut self) {
+        let completion = match self.state_mut().completion.take() {
+            Some(completion) => completion,
+            None => return,
+        };
+        let candidate = completion
+            .current()
+            .or_else(|| completion.matches().first().copied())
+            .cloned();
+        let cursor = self.state().get_cursor_offset();
+        let range = completion.start..cursor;
+        match candidate {
+            Some(Candidate {
+                snippet: Some(body),
+                ..
+            }) => self.root_mut().snippet().expand(range, &body),
+            Some(candidate) => self.root_mut().edit().complete(range, &candidate.word),
+            None => {}
+        }
+    }
+
+    pub(crate) fn action(&mut self, kind: CompletionKind, count: usize) {
+        if !self.state().mode.is_insert() {
+            return;
+        }
+        use CompletionKind::*;
+        match kind {
+            Next | Prev => {
+                if self.state().completion.is_none() {
+                    self.open(None);
+                }
+                if let Some(completion) = &mut self.state_mut().completion {
+                    completion.select(kind == Next, count);
+                }
+            }
+            Accept => self.accept(),
+            Cancel => self.state_mut().completion = None,
+        }
+    }
+}
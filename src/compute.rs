@@ -1,14 +1,21 @@
 use crate::buffer::Buffer;
+use crate::diagnostic::Diagnostic;
+use crate::fold;
+use crate::inspect::TreeView;
 use crate::mode::Mode;
+use crate::options::Options;
+use crate::picker::Picker;
 use crate::position::Position;
 use crate::search;
 use crate::search::Match;
 use crate::state::{SearchDirection, State};
-use core::cmp::{max, min};
+use crate::theme::Theme;
+use core::cmp::max;
 use hashbrown::HashMap;
-use regex::Regex;
+use regex::RegexBuilder;
 use std::any::{Any, TypeId};
 use std::ops::Range;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 #[derive(Clone, Debug)]
 struct Computed<C>
@@ -117,8 +124,10 @@ where
             return computed.value;
         }
 
+        // Still up to date, which holds for the rest of this generation.
         let source = reactor.compute::<C::Source>();
         if source == computed.source {
+            reactor.insert_computed(computed.value.clone(), source);
             return computed.value;
         }
 
@@ -166,6 +175,121 @@ where
     }
 }
 
+impl<T1, T2, T3, T4, T5> ComputeWithReactor for (T1, T2, T3, T4, T5)
+where
+    T1: Compute,
+    T2: Compute,
+    T3: Compute,
+    T4: Compute,
+    T5: Compute,
+{
+    fn compute_with_reactor(reactor: &mut Reactor) -> Self {
+        (
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+        )
+    }
+}
+
+impl<T1, T2, T3, T4, T5, T6> ComputeWithReactor for (T1, T2, T3, T4, T5, T6)
+where
+    T1: Compute,
+    T2: Compute,
+    T3: Compute,
+    T4: Compute,
+    T5: Compute,
+    T6: Compute,
+{
+    fn compute_with_reactor(reactor: &mut Reactor) -> Self {
+        (
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+        )
+    }
+}
+
+impl<T1, T2, T3, T4, T5, T6, T7> ComputeWithReactor for (T1, T2, T3, T4, T5, T6, T7)
+where
+    T1: Compute,
+    T2: Compute,
+    T3: Compute,
+    T4: Compute,
+    T5: Compute,
+    T6: Compute,
+    T7: Compute,
+{
+    fn compute_with_reactor(reactor: &mut Reactor) -> Self {
+        (
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+        )
+    }
+}
+
+impl<T1, T2, T3, T4, T5, T6, T7, T8> ComputeWithReactor for (T1, T2, T3, T4, T5, T6, T7, T8)
+where
+    T1: Compute,
+    T2: Compute,
+    T3: Compute,
+    T4: Compute,
+    T5: Compute,
+    T6: Compute,
+    T7: Compute,
+    T8: Compute,
+{
+    fn compute_with_reactor(reactor: &mut Reactor) -> Self {
+        (
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+        )
+    }
+}
+
+impl<T1, T2, T3, T4, T5, T6, T7, T8, T9> ComputeWithReactor for (T1, T2, T3, T4, T5, T6, T7, T8, T9)
+where
+    T1: Compute,
+    T2: Compute,
+    T3: Compute,
+    T4: Compute,
+    T5: Compute,
+    T6: Compute,
+    T7: Compute,
+    T8: Compute,
+    T9: Compute,
+{
+    fn compute_with_reactor(reactor: &mut Reactor) -> Self {
+        (
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+            reactor.compute(),
+        )
+    }
+}
+
 impl ComputeWithReactor for () {
     fn compute_with_reactor(_reactor: &mut Reactor) -> Self {}
 }
@@ -203,6 +327,123 @@ impl Compute for MaxLineDigit {
     }
 }
 
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct NumberWidth(pub(super) usize);
+
+impl Compute for NumberWidth {
+    type Source = (MaxLineDigit, Number);
+    fn compute(source: &Self::Source) -> Self {
+        match source.1 .0 {
+            true => Self(source.0 .0 + 1),
+            false => Self(0),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct Diagnostics(pub(super) Vec<Diagnostic>);
+
+impl Compute for Diagnostics {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.diagnostics.clone())
+    }
+}
+
+// The sign column shows while there are diagnostics.
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct SignWidth(pub(super) usize);
+
+impl Compute for SignWidth {
+    type Source = Diagnostics;
+    fn compute(source: &Self::Source) -> Self {
+        match source.0.is_empty() {
+            true => Self(0),
+            false => Self(2),
+        }
+    }
+}
+
+// Where the text starts, after the sign column and the line numbers.
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct GutterWidth(pub(super) usize);
+
+impl Compute for GutterWidth {
+    type Source = (SignWidth, NumberWidth);
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.0 .0 + source.1 .0)
+    }
+}
+
+impl Compute for Options {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        source.options()
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct TabStop(pub(super) usize);
+
+impl Compute for TabStop {
+    type Source = Options;
+    fn compute(source: &Self::Source) -> Self {
+        Self(max(1, source.number("tabstop")))
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct Number(pub(super) bool);
+
+impl Compute for Number {
+    type Source = Options;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.bool("number"))
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct IgnoreCase(pub(super) bool);
+
+impl Compute for IgnoreCase {
+    type Source = (Options, SearchPattern);
+    fn compute(source: &Self::Source) -> Self {
+        let options = &source.0;
+        let smart = options.bool("smartcase") && source.1 .0.chars().any(char::is_uppercase);
+        Self(options.bool("ignorecase") && !smart)
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct HlSearch(pub(super) bool);
+
+impl Compute for HlSearch {
+    type Source = Options;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.bool("hlsearch"))
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct WrapScan(pub(super) bool);
+
+impl Compute for WrapScan {
+    type Source = Options;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.bool("wrapscan"))
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct Wrap(pub(super) bool);
+
+impl Compute for Wrap {
+    type Source = Options;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.bool("wrap"))
+    }
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub(super) struct CurrentLine(pub(super) String);
 
@@ -242,6 +483,23 @@ impl Compute for TerminalHeight {
     }
 }
 
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct TerminalWidth(pub(super) usize);
+
+impl Compute for TerminalWidth {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.size.0 as usize)
+    }
+}
+
+impl Compute for Theme {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        (*source.theme).clone()
+    }
+}
+
 impl Compute for Mode {
     type Source = State;
     fn compute(source: &Self::Source) -> Self {
@@ -249,6 +507,24 @@ impl Compute for Mode {
     }
 }
 
+// The first and the last selected position in visual mode.
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct VisualSelection(pub(super) Option<(Position, Position)>);
+
+impl Compute for VisualSelection {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        if !source.mode.is_visual() {
+            return Self(None);
+        }
+        let (anchor, cursor) = (source.anchor, source.cursor);
+        match (anchor.row, anchor.col) <= (cursor.row, cursor.col) {
+            true => Self(Some((anchor, cursor))),
+            false => Self(Some((cursor, anchor))),
+        }
+    }
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub(super) struct RowOffset(pub(super) usize);
 
@@ -265,38 +541,378 @@ pub(super) struct TextareaHeight(pub(super) usize);
 impl Compute for TextareaHeight {
     type Source = TerminalHeight;
     fn compute(source: &Self::Source) -> Self {
-        Self(source.0 - 2)
+        Self(max(1, source.0.saturating_sub(2)))
+    }
+}
+
+// The columns of the `:InspectTree` split on the right half, its border included.
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct SplitWidth(pub(super) usize);
+
+impl Compute for SplitWidth {
+    type Source = (InspectedTree, TerminalWidth);
+    fn compute(source: &Self::Source) -> Self {
+        let width = source.1 .0;
+        match source.0 .0 {
+            Some(_) => Self(width - width / 2),
+            None => Self(0),
+        }
+    }
+}
+
+// The columns between the gutter and the split.
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct TextareaWidth(pub(super) usize);
+
+impl Compute for TextareaWidth {
+    type Source = (TerminalWidth, GutterWidth, SplitWidth);
+    fn compute(source: &Self::Source) -> Self {
+        let width = source.0 .0.saturating_sub(source.1 .0 + source.2 .0);
+        Self(max(1, width))
+    }
+}
+
+// The display columns where the screen rows of a line start, when `line` has its tabs
+// expanded and wraps at `width`. A wide char that does not fit goes to the next row.
+pub(super) fn row_starts(line: &str, width: usize) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut col = 0;
+    for c in line.chars() {
+        let w = c.width().unwrap_or(0);
+        if col + w > starts[starts.len() - 1] + width && col > starts[starts.len() - 1] {
+            starts.push(col);
+        }
+        col += w;
     }
+    starts
+}
+
+// How the lines wrap, if they do.
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct Wrapping {
+    buffer: Buffer,
+    tab_stop: usize,
+    width: Option<usize>,
+}
+
+impl Compute for Wrapping {
+    type Source = (Buffer, TabStop, Wrap, TextareaWidth);
+    fn compute(source: &Self::Source) -> Self {
+        Self {
+            buffer: source.0.clone(),
+            tab_stop: source.1 .0,
+            width: Some(source.3 .0).filter(|_| source.2 .0),
+        }
+    }
+}
+
+impl Wrapping {
+    // The display columns where the screen rows of the line `row` start.
+    pub(super) fn starts(&self, row: usize) -> Vec<usize> {
+        match self.width {
+            Some(width) => {
+                let line = self.buffer.line(row);
+                row_starts(&expand_tabs(&line.as_str(), self.tab_stop), width)
+            }
+            None => vec![0],
+        }
+    }
+
+    pub(super) fn height(&self, row: usize) -> usize {
+        self.starts(row).len()
+    }
+}
+
+pub(super) fn expand_tabs(s: &str, tab_stop: usize) -> String {
+    let mut result = String::new();
+    let mut width = 0;
+    for c in s.chars() {
+        if c == '\t' {
+            let n = tab_stop - width % tab_stop;
+            result.push_str(&" ".repeat(n));
+            width += n;
+        } else {
+            result.push(c);
+            width += c.width().unwrap_or(0);
+        }
+    }
+    result
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub(super) struct RowOffsetView(pub(super) usize);
 
 impl Compute for RowOffsetView {
-    type Source = (RowOffset, TextareaHeight, CursorRowView);
+    type Source = (
+        RowOffset,
+        TextareaHeight,
+        CursorRowView,
+        ClosedFolds,
+        Wrapping,
+    );
     fn compute(source: &Self::Source) -> Self {
         let row_offset = source.0 .0;
         let textarea_height = source.1 .0;
         let row = source.2 .0;
-
-        Self(max(
-            min(row, row_offset),
-            (row + 1).saturating_sub(textarea_height),
+        let closed = &source.3 .0;
+        let wrapping = &source.4;
+
+        Self(fold::scroll(
+            closed,
+            row_offset,
+            row,
+            textarea_height,
+            |row| wrapping.height(row),
         ))
     }
 }
 
 #[derive(PartialEq, Clone, Debug)]
-pub(super) struct LineRange(pub(super) Range<usize>);
+pub(super) struct ClosedFolds(pub(super) Vec<Range<usize>>);
 
-impl Compute for LineRange {
-    type Source = (RowOffsetView, LineCount, TextareaHeight);
+impl Compute for ClosedFolds {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.closed_folds())
+    }
+}
+
+// A line on the screen: the rows it shows, whether they are a closed fold, and where on
+// the screen its text starts each row it wraps to, cut off at the bottom.
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct ScreenLine {
+    pub(super) rows: Range<usize>,
+    pub(super) folded: bool,
+    pub(super) top: usize,
+    pub(super) starts: Vec<usize>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct VisibleLines(pub(super) Vec<ScreenLine>);
+
+impl Compute for VisibleLines {
+    type Source = (
+        RowOffsetView,
+        LineCount,
+        TextareaHeight,
+        ClosedFolds,
+        Wrapping,
+    );
     fn compute(source: &Self::Source) -> Self {
         let row_offset = source.0 .0;
         let line_count = source.1 .0;
         let textarea_height = source.2 .0;
+        let closed = &source.3 .0;
+        let wrapping = &source.4;
+
+        let lines = fold::lines(closed, row_offset, textarea_height, line_count, |row| {
+            wrapping.height(row)
+        });
+        let mut top = 0;
+        let lines = lines.into_iter().map(|(rows, folded)| {
+            let mut starts = match folded {
+                true => vec![0],
+                false => wrapping.starts(rows.start),
+            };
+            starts.truncate(textarea_height - top);
+            let line = ScreenLine {
+                rows,
+                folded,
+                top,
+                starts,
+            };
+            top += line.starts.len();
+            line
+        });
+        Self(lines.collect())
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct LineRange(pub(super) Range<usize>);
+
+impl Compute for LineRange {
+    type Source = VisibleLines;
+    fn compute(source: &Self::Source) -> Self {
+        match (source.0.first(), source.0.last()) {
+            (Some(first), Some(last)) => Self(first.rows.start..last.rows.end),
+            _ => Self(0..0),
+        }
+    }
+}
 
-        Self(row_offset..min(line_count, textarea_height + row_offset))
+// Where the cursor line is on the screen, and whether it is a closed fold.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub(super) struct CursorLineView {
+    pub(super) row: usize,
+    pub(super) folded: bool,
+    pub(super) starts: Vec<usize>,
+}
+
+impl Compute for CursorLineView {
+    type Source = (VisibleLines, CursorRowView);
+    fn compute(source: &Self::Source) -> Self {
+        let row = source.1 .0;
+        let line = source.0 .0.iter().find(|line| line.rows.contains(&row));
+        match line {
+            Some(line) => Self {
+                row: line.top,
+                folded: line.folded,
+                starts: line.starts.clone(),
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct FileStatus {
+    pub(super) name: String,
+    pub(super) read_only: bool,
+}
+
+impl Compute for FileStatus {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        Self {
+            name: source.file_name(),
+            read_only: source.read_only,
+        }
+    }
+}
+
+// The definitions around the cursor, like `impl RootStore > fn action`.
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct SymbolPath(pub(super) Vec<String>);
+
+impl Compute for SymbolPath {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.symbol_path.clone())
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct InspectedTree(pub(super) Option<TreeView>);
+
+impl Compute for InspectedTree {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.tree_view.clone())
+    }
+}
+
+// Lines shown at the cursor, like a hover.
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct Popup(pub(super) Vec<String>);
+
+impl Compute for Popup {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        Self(source.popup.clone())
+    }
+}
+
+// The matches of the completion menu that fit in it, with the selected one among them,
+// and how many columns the completed word starts left of the cursor. Empty when closed.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub(super) struct CompletionView {
+    pub(super) lines: Vec<String>,
+    pub(super) selected: Option<usize>,
+    pub(super) prefix_width: usize,
+}
+
+const MENU_HEIGHT: usize = 10;
+
+impl Compute for CompletionView {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        let completion = match &source.completion {
+            Some(completion) => completion,
+            None => return Self::default(),
+        };
+        let matches = completion.matches();
+        let start = completion
+            .selected
+            .map_or(0, |selected| selected.saturating_sub(MENU_HEIGHT - 1));
+        let shown: Vec<_> = matches.iter().skip(start).take(MENU_HEIGHT).collect();
+        let width = shown.iter().map(|c| c.word.width()).max().unwrap_or(0);
+        Self {
+            lines: shown
+                .iter()
+                .map(|c| {
+                    let detail = match c.detail.is_empty() {
+                        true => c.source.label(),
+                        false => &c.detail,
+                    };
+                    let padding = " ".repeat(width - c.word.width());
+                    format!("{}{}  {}", c.word, padding, detail)
+                })
+                .collect(),
+            selected: completion.selected.map(|selected| selected - start),
+            prefix_width: completion.prefix.width(),
+        }
+    }
+}
+
+impl Compute for Picker {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        source.picker.clone()
+    }
+}
+
+// The prompt of the picker, and the matching items that fit in the lower half of the text
+// area with the selected one among them. Empty out of the picker.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub(super) struct PickerView {
+    pub(super) prompt: String,
+    pub(super) lines: Vec<String>,
+    pub(super) selected: usize,
+}
+
+impl Compute for PickerView {
+    type Source = (Mode, Picker, TextareaHeight);
+    fn compute(source: &Self::Source) -> Self {
+        if source.0 != Mode::Picker {
+            return Self::default();
+        }
+        let picker = &source.1;
+        let height = max(1, source.2 .0 / 2);
+        let matches = picker.matches();
+        let start = picker.selected.saturating_sub(height - 1);
+        Self {
+            prompt: format!(
+                "{} ({}/{})> {}",
+                picker.title,
+                matches.len(),
+                picker.items.len(),
+                picker.query
+            ),
+            lines: matches
+                .iter()
+                .skip(start)
+                .take(height)
+                .map(|item| item.label.clone())
+                .collect(),
+            selected: picker.selected - start,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub(super) struct Message(pub(super) Option<String>);
+
+// The diagnostic under the cursor shows when there is nothing else to say.
+impl Compute for Message {
+    type Source = State;
+    fn compute(source: &Self::Source) -> Self {
+        let diagnostic = source
+            .diagnostics
+            .iter()
+            .find(|d| d.contains(source.cursor))
+            .map(|d| d.message.clone());
+        Self(source.message.clone().or(diagnostic))
     }
 }
 
@@ -314,13 +930,15 @@ impl Compute for SearchPattern {
 pub(super) struct MatchPositions(pub(super) Vec<Match>);
 
 impl Compute for MatchPositions {
-    type Source = (SearchPattern, Buffer);
+    type Source = (SearchPattern, Buffer, IgnoreCase);
     fn compute(source: &Self::Source) -> Self {
         let pattern = &source.0 .0;
         if pattern.is_empty() {
             return Self(Vec::new());
         }
-        let re = Regex::new(pattern);
+        let re = RegexBuilder::new(pattern)
+            .case_insensitive(source.2 .0)
+            .build();
         if re.is_err() {
             return Self(Vec::new());
         }
@@ -329,15 +947,8 @@ impl Compute for MatchPositions {
             .find_iter(&source.1.as_str())
             .map(|m| {
                 let range = m.range();
-                let start_position = source.1.get_cursor_by_byte(range.start);
-                let end_position = source.1.get_cursor_by_byte(range.end);
-                Match::new(
-                    Position {
-                        row: start_position.row,
-                        col: start_position.col,
-                    },
-                    end_position.col - start_position.col,
-                )
+                let start_position = source.1.get_position_by_byte(range.start);
+                Match::new(start_position, m.as_str().chars().count())
             })
             .collect();
         Self(result)
@@ -348,9 +959,12 @@ impl Compute for MatchPositions {
 pub(super) struct MatchPositionsInView(pub(super) Vec<Match>);
 
 impl Compute for MatchPositionsInView {
-    type Source = (MatchPositions, LineRange);
+    type Source = (MatchPositions, LineRange, HlSearch);
     fn compute(source: &Self::Source) -> Self {
         let mut result = Vec::new();
+        if !source.2 .0 {
+            return Self(result);
+        }
         let line_range = &source.1 .0;
         for m in &source.0 .0 {
             if line_range.start > m.pos.row {
@@ -359,14 +973,7 @@ impl Compute for MatchPositionsInView {
             if line_range.end <= m.pos.row {
                 break;
             }
-            // FIXME: make it actual position
-            result.push(Match::new(
-                Position {
-                    row: m.pos.row - line_range.start,
-                    col: m.pos.col,
-                },
-                m.len,
-            ));
+            result.push(m.clone());
         }
         Self(result)
     }
@@ -383,7 +990,7 @@ impl Compute for SearchDirection {
 pub(super) struct CursorView(pub(super) Position);
 
 impl Compute for CursorView {
-    type Source = (Cursor, Mode, MatchPositions, SearchDirection);
+    type Source = (Cursor, Mode, MatchPositions, SearchDirection, WrapScan);
 
     fn compute(source: &Self::Source) -> Self {
         let cursor = &source.0 .0;
@@ -403,9 +1010,10 @@ impl Compute for CursorView {
             });
         }
 
+        let wrap = source.4 .0;
         if source.3 == SearchDirection::Forward {
-            return CursorView(*search::get_next(cursor, matches));
+            return CursorView(*search::get_next(cursor, matches, wrap).unwrap_or(cursor));
         }
-        CursorView(*search::get_prev(cursor, matches))
+        CursorView(*search::get_prev(cursor, matches, wrap).unwrap_or(cursor))
     }
 }
@@ -1,5 +1,8 @@
+use crate::keymap::KeymapConfig;
+use crate::language::LanguageConfig;
 use anyhow::Result;
 use serde_derive::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
@@ -9,13 +12,12 @@ pub(super) fn parse<P: AsRef<Path>>(p: P) -> Result<NeuConfig> {
     Ok(config)
 }
 
-#[derive(Deserialize)]
+#[derive(Default, Deserialize)]
+#[serde(default)]
 pub(super) struct NeuConfig {
     pub(super) debug: bool,
-}
-
-impl Default for NeuConfig {
-    fn default() -> Self {
-        NeuConfig { debug: false }
-    }
+    pub(super) theme: Option<String>,
+    pub(super) keymap: KeymapConfig,
+    pub(super) options: toml::value::Table,
+    pub(super) language: HashMap<String, LanguageConfig>,
 }
@@ -0,0 +1,112 @@
+use crate::buffer::Buffer;
+use crate::position::Position;
+use core::cmp::min;
+use tree_sitter::{Node, Tree};
+
+// Errors longer than this are cut in the message.
+const MAX_SNIPPET: usize = 20;
+
+// The LSP severities, most severe first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    pub(super) fn from_lsp(severity: u64) -> Self {
+        match severity {
+            2 => Self::Warning,
+            3 => Self::Information,
+            4 => Self::Hint,
+            _ => Self::Error,
+        }
+    }
+
+    pub(super) fn sign(self) -> &'static str {
+        match self {
+            Self::Error => "E",
+            Self::Warning => "W",
+            Self::Information => "I",
+            Self::Hint => "H",
+        }
+    }
+}
+
+// A problem in the buffer, shown underlined with its message in the status line.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Diagnostic {
+    pub(super) start: Position,
+    pub(super) end: Position,
+    pub(super) message: String,
+    pub(super) severity: Severity,
+}
+
+impl Diagnostic {
+    // An empty range, like a missing `;`, holds the char at its start.
+    pub(super) fn contains(&self, pos: Position) -> bool {
+        let (start, end, pos) = (
+            (self.start.row, self.start.col),
+            (self.end.row, self.end.col),
+            (pos.row, pos.col),
+        );
+        start <= pos && (pos < end || pos == start)
+    }
+}
+
+// The tree may be behind the buffer, so its bytes are kept within it.
+fn position(b: &Buffer, byte: usize) -> Position {
+    b.get_position_by_byte(min(byte, b.0.len_bytes()))
+}
+
+fn snippet(b: &Buffer, node: Node) -> String {
+    let start = min(node.start_byte(), b.0.len_bytes());
+    let bytes: Vec<_> = b
+        .bytes_at(start)
+        .take(node.end_byte().saturating_sub(start))
+        .collect();
+    let text = String::from_utf8_lossy(&bytes);
+    let line = text.lines().next().unwrap_or_default().trim();
+    match line.chars().count() > MAX_SNIPPET {
+        true => format!("{}…", line.chars().take(MAX_SNIPPET).collect::<String>()),
+        false => line.to_string(),
+    }
+}
+
+// Only the subtrees holding errors are walked.
+fn collect(node: Node, b: &Buffer, result: &mut Vec<Diagnostic>) {
+    let message = if node.is_missing() {
+        format!("Missing `{}`", node.kind())
+    } else if node.is_error() {
+        match snippet(b, node) {
+            s if s.is_empty() => "Syntax error".to_string(),
+            s => format!("Syntax error: unexpected `{}`", s),
+        }
+    } else {
+        if node.has_error() {
+            let mut cursor = node.walk();
+            for child in node.children(&mut cursor) {
+                collect(child, b, result);
+            }
+        }
+        return;
+    };
+    // A token missing at the end of a line goes on its last char, where the cursor can be.
+    let mut start = position(b, node.start_byte());
+    start.col = min(start.col, b.row_len(start.row).saturating_sub(1));
+    result.push(Diagnostic {
+        start,
+        end: position(b, node.end_byte()),
+        message,
+        severity: Severity::Error,
+    });
+}
+
+// The `ERROR` and `MISSING` nodes of `tree`, in order.
+pub(super) fn syntax_errors(tree: &Tree, b: &Buffer) -> Vec<Diagnostic> {
+    let mut result = Vec::new();
+    collect(tree.root_node(), b, &mut result);
+    result
+}
@@ -9,6 +9,107 @@ pub(crate) enum EditKind {
     RemoveSelection(Selection),
     AppendYank,
     InsertYank,
-    InsertString(Option<MovementKind>, String),
-    Edit(Selection, String),
+    InsertString(Option<MovementKind>, Inserted),
+    Edit(Selection, Inserted),
+    SwapNextSibling,
+    SwapPrevSibling,
+    // Insert-mode keys, which `.` repeats as part of the inserted text.
+    Backspace,
+    Delete,
+    RemoveWordBack,
+    RemoveLineBack,
+    Indent,
+    Dedent,
+    InsertRegister(char),
+}
+
+// An insert-mode key that changes the text, as `.` repeats it.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum InsertKey {
+    Text(String),
+    Backspace,
+    Delete,
+    Indent,
+    Dedent,
+}
+
+// What was typed in insert mode, in order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Inserted(pub(crate) Vec<InsertKey>);
+
+impl From<&str> for Inserted {
+    fn from(s: &str) -> Self {
+        let mut inserted = Self::default();
+        inserted.push_str(s);
+        inserted
+    }
+}
+
+impl Inserted {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn push_str(&mut self, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        match self.0.last_mut() {
+            Some(InsertKey::Text(text)) => text.push_str(s),
+            _ => self.0.push(InsertKey::Text(s.to_string())),
+        }
+    }
+
+    pub(crate) fn push(&mut self, key: InsertKey) {
+        match key {
+            InsertKey::Text(s) => self.push_str(&s),
+            key => self.0.push(key),
+        }
+    }
+
+    // The last char typed, if nothing was pressed after it.
+    pub(crate) fn last_char(&self) -> Option<char> {
+        match self.0.last() {
+            Some(InsertKey::Text(text)) => text.chars().next_back(),
+            _ => None,
+        }
+    }
+
+    // Takes back the last char typed, if nothing was pressed after it.
+    pub(crate) fn pop_char(&mut self) -> Option<char> {
+        let text = match self.0.last_mut() {
+            Some(InsertKey::Text(text)) => text,
+            _ => return None,
+        };
+        let c = text.pop();
+        if text.is_empty() {
+            self.0.pop();
+        }
+        c
+    }
+
+    // How many of the typed chars are left, those before the insert taken back by
+    // backspaces not counted.
+    pub(crate) fn typed(&self) -> usize {
+        self.0.iter().fold(0, |typed, key| match key {
+            InsertKey::Text(text) => typed + text.chars().count(),
+            InsertKey::Backspace => typed.saturating_sub(1),
+            _ => typed,
+        })
+    }
+
+    // The text left of what was typed, for the `.` register.
+    pub(crate) fn text(&self) -> String {
+        let mut result = String::new();
+        for key in &self.0 {
+            match key {
+                InsertKey::Text(text) => result.push_str(text),
+                InsertKey::Backspace => {
+                    result.pop();
+                }
+                _ => {}
+            }
+        }
+        result
+    }
 }
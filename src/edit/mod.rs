@@ -1,5 +1,5 @@
 mod action;
 mod store;
 
-pub(crate) use action::EditKind;
+pub(crate) use action::{EditKind, Inserted};
 pub(super) use store::EditStore;
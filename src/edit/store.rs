@@ -1,11 +1,28 @@
-use super::action::EditKind;
+use super::action::{EditKind, InsertKey, Inserted};
 use crate::action::ActionKind;
+use crate::fold;
 use crate::mode::Mode;
 use crate::position::Position;
-use crate::selection::Selection;
+use crate::selection::{Selection, SelectionKind};
 use crate::store::{RootStore, Store};
+use crate::syntax;
+use core::cmp::{max, min};
 use core::ops::Range;
-use tree_sitter::{InputEdit, Point};
+use regex::Regex;
+use tree_sitter::InputEdit;
+use unicode_width::UnicodeWidthChar;
+
+// The display width of the start of a line, tabs going to the next tabstop.
+fn width(s: &str, tabstop: usize) -> usize {
+    s.chars().fold(0, |width, c| match c {
+        '\t' => (width / tabstop + 1) * tabstop,
+        c => width + c.width().unwrap_or(0),
+    })
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
 
 pub(crate) struct EditStore<'a> {
     root: &'a mut RootStore,
@@ -13,11 +30,11 @@ pub(crate) struct EditStore<'a> {
 
 impl<'a> Store for EditStore<'a> {
     fn root(&self) -> &RootStore {
-        &self.root
+        self.root
     }
 
     fn root_mut(&mut self) -> &mut RootStore {
-        &mut self.root
+        self.root
     }
 }
 
@@ -26,33 +43,61 @@ impl<'a> EditStore<'a> {
         Self { root }
     }
 
+    // `to` and `range` are char offsets; tree-sitter is told about the bytes and points.
     fn insert(&mut self, to: usize, s: &str) {
-        let pos = self.state().buffer.get_position_by_offset(to);
-        let byte_l = s.bytes().count();
+        self.root_mut().lsp().record_change(Some(to..to), s);
+        let buffer = &self.state().buffer;
+        let pos = buffer.get_position_by_offset(to);
+        let start_byte = buffer.0.char_to_byte(to);
+        let start_position = buffer.get_point_by_byte(start_byte);
+        self.state_mut().buffer.insert(pos, s);
+        if let Some(session) = &mut self.state_mut().snippet {
+            session.inserted(to, s.chars().count());
+        }
+        let rows = s.matches('\n').count();
+        fold::edit(
+            &mut self.state_mut().folds,
+            pos.row,
+            pos.row,
+            pos.row + rows,
+        );
+
+        let new_end_byte = start_byte + s.len();
         let edit = InputEdit {
-            start_byte: to,
-            old_end_byte: to,
-            new_end_byte: to + byte_l,
-            start_position: Point::default(),
-            old_end_position: Point::default(),
-            new_end_position: Point::default(),
+            start_byte,
+            old_end_byte: start_byte,
+            new_end_byte,
+            start_position,
+            old_end_position: start_position,
+            new_end_position: self.state().buffer.get_point_by_byte(new_end_byte),
         };
         self.highlighter_mut().edit_tree(&edit);
-        self.state_mut().buffer.insert(pos, s);
     }
 
     fn remove(&mut self, range: Range<usize>) -> String {
-        let start = range.start;
-        let s = self.state_mut().buffer.remove(range);
+        self.root_mut().lsp().record_change(Some(range.clone()), "");
+        let buffer = &self.state().buffer;
+        let start_byte = buffer.0.char_to_byte(range.start);
+        let old_end_byte = buffer.0.char_to_byte(range.end);
+        let start_position = buffer.get_point_by_byte(start_byte);
+        let old_end_position = buffer.get_point_by_byte(old_end_byte);
+        let (start_row, end_row) = (
+            buffer.0.char_to_line(range.start),
+            buffer.0.char_to_line(range.end),
+        );
+        let s = self.state_mut().buffer.remove(range.clone());
+        if let Some(session) = &mut self.state_mut().snippet {
+            session.removed(range);
+        }
+        fold::edit(&mut self.state_mut().folds, start_row, end_row, start_row);
 
-        let byte_l = s.bytes().count();
         let edit = InputEdit {
-            start_byte: start,
-            old_end_byte: start + byte_l,
-            new_end_byte: start,
-            start_position: Point::default(),
-            old_end_position: Point::default(),
-            new_end_position: Point::default(),
+            start_byte,
+            old_end_byte,
+            new_end_byte: start_byte,
+            start_position,
+            old_end_position,
+            new_end_position: start_position,
         };
         self.highlighter_mut().edit_tree(&edit);
         s
@@ -66,12 +111,15 @@ impl<'a> EditStore<'a> {
 
     pub(crate) fn remove_selection(&mut self, selection: &Selection, count: usize) {
         for _ in 0..count {
-            let range = self.state().measure_selection(selection.clone());
+            let range = self.root_mut().measure_selection(selection.clone());
             let start = range.start;
             let yank = self.remove(range);
             self.root_mut().action(ActionKind::SetYank(yank).once());
             self.root_mut().movement().offset(start);
         }
+        if selection.kind == SelectionKind::Visual {
+            self.state_mut().mode = Mode::Normal(String::new());
+        }
     }
 
     pub(crate) fn append_yank(&mut self, count: usize) {
@@ -116,47 +164,469 @@ impl<'a> EditStore<'a> {
 
         for _ in 0..count {
             self.insert(to, "\n");
-            if let Mode::Insert(_, s) = &mut self.state_mut().mode {
-                s.push('\n');
+            if let Mode::Insert(_, inserted) = &mut self.state_mut().mode {
+                inserted.push_str("\n");
             }
             self.root_mut().movement().down(1);
             self.root_mut().movement().line_head();
         }
     }
 
-    pub(crate) fn insert_char(&mut self, c: char, count: usize) {
+    // Inserts `text` at the cursor as if it was typed.
+    fn type_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
         let to = self.state().get_cursor_offset();
+        self.insert(to, text);
+        if let Mode::Insert(_, inserted) = &mut self.state_mut().mode {
+            inserted.push_str(text);
+        }
+        self.root_mut().movement().offset(to + text.chars().count());
+    }
+
+    fn type_key(&mut self, key: InsertKey) {
+        if let Mode::Insert(_, inserted) = &mut self.state_mut().mode {
+            inserted.push(key);
+        }
+    }
+
+    // The chars before the cursor that were typed since entering insert mode.
+    fn typed(&self) -> usize {
+        match &self.state().mode {
+            Mode::Insert(_, inserted) => inserted.typed(),
+            _ => 0,
+        }
+    }
+
+    // Where the typed text starts on the cursor line. Removing back stops there once.
+    fn insert_start(&self) -> Option<usize> {
+        let typed = self.typed();
+        let cursor = self.state().get_cursor_offset();
+        let head = self.state().current_line().start;
+        (typed > 0).then(|| max(cursor.saturating_sub(typed), head))
+    }
+
+    // Removes from `start` to the cursor. Typed chars are taken back from what `.`
+    // repeats, the others are backspaces in it.
+    fn remove_back(&mut self, start: usize) {
+        let cursor = self.state().get_cursor_offset();
+        if start >= cursor {
+            return;
+        }
+        self.remove(start..cursor);
+        if let Mode::Insert(_, inserted) = &mut self.state_mut().mode {
+            for _ in start..cursor {
+                if inserted.pop_char().is_none() {
+                    inserted.push(InsertKey::Backspace);
+                }
+            }
+        }
+        self.root_mut().movement().offset(start);
+    }
+
+    pub(crate) fn insert_char(&mut self, c: char, count: usize) {
         for _ in 0..count {
-            if let Mode::Insert(_, s) = &mut self.state_mut().mode {
-                s.push(c);
+            let options = self.state().options();
+            match c {
+                // With 'expandtab' a tab is the spaces up to the next tabstop.
+                '\t' if options.bool("expandtab") => {
+                    let tabstop = max(1, options.number("tabstop"));
+                    let head = self.state().current_line().start;
+                    let cursor = self.state().get_cursor_offset();
+                    let before = self.state().buffer.slice(head..cursor).as_str().to_string();
+                    let spaces = tabstop - width(&before, tabstop) % tabstop;
+                    self.type_text(&" ".repeat(spaces));
+                }
+                c => self.type_text(&c.to_string()),
             }
-            self.insert(to, &c.to_string());
-            self.root_mut().movement().right(1);
         }
-        self.history_mut().pop();
     }
 
-    pub(crate) fn insert_string(&mut self, s: &str, count: usize) {
-        let to = self.state().get_cursor_offset();
+    // Over a line start it joins the lines. In the indent with 'expandtab' set, it goes
+    // back to the previous multiple of 'shiftwidth'.
+    pub(crate) fn backspace(&mut self, count: usize) {
         for _ in 0..count {
-            self.insert(to, &s);
-            self.root_mut().movement().right(s.chars().count());
+            let options = self.state().options();
+            let head = self.state().current_line().start;
+            let cursor = self.state().get_cursor_offset();
+            let mut before: Vec<char> = self.state().buffer.slice(head..cursor).chars().collect();
+            let in_indent = !before.is_empty() && before.iter().all(|c| is_blank(*c));
+            if !(in_indent && options.bool("expandtab")) {
+                self.remove_back(cursor.saturating_sub(1));
+                continue;
+            }
+            let (shiftwidth, tabstop) = (
+                max(1, options.number("shiftwidth")),
+                max(1, options.number("tabstop")),
+            );
+            let indent = |chars: &[char]| width(&chars.iter().collect::<String>(), tabstop);
+            let target = (indent(&before) - 1) / shiftwidth * shiftwidth;
+            while indent(&before) > target {
+                before.pop();
+            }
+            self.remove_back(head + before.len());
+        }
+    }
+
+    // At the end of a line it joins the next one.
+    pub(crate) fn delete(&mut self, count: usize) {
+        let cursor = self.state().get_cursor_offset();
+        // The last new line stays.
+        let end = min(
+            cursor + count,
+            self.state().buffer.0.len_chars().saturating_sub(1),
+        );
+        if end <= cursor {
+            return;
+        }
+        self.remove(cursor..end);
+        for _ in cursor..end {
+            self.type_key(InsertKey::Delete);
+        }
+    }
+
+    // Removes the word before the cursor, or the line break at the line start.
+    pub(crate) fn remove_word_back(&mut self) {
+        let head = self.state().current_line().start;
+        let cursor = self.state().get_cursor_offset();
+        if cursor == head {
+            self.backspace(1);
+            return;
+        }
+        let before: Vec<char> = self.state().buffer.slice(head..cursor).chars().collect();
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let mut i = before.len();
+        while i > 0 && before[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        if let Some(&last) = before[..i].last() {
+            while i > 0 && !before[i - 1].is_whitespace() && is_word(before[i - 1]) == is_word(last)
+            {
+                i -= 1;
+            }
+        }
+        let start = max(head + i, self.insert_start().unwrap_or(0));
+        self.remove_back(start);
+    }
+
+    // Removes the typed text before the cursor on the line, or else back to the indent
+    // and then to the line start.
+    pub(crate) fn remove_line_back(&mut self) {
+        let head = self.state().current_line().start;
+        let cursor = self.state().get_cursor_offset();
+        if cursor == head {
+            self.backspace(1);
+            return;
+        }
+        let indent = self
+            .state()
+            .buffer
+            .slice(head..cursor)
+            .chars()
+            .take_while(|c| is_blank(*c))
+            .count();
+        let start = match self.insert_start() {
+            Some(start) => start,
+            None if cursor > head + indent => head + indent,
+            None => head,
+        };
+        self.remove_back(start);
+    }
+
+    // Indents or dedents the line by 'shiftwidth' to a multiple of it, the cursor staying
+    // on the same char.
+    pub(crate) fn shift(&mut self, forward: bool) {
+        let options = self.state().options();
+        let (shiftwidth, tabstop) = (
+            max(1, options.number("shiftwidth")),
+            max(1, options.number("tabstop")),
+        );
+        let head = self.state().current_line().start;
+        let line = self
+            .state()
+            .buffer
+            .line(self.state().cursor.row)
+            .as_str()
+            .to_string();
+        let old: String = line.chars().take_while(|c| is_blank(*c)).collect();
+        let indent = width(&old, tabstop);
+        let indent = match forward {
+            true => (indent / shiftwidth + 1) * shiftwidth,
+            false => indent.saturating_sub(1) / shiftwidth * shiftwidth,
+        };
+        let new = match options.bool("expandtab") {
+            true => " ".repeat(indent),
+            false => "\t".repeat(indent / tabstop) + &" ".repeat(indent % tabstop),
+        };
+
+        let col = self.state().cursor.col;
+        let (old_len, new_len) = (old.chars().count(), new.chars().count());
+        if old != new {
+            self.replace(head..head + old_len, &new);
+        }
+        let col = match col >= old_len {
+            true => col + new_len - old_len,
+            false => min(col, new_len),
+        };
+        self.root_mut().movement().offset(head + col);
+        self.type_key(if forward {
+            InsertKey::Indent
+        } else {
+            InsertKey::Dedent
+        });
+    }
+
+    // `"` and `0` are the yank, `.` the last inserted text, `%` the file name and `/` the
+    // search pattern. The text goes in as it is.
+    pub(crate) fn insert_register(&mut self, register: char) {
+        let state = self.state();
+        let text = match register {
+            '"' | '0' => state.yanked.clone(),
+            '.' => match &state.prev_edit {
+                Some((EditKind::InsertString(_, inserted) | EditKind::Edit(_, inserted), _)) => {
+                    inserted.text()
+                }
+                _ => String::new(),
+            },
+            '%' if state.path.is_some() => state.file_name(),
+            '/' => state.search_pattern.clone(),
+            _ => return,
+        };
+        self.type_text(&text);
+    }
+
+    // Types the keys again.
+    fn play(&mut self, inserted: &Inserted) {
+        for key in &inserted.0 {
+            match key {
+                InsertKey::Text(text) => self.type_text(text),
+                InsertKey::Backspace => {
+                    let cursor = self.state().get_cursor_offset();
+                    self.remove_back(cursor.saturating_sub(1));
+                }
+                InsertKey::Delete => self.delete(1),
+                InsertKey::Indent => self.shift(true),
+                InsertKey::Dedent => self.shift(false),
+            }
+        }
+    }
+
+    pub(crate) fn insert_string(&mut self, inserted: &Inserted, count: usize) {
+        for _ in 0..count {
+            self.play(inserted);
+        }
+    }
+
+    pub(crate) fn replace(&mut self, range: Range<usize>, s: &str) {
+        let start = range.start;
+        if !range.is_empty() {
+            self.remove(range);
+        }
+        if !s.is_empty() {
+            self.insert(start, s);
         }
     }
 
-    pub(crate) fn edit(&mut self, selection: &Selection, s: &str) {
+    // Replaces the word typed in `range` with a completion, in what `.` repeats too. The
+    // part of the word typed before this insert stays out of it.
+    pub(crate) fn complete(&mut self, range: Range<usize>, word: &str) {
+        let start = range.start;
+        let typed = self.remove(range);
+        self.insert(start, word);
+        self.root_mut()
+            .movement()
+            .offset(start + word.chars().count());
+        if let Mode::Insert(_, inserted) = &mut self.state_mut().mode {
+            let mut here = 0;
+            for c in typed.chars().rev() {
+                if inserted.last_char() != Some(c) {
+                    break;
+                }
+                inserted.pop_char();
+                here += 1;
+            }
+            let before: String = typed.chars().take(typed.chars().count() - here).collect();
+            inserted.push_str(word.strip_prefix(before.as_str()).unwrap_or(word));
+        }
+    }
+
+    pub(crate) fn edit(&mut self, selection: &Selection, inserted: &Inserted) {
         self.remove_selection(selection, 1);
-        self.insert_string(s, 1);
+        self.insert_string(inserted, 1);
         self.root_mut().movement().left(1);
     }
 
+    pub(crate) fn replace_all(&mut self, s: &str) {
+        let record = self.root().create_record();
+        let len = self.state().buffer.0.len_chars();
+        self.remove(0..len);
+        self.insert(0, s);
+        self.history_mut().push(record);
+
+        let row = min(
+            self.state().cursor.row,
+            self.state().buffer.count_lines() - 1,
+        );
+        self.state_mut().cursor.row = row;
+    }
+
+    // Applies edits given last ones first, as one undo step.
+    pub(crate) fn apply_text_edits(&mut self, edits: &[(Range<usize>, String)]) {
+        let record = self.root().create_record();
+        for (range, text) in edits {
+            if !range.is_empty() {
+                self.remove(range.clone());
+            }
+            if !text.is_empty() {
+                self.insert(range.start, text);
+            }
+        }
+        self.history_mut().push(record);
+
+        let row = min(
+            self.state().cursor.row,
+            self.state().buffer.count_lines().saturating_sub(1),
+        );
+        self.state_mut().cursor.row = row;
+    }
+
+    // Renames the local symbol under the cursor throughout its scope, as one undo step.
+    pub(crate) fn rename(&mut self, name: &str) {
+        let buffer = self.state().buffer.clone();
+        let cursor = buffer.get_byte_by_position(self.state().cursor);
+        let symbols = self.highlighter_mut().symbols(&buffer, cursor);
+        let symbol = match symbols.into_iter().find(|symbol| symbol.contains(cursor)) {
+            Some(symbol) => symbol,
+            None => {
+                let message = "No local symbol under cursor".to_string();
+                self.root_mut().action(ActionKind::Fail(message).once());
+                return;
+            }
+        };
+
+        let record = self.root().create_record();
+        let occurrences: Vec<_> = symbol
+            .occurrences()
+            .map(|r| buffer.0.byte_to_char(r.start)..buffer.0.byte_to_char(r.end))
+            .collect();
+        for range in occurrences.iter().rev() {
+            self.remove(range.clone());
+            self.insert(range.start, name);
+        }
+        self.history_mut().push(record);
+
+        // Keeps the cursor at the start of the renamed occurrence it was on.
+        let before = occurrences
+            .iter()
+            .take_while(|r| buffer.0.char_to_byte(r.end) <= cursor)
+            .count();
+        let delta = name.chars().count() as isize - symbol.name.chars().count() as isize;
+        let head = occurrences[before].start as isize + before as isize * delta;
+        self.root_mut().movement().offset(head as usize);
+    }
+
+    // Swaps the node at the cursor, or the selected one, with its next or previous named
+    // sibling. The node stays under the cursor or selected at its new place.
+    pub(crate) fn swap_sibling(&mut self, forward: bool) {
+        let buffer = self.state().buffer.clone();
+        let bytes = self.state().selected_bytes();
+        let tree = self.highlighter_mut().current_tree(&buffer);
+        let ranges = tree.and_then(|tree| {
+            let node = syntax::node_at(tree, bytes)?;
+            let sibling = syntax::sibling(node, forward)?;
+            Some((node.byte_range(), sibling.byte_range()))
+        });
+        let (node, sibling) = match ranges {
+            Some(ranges) => ranges,
+            None => {
+                self.state_mut().message = Some("No sibling node to swap with".to_string());
+                return;
+            }
+        };
+
+        let chars = |r: Range<usize>| buffer.0.byte_to_char(r.start)..buffer.0.byte_to_char(r.end);
+        let (node, sibling) = (chars(node), chars(sibling));
+        let (first, second) = match forward {
+            true => (node.clone(), sibling),
+            false => (sibling, node.clone()),
+        };
+        let first_text = buffer.slice(first.clone()).as_str().to_string();
+        let second_text = buffer.slice(second.clone()).as_str().to_string();
+        // The later node is replaced first so that the earlier offsets stay valid.
+        self.remove(second.clone());
+        self.insert(second.start, &first_text);
+        self.remove(first.clone());
+        self.insert(first.start, &second_text);
+
+        let start = match forward {
+            true => second.start + second.len() - first.len(),
+            false => first.start,
+        };
+        if self.state().mode.is_visual() {
+            let anchor = self.state().buffer.get_position_by_offset(start);
+            self.state_mut().anchor = anchor;
+            self.state_mut().expanded.clear();
+            self.root_mut().movement().offset(start + node.len() - 1);
+        } else {
+            self.root_mut().movement().offset(start);
+        }
+    }
+
+    pub(crate) fn substitute(
+        &mut self,
+        rows: Range<usize>,
+        re: &Regex,
+        replacement: &str,
+        global: bool,
+    ) -> bool {
+        let record = self.root().create_record();
+        let mut last = None;
+        for row in rows.rev() {
+            let range = self.state().buffer.line_range(row);
+            let line = self.state().buffer.line(row).as_str().to_string();
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let limit = if global { 0 } else { 1 };
+            let replaced = re.replacen(line, limit, replacement);
+            if replaced == line {
+                continue;
+            }
+
+            let start = range.start;
+            self.remove(start..start + line.chars().count());
+            self.insert(start, &replaced);
+            last.get_or_insert(row);
+        }
+
+        match last {
+            Some(row) => {
+                self.history_mut().push(record);
+                let head = self.state().buffer.current_line_indent_head(row);
+                self.root_mut().movement().offset(head);
+                true
+            }
+            None => false,
+        }
+    }
+
     pub(crate) fn action(&mut self, edit: EditKind, count: usize) {
-        use EditKind::*;
+        // Edits in insert mode are part of the insert, which is one undo step and what `.`
+        // repeats.
+        if self.state().mode.is_insert() {
+            self.apply(&edit, count);
+            return;
+        }
         let record = self.root().create_record();
         self.history_mut().push(record);
-        match &edit {
+        self.apply(&edit, count);
+        self.state_mut().prev_edit = Some((edit, count));
+    }
+
+    fn apply(&mut self, edit: &EditKind, count: usize) {
+        use EditKind::*;
+        match edit {
             RemoveChar => self.remove_char(count),
-            RemoveSelection(selection) => self.remove_selection(&selection, count),
+            RemoveSelection(selection) => self.remove_selection(selection, count),
             AppendYank => self.append_yank(count),
             InsertYank => self.insert_yank(count),
             LineBreak => self.line_break(count),
@@ -168,7 +638,128 @@ impl<'a> EditStore<'a> {
                 self.insert_string(s, count)
             }
             Edit(selection, s) => self.edit(selection, s),
+            SwapNextSibling => self.swap_sibling(true),
+            SwapPrevSibling => self.swap_sibling(false),
+            Backspace => self.backspace(count),
+            Delete => self.delete(count),
+            RemoveWordBack => self.remove_word_back(),
+            RemoveLineBack => self.remove_line_back(),
+            Indent => self.shift(true),
+            Dedent => self.shift(false),
+            InsertRegister(register) => self.insert_register(*register),
         };
-        self.state_mut().prev_edit = Some((edit, count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::buffer::Buffer;
+    use crate::highlight::Highlighter;
+    use crate::language;
+    use crate::renderer::Renderer;
+    use tree_sitter::Parser;
+
+    const TEXTS: [&str; 6] = ["é", "日本", "🦀", "a", "\n", "ß "];
+
+    // xorshift, so that a failure can be replayed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    // The char ranges between the quotes of the string literals, which stay valid Rust
+    // whatever is typed into them.
+    fn literals(s: &str) -> Vec<Range<usize>> {
+        let quotes: Vec<_> = s
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| *c == '"')
+            .map(|(i, _)| i)
+            .collect();
+        quotes.chunks(2).map(|q| q[0] + 1..q[1]).collect()
+    }
+
+    #[test]
+    fn edits_keep_the_tree_in_sync_with_the_buffer() {
+        let (_, rx) = flume::unbounded();
+        let renderer = Renderer::with_backend(Box::new(MemoryBackend::new(40, 8)));
+        let mut root = RootStore::new(rx, renderer);
+        let mut store = root.edit();
+        store.state_mut().buffer = Buffer::from("fn f() {\n    let s = \"ä\";\n}\n");
+        *store.highlighter_mut() = Highlighter::new("rust");
+
+        let mut parser = Parser::new();
+        let grammar = language::find("rust").and_then(|def| def.grammar).unwrap();
+        parser.set_language(grammar()).unwrap();
+
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let literals = literals(&store.state().buffer.as_str());
+            let literal = literals[rng.below(literals.len())].clone();
+            let text = store.state().buffer.as_str().to_string();
+            match rng.below(6) {
+                0 => {
+                    let to = literal.start + rng.below(literal.len() + 1);
+                    store.insert(to, TEXTS[rng.below(TEXTS.len())]);
+                }
+                1 if !literal.is_empty() => {
+                    let start = literal.start + rng.below(literal.len());
+                    let end = start + 1 + rng.below(literal.end - start);
+                    store.remove(start..end);
+                }
+                // From inside one literal to inside a later one, which joins the two statements
+                // and removes every node in between.
+                2 if literals.len() > 1 => {
+                    let i = rng.below(literals.len() - 1);
+                    let (first, last) = (
+                        &literals[i],
+                        &literals[i + 1 + rng.below(literals.len() - i - 1)],
+                    );
+                    let start = first.start + rng.below(first.len() + 1);
+                    let end = last.start + rng.below(last.len() + 1);
+                    store.remove(start..end);
+                }
+                // Splits a literal into two statements.
+                3 => {
+                    let to = literal.start + rng.below(literal.len() + 1);
+                    store.insert(to, "\";\n    let u = \"ö");
+                }
+                // A multibyte char, and sometimes the ASCII before it.
+                4 => {
+                    let chars: Vec<_> = literals
+                        .iter()
+                        .flat_map(|l| l.clone())
+                        .filter(|&i| text.chars().nth(i).is_some_and(|c| c.len_utf8() > 1))
+                        .collect();
+                    if !chars.is_empty() {
+                        let end = chars[rng.below(chars.len())] + 1;
+                        let start = end - 1 - rng.below(2);
+                        if literals.iter().any(|l| l.start <= start && end <= l.end) {
+                            store.remove(start..end);
+                        }
+                    }
+                }
+                _ => store.insert(literal.end + 2, "\n    let t = \"ü\";"),
+            }
+
+            let buffer = store.state().buffer.clone();
+            let incremental = store.highlighter_mut().current_tree(&buffer).unwrap();
+            let fresh = parser.parse(buffer.as_str().as_bytes(), None).unwrap();
+            assert_eq!(
+                incremental.root_node().to_sexp(),
+                fresh.root_node().to_sexp(),
+                "{}",
+                buffer.as_str()
+            );
+            assert_eq!(incremental.root_node().end_byte(), buffer.0.len_bytes());
+        }
     }
 }
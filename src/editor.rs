@@ -1,4 +1,5 @@
-use std::io::stdin;
+use std::io::Read;
+use std::thread;
 
 use termion::event::Key;
 use termion::input::TermRead;
@@ -6,11 +7,18 @@ use termion::input::TermRead;
 use crate::action::{Action, ActionKind};
 use crate::cmd;
 use crate::cmdline;
+use crate::completion::CompletionKind;
 use crate::edit::EditKind;
+use crate::keymap::{Lookup, Rhs};
 use crate::mode::Mode;
 use crate::movement::MovementKind;
+use crate::snippet::SnippetKind;
+use crate::state::State;
 
-use flume::Sender;
+use flume::{RecvTimeoutError, Sender};
+use smol::{future, Timer};
+
+const MAX_MAP_DEPTH: usize = 1000;
 
 pub(super) struct Editor {
     store: Sender<Action>,
@@ -25,7 +33,10 @@ impl Editor {
         let (tx, rx) = flume::unbounded();
         self.store.send(ActionKind::GetState(tx).once()).unwrap();
         let state = rx.recv_async().await.unwrap();
-        let parsed = cmd::parse(state.mode.get_cmd());
+        let parsed = match &state.mode {
+            Mode::Visual(cmd) => cmd::parse_visual(cmd),
+            mode => cmd::parse(mode.get_cmd()),
+        };
         if parsed.is_err() {
             return;
         }
@@ -39,106 +50,253 @@ impl Editor {
         let (tx, rx) = flume::unbounded();
         self.store.send(ActionKind::GetState(tx).once()).unwrap();
         let state = rx.recv_async().await.unwrap();
-        let parsed = cmdline::parse(state.mode.get_cmdline());
-        if parsed.is_err() {
-            self.store.send(ActionKind::IntoNormalMode.once()).unwrap();
-            return;
+        let cmdline = state.mode.get_cmdline();
+        self.store.send(ActionKind::IntoNormalMode.once()).unwrap();
+        self.execute(cmdline);
+    }
+
+    fn execute(&self, cmdline: &str) {
+        match cmdline::parse(cmdline) {
+            Ok((_, action)) => self.store.send(action).unwrap(),
+            Err(_) if cmdline.is_empty() => {}
+            Err(_) => {
+                let message = format!("E492: Not an editor command: {}", cmdline);
+                self.store.send(ActionKind::Fail(message).once()).unwrap();
+            }
         }
-        let (_, action) = parsed.unwrap();
+    }
 
-        self.store.send(action).unwrap();
-        self.store.send(ActionKind::IntoNormalMode.once()).unwrap();
+    // Some keys go to the completion menu while it is open.
+    fn handle_insert_mode(&self, k: Key, state: &State) {
+        let completion = state.completion.as_ref();
+        let open = completion.is_some();
+        let selected = completion.is_some_and(|c| c.selected.is_some());
+        let action = match k {
+            Key::Ctrl('n') => ActionKind::from(CompletionKind::Next),
+            Key::Ctrl('p') => ActionKind::from(CompletionKind::Prev),
+            Key::Down if open => ActionKind::from(CompletionKind::Next),
+            Key::Up if open => ActionKind::from(CompletionKind::Prev),
+            Key::Ctrl('y') if open => ActionKind::from(CompletionKind::Accept),
+            Key::Ctrl('e') if open => ActionKind::from(CompletionKind::Cancel),
+            Key::Char('\n') if selected => ActionKind::from(CompletionKind::Accept),
+            Key::Char('\n') => ActionKind::from(EditKind::LineBreak),
+            Key::Char('\t') => ActionKind::from(SnippetKind::Next),
+            Key::BackTab => ActionKind::from(SnippetKind::Prev),
+            Key::Char(c) => ActionKind::from(EditKind::InsertChar(c)),
+            Key::Backspace | Key::Ctrl('h') => ActionKind::from(EditKind::Backspace),
+            Key::Delete => ActionKind::from(EditKind::Delete),
+            Key::Ctrl('w') => ActionKind::from(EditKind::RemoveWordBack),
+            Key::Ctrl('u') => ActionKind::from(EditKind::RemoveLineBack),
+            Key::Ctrl('t') => ActionKind::from(EditKind::Indent),
+            Key::Ctrl('d') => ActionKind::from(EditKind::Dedent),
+            Key::Ctrl('o') => ActionKind::IntoOneShotMode,
+            Key::Left => ActionKind::InsertMovement(MovementKind::Left),
+            Key::Right => ActionKind::InsertMovement(MovementKind::Right),
+            Key::Up => ActionKind::InsertMovement(MovementKind::Up),
+            Key::Down => ActionKind::InsertMovement(MovementKind::Down),
+            Key::Home => ActionKind::InsertMovement(MovementKind::LineHead),
+            Key::End => ActionKind::InsertMovement(MovementKind::LineTail),
+            Key::Esc | Key::Ctrl('c') => ActionKind::IntoNormalMode,
+            _ => return,
+        };
+        self.store.send(action.once()).unwrap();
     }
 
-    fn handle_insert_mode(&self, k: Key) {
-        match k {
-            Key::Char(c) => {
-                if c == '\n' {
-                    self.store
-                        .send(ActionKind::from(EditKind::LineBreak).once())
-                        .unwrap();
-                    return;
+    pub(super) async fn run<R: Read + Send + 'static>(&self, input: R) {
+        let (tx, rx) = flume::unbounded();
+        thread::spawn(move || {
+            for k in input.keys() {
+                let sent = match k {
+                    // Terminals send <A-x> as `<Esc>x`, which is what scripts mean by it.
+                    Ok(Key::Alt(c)) => tx.send(Key::Esc).and_then(|_| tx.send(Key::Char(c))),
+                    Ok(k) => tx.send(k),
+                    Err(_) => break,
+                };
+                if sent.is_err() {
+                    break;
                 }
-                self.store
-                    .send(ActionKind::from(EditKind::InsertChar(c)).once())
-                    .unwrap();
             }
-            Key::Esc | Key::Ctrl('c') => {
-                self.store.send(ActionKind::IntoNormalMode.once()).unwrap();
+        });
+
+        let mut pending = Vec::new();
+        loop {
+            let timeout = match pending.is_empty() {
+                true => None,
+                false => self.state().await.map(|state| state.keymap.timeout),
+            };
+            // Waiting must not block the executor, which runs the language servers too.
+            let recv = async {
+                rx.recv_async()
+                    .await
+                    .map_err(|_| RecvTimeoutError::Disconnected)
+            };
+            let k = match timeout {
+                Some(timeout) => {
+                    let timer = async {
+                        Timer::after(timeout).await;
+                        Err(RecvTimeoutError::Timeout)
+                    };
+                    future::or(recv, timer).await
+                }
+                None => recv.await,
+            };
+            match k {
+                Ok(k) => {
+                    pending.push(k);
+                    self.feed(&mut pending, false).await;
+                }
+                Err(RecvTimeoutError::Timeout) => self.feed(&mut pending, true).await,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.feed(&mut pending, true).await;
+                    break;
+                }
             }
-            _ => {}
         }
     }
 
-    pub(super) async fn run(&self) {
-        let stdin = stdin();
-        for k in stdin.keys() {
-            let (tx, rx) = flume::unbounded();
-            self.store.send(ActionKind::GetState(tx).once()).unwrap();
-            let state = rx.recv_async().await.unwrap();
-            match &state.mode {
-                Mode::Normal(_) => {
-                    match k.unwrap() {
-                        Key::Char(c) => self.store.send(ActionKind::PushCmd(c).once()).unwrap(),
-                        Key::Ctrl(c) => self
-                            .store
-                            .send(ActionKind::PushCmdStr(format!("<C-{}>", c)).once())
-                            .unwrap(),
-                        Key::Up => self
-                            .store
-                            .send(ActionKind::PushCmdStr("<Up>".to_string()).once())
-                            .unwrap(),
-                        Key::Down => self
-                            .store
-                            .send(ActionKind::PushCmdStr("<Down>".to_string()).once())
-                            .unwrap(),
-                        Key::Left => self
-                            .store
-                            .send(ActionKind::PushCmdStr("<Left>".to_string()).once())
-                            .unwrap(),
-                        Key::Right => self
-                            .store
-                            .send(ActionKind::PushCmdStr("<Right>".to_string()).once())
-                            .unwrap(),
-                        Key::Esc => self
-                            .store
-                            .send(ActionKind::PushCmdStr("<Esc>".to_string()).once())
-                            .unwrap(),
-                        _ => {}
-                    };
-                    self.handle_normal_mode().await;
+    async fn state(&self) -> Option<State> {
+        let (tx, rx) = flume::unbounded();
+        self.store.send(ActionKind::GetState(tx).once()).ok()?;
+        rx.recv_async().await.ok()
+    }
+
+    // Expands mappings at the head of `pending` and handles the keys that are left.
+    async fn feed(&self, pending: &mut Vec<Key>, timed_out: bool) {
+        let mut depth = 0;
+        while !pending.is_empty() {
+            let state = match self.state().await {
+                Some(state) => state,
+                None => return,
+            };
+            let (len, mapping) = match state.keymap.lookup(&state.mode, pending, timed_out) {
+                Lookup::Pending => return,
+                // <C-r> in insert mode waits for the name of the register.
+                Lookup::NotFound if state.mode.is_insert() && pending[0] == Key::Ctrl('r') => {
+                    if pending.len() < 2 {
+                        return;
+                    }
+                    pending.remove(0);
+                    if let Key::Char(c) = pending.remove(0) {
+                        let action = ActionKind::from(EditKind::InsertRegister(c));
+                        self.store.send(action.once()).unwrap();
+                    }
+                    continue;
                 }
-                Mode::Insert(_, _) => self.handle_insert_mode(k.unwrap()),
-                Mode::CmdLine(_) => {
-                    match k.unwrap() {
-                        Key::Char('\n') => {
-                            self.handle_cmd_line_mode().await;
-                        }
-                        Key::Char(c) => self.store.send(ActionKind::PushCmd(c).once()).unwrap(),
-                        Key::Backspace => self.store.send(ActionKind::PopCmd.once()).unwrap(),
-                        Key::Esc | Key::Ctrl('c') => {
-                            self.store.send(ActionKind::IntoNormalMode.once()).unwrap()
-                        }
-                        _ => {}
-                    };
+                Lookup::NotFound => {
+                    let k = pending.remove(0);
+                    self.handle_key(k).await;
+                    continue;
                 }
-                Mode::Search => {
-                    match k.unwrap() {
-                        Key::Char('\n') => {
-                            self.store
-                                .send(ActionKind::from(MovementKind::AsSeenOnView).once())
-                                .unwrap();
-                            self.store.send(ActionKind::IntoNormalMode.once()).unwrap()
-                        }
-                        Key::Char(c) => self.store.send(ActionKind::PushSearch(c).once()).unwrap(),
-                        Key::Backspace => self.store.send(ActionKind::PopSearch.once()).unwrap(),
-                        Key::Esc | Key::Ctrl('c') => {
-                            self.store.send(ActionKind::ClearSearch.once()).unwrap();
-                            self.store.send(ActionKind::IntoNormalMode.once()).unwrap()
-                        }
-                        _ => {}
-                    };
+                Lookup::Found(len, mapping) => (len, mapping),
+            };
+            pending.drain(..len);
+
+            depth += 1;
+            if depth > MAX_MAP_DEPTH {
+                pending.clear();
+                let message = "E223: recursive mapping".to_string();
+                self.store.send(ActionKind::Fail(message).once()).unwrap();
+                return;
+            }
+            match mapping.rhs {
+                Rhs::Command(cmdline) => self.execute(&cmdline),
+                Rhs::Keys(keys) if mapping.remap => {
+                    pending.splice(..0, keys);
                 }
+                Rhs::Keys(keys) => {
+                    for k in keys {
+                        self.handle_key(k).await;
+                    }
+                }
+            }
+        }
+    }
+
+    pub(super) fn quit(&self) {
+        self.store.send(ActionKind::Quit.once()).ok();
+    }
+
+    async fn handle_key(&self, k: Key) {
+        let state = match self.state().await {
+            Some(state) => state,
+            None => return,
+        };
+        match &state.mode {
+            Mode::Normal(_) | Mode::Visual(_) => {
+                match k {
+                    Key::Char(c) => self.store.send(ActionKind::PushCmd(c).once()).unwrap(),
+                    Key::Ctrl(c) => self
+                        .store
+                        .send(ActionKind::PushCmdStr(format!("<C-{}>", c)).once())
+                        .unwrap(),
+                    Key::Up => self
+                        .store
+                        .send(ActionKind::PushCmdStr("<Up>".to_string()).once())
+                        .unwrap(),
+                    Key::Down => self
+                        .store
+                        .send(ActionKind::PushCmdStr("<Down>".to_string()).once())
+                        .unwrap(),
+                    Key::Left => self
+                        .store
+                        .send(ActionKind::PushCmdStr("<Left>".to_string()).once())
+                        .unwrap(),
+                    Key::Right => self
+                        .store
+                        .send(ActionKind::PushCmdStr("<Right>".to_string()).once())
+                        .unwrap(),
+                    Key::Esc => self
+                        .store
+                        .send(ActionKind::PushCmdStr("<Esc>".to_string()).once())
+                        .unwrap(),
+                    _ => {}
+                };
+                self.handle_normal_mode().await;
+            }
+            Mode::Insert(_, _) => self.handle_insert_mode(k, &state),
+            Mode::CmdLine(_) => {
+                match k {
+                    Key::Char('\n') => {
+                        self.handle_cmd_line_mode().await;
+                    }
+                    Key::Char(c) => self.store.send(ActionKind::PushCmd(c).once()).unwrap(),
+                    Key::Backspace => self.store.send(ActionKind::PopCmd.once()).unwrap(),
+                    Key::Esc | Key::Ctrl('c') => {
+                        self.store.send(ActionKind::IntoNormalMode.once()).unwrap()
+                    }
+                    _ => {}
+                };
+            }
+            Mode::Search => {
+                match k {
+                    Key::Char('\n') => {
+                        self.store
+                            .send(ActionKind::from(MovementKind::AsSeenOnView).once())
+                            .unwrap();
+                        self.store.send(ActionKind::IntoNormalMode.once()).unwrap()
+                    }
+                    Key::Char(c) => self.store.send(ActionKind::PushSearch(c).once()).unwrap(),
+                    Key::Backspace => self.store.send(ActionKind::PopSearch.once()).unwrap(),
+                    Key::Esc | Key::Ctrl('c') => {
+                        self.store.send(ActionKind::ClearSearch.once()).unwrap();
+                        self.store.send(ActionKind::IntoNormalMode.once()).unwrap()
+                    }
+                    _ => {}
+                };
+            }
+            Mode::Picker => {
+                let action = match k {
+                    Key::Char('\n') => ActionKind::Pick,
+                    Key::Down | Key::Ctrl('n') | Key::Char('\t') => {
+                        ActionKind::SelectPickerItem(true)
+                    }
+                    Key::Up | Key::Ctrl('p') | Key::BackTab => ActionKind::SelectPickerItem(false),
+                    Key::Char(c) => ActionKind::PushPicker(c),
+                    Key::Backspace => ActionKind::PopPicker,
+                    Key::Esc | Key::Ctrl('c') => ActionKind::IntoNormalMode,
+                    _ => return,
+                };
+                self.store.send(action.once()).unwrap();
             }
         }
     }
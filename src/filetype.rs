@@ -0,0 +1,211 @@
+use crate::buffer::Buffer;
+use crate::grammar;
+use crate::language::{LanguageDef, LANGUAGES};
+use core::cmp::{max, min};
+use std::path::Path;
+
+// Emacs mode names that differ from filetypes.
+const ALIASES: &[(&str, &str)] = &[("js", "javascript"), ("shell-script", "sh"), ("c++", "c")];
+
+// Lines at the start and end of a buffer that are searched for modelines.
+const MODELINES: usize = 5;
+
+fn alias(name: &str) -> String {
+    let name = name.to_lowercase();
+    ALIASES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, ft)| ft.to_string())
+        .or_else(|| from_interpreter(&name))
+        .unwrap_or(name)
+}
+
+fn find_language(f: impl Fn(&LanguageDef) -> bool) -> Option<String> {
+    LANGUAGES
+        .iter()
+        .find(|def| f(def))
+        .map(|def| def.name.to_string())
+}
+
+// `python3.11` is `python`.
+fn from_interpreter(name: &str) -> Option<String> {
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    find_language(|def| def.interpreters.contains(&name))
+}
+
+// Languages named by code blocks and injections: `rust`, `rs`, `py`, `python3` or `bash`.
+pub(super) fn from_name(name: &str) -> String {
+    let name = name.to_lowercase();
+    find_language(|def| def.name == name || def.extensions.contains(&name.as_str()))
+        .or_else(|| grammar::find_dynamic(|g| g.extensions.contains(&name)))
+        .unwrap_or_else(|| alias(&name))
+}
+
+// The files declared by grammars loaded at runtime win over the built-in ones.
+fn from_path(path: &str) -> Option<String> {
+    let path = Path::new(path);
+    let name = path.file_name()?.to_str()?;
+    grammar::find_dynamic(|g| g.file_names.iter().any(|n| n == name))
+        .or_else(|| find_language(|def| def.file_names.contains(&name)))
+        .or_else(|| {
+            let extension = path.extension()?.to_str()?;
+            grammar::find_dynamic(|g| g.extensions.iter().any(|e| e == extension))
+                .or_else(|| find_language(|def| def.extensions.contains(&extension)))
+        })
+}
+
+// `#!/usr/bin/python3` or `#!/usr/bin/env -S node --flag`
+fn from_shebang(buffer: &Buffer) -> Option<String> {
+    let line = buffer.line(0).as_str().to_string();
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = words.find(|w| !w.starts_with('-'))?;
+    }
+    from_interpreter(interpreter)
+}
+
+pub(super) fn detect(path: Option<&str>, buffer: &Buffer) -> Option<String> {
+    path.and_then(from_path).or_else(|| from_shebang(buffer))
+}
+
+// `vim: set ts=4 sw=4 et:` or `vim: ts=4:sw=4`
+fn vim_modeline(line: &str) -> Option<Vec<String>> {
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        let i = line.find(marker)?;
+        let preceded = line[..i].ends_with(char::is_whitespace) || (i == 0 && *marker == "vim:");
+        Some(i + marker.len()).filter(|_| preceded)
+    })?;
+    let rest = line[start..].trim_start();
+
+    let args = match rest
+        .strip_prefix("set ")
+        .or_else(|| rest.strip_prefix("se "))
+    {
+        Some(rest) => rest
+            .split(':')
+            .next()?
+            .split_whitespace()
+            .collect::<Vec<_>>(),
+        None => rest
+            .split([':', ' ', '\t', '\n'])
+            .filter(|s| !s.is_empty())
+            .collect(),
+    };
+    Some(args.into_iter().map(String::from).collect())
+}
+
+// `-*- mode: python; tab-width: 4 -*-` or `-*- python -*-`
+fn emacs_modeline(line: &str) -> Option<Vec<String>> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    let content = line[start..end].trim();
+    if !content.contains(':') {
+        return Some(vec![format!("filetype={}", alias(content))]);
+    }
+
+    let mut args = Vec::new();
+    for variable in content.split(';') {
+        let (key, value) = match variable.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        match key {
+            "mode" => args.push(format!("filetype={}", alias(value))),
+            "tab-width" => args.push(format!("tabstop={}", value)),
+            "indent-tabs-mode" if value == "nil" => args.push("expandtab".to_string()),
+            "indent-tabs-mode" => args.push("noexpandtab".to_string()),
+            _ => {}
+        }
+    }
+    Some(args)
+}
+
+// Collects `:setlocal` arguments from modelines.
+pub(super) fn modeline(buffer: &Buffer) -> Vec<String> {
+    let count = buffer.count_lines();
+    let mut args = Vec::new();
+    let tail = max(MODELINES, count.saturating_sub(MODELINES));
+    for row in (0..min(MODELINES, count)).chain(tail..count) {
+        let line = buffer.line(row).as_str().to_string();
+        // Emacs only looks at the first line, or the second after a shebang.
+        if row < 2 {
+            if let Some(a) = emacs_modeline(&line) {
+                args.extend(a);
+            }
+        }
+        if let Some(a) = vim_modeline(&line) {
+            args.extend(a);
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_text(path: Option<&str>, text: &str) -> Option<String> {
+        detect(path, &Buffer::from(text))
+    }
+
+    #[test]
+    fn detects_from_the_file_name() {
+        assert_eq!(
+            detect_text(Some("src/Makefile"), ""),
+            Some("make".to_string())
+        );
+        assert_eq!(
+            detect_text(Some("Cargo.lock"), ""),
+            Some("toml".to_string())
+        );
+        assert_eq!(detect_text(Some("main.rs"), ""), Some("rust".to_string()));
+        assert_eq!(detect_text(Some("notes"), ""), None);
+    }
+
+    #[test]
+    fn detects_from_the_shebang() {
+        let node = "#!/usr/bin/env -S node --harmony\n";
+        assert_eq!(
+            detect_text(Some("run"), node),
+            Some("javascript".to_string())
+        );
+        let python = "#!/usr/bin/python3.11\n";
+        assert_eq!(detect_text(None, python), Some("python".to_string()));
+        assert_eq!(detect_text(None, "#!/usr/bin/env -S\n"), None);
+    }
+
+    #[test]
+    fn reads_vim_modelines() {
+        let buffer = Buffer::from("# vim: set ft=python ts=4: trailing\nx\n");
+        assert_eq!(modeline(&buffer), vec!["ft=python", "ts=4"]);
+        let buffer = Buffer::from("x\n// vim: ts=2:sw=2\n");
+        assert_eq!(modeline(&buffer), vec!["ts=2", "sw=2"]);
+        // `vim:` has to start a word.
+        assert!(modeline(&Buffer::from("novim: ts=2\n")).is_empty());
+    }
+
+    #[test]
+    fn reads_emacs_modelines() {
+        let buffer = Buffer::from("// -*- mode: c++; tab-width: 4; indent-tabs-mode: nil -*-\n");
+        assert_eq!(
+            modeline(&buffer),
+            vec!["filetype=c", "tabstop=4", "expandtab"]
+        );
+        let buffer = Buffer::from("#!/bin/sh\n# -*- shell-script -*-\n");
+        assert_eq!(modeline(&buffer), vec!["filetype=sh"]);
+        // Only the first two lines count.
+        assert!(modeline(&Buffer::from("\n\n# -*- python -*-\n")).is_empty());
+    }
+
+    #[test]
+    fn aliases_name_known_languages() {
+        for (_, filetype) in ALIASES {
+            assert!(
+                LANGUAGES.iter().any(|def| def.name == *filetype),
+                "{}",
+                filetype
+            );
+        }
+    }
+}
@@ -0,0 +1,11 @@
+use crate::selection::Selection;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum FoldKind {
+    Create(Selection),
+    Open,
+    Close,
+    Toggle,
+    OpenAll,
+    CloseAll,
+}
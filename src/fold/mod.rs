@@ -0,0 +1,7 @@
+mod action;
+mod rows;
+mod store;
+
+pub(crate) use action::FoldKind;
+pub(crate) use rows::{closed, edit, line, lines, next_line, prev_line, scroll, Fold};
+pub(super) use store::FoldStore;
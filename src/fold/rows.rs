@@ -0,0 +1,236 @@
+use core::cmp::{max, Reverse};
+use core::ops::Range;
+
+// When closed, the rows show as one summary line.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Fold {
+    pub(crate) rows: Range<usize>,
+    pub(crate) closed: bool,
+}
+
+// The closed folds that are not inside another closed one, in order.
+pub(crate) fn closed(folds: &[Fold]) -> Vec<Range<usize>> {
+    let mut rows: Vec<_> = folds
+        .iter()
+        .filter(|fold| fold.closed)
+        .map(|fold| fold.rows.clone())
+        .collect();
+    rows.sort_by_key(|r| (r.start, Reverse(r.end)));
+
+    let mut result: Vec<Range<usize>> = Vec::new();
+    for r in rows {
+        match result.last_mut() {
+            Some(last) if r.start < last.end => last.end = max(last.end, r.end),
+            _ => result.push(r),
+        }
+    }
+    result
+}
+
+// The rows of the line that `row` shows on.
+pub(crate) fn line(closed: &[Range<usize>], row: usize) -> Range<usize> {
+    closed
+        .iter()
+        .find(|r| r.contains(&row))
+        .cloned()
+        .unwrap_or(row..row + 1)
+}
+
+// The start of the line `count` lines above that of `row`, or of the first one.
+pub(crate) fn prev_line(closed: &[Range<usize>], row: usize, count: usize) -> usize {
+    let mut start = line(closed, row).start;
+    for _ in 0..count {
+        match start {
+            0 => break,
+            _ => start = line(closed, start - 1).start,
+        }
+    }
+    start
+}
+
+// The start of the line `count` lines below that of `row`, or of the last one.
+pub(crate) fn next_line(
+    closed: &[Range<usize>],
+    row: usize,
+    count: usize,
+    line_count: usize,
+) -> usize {
+    let mut rows = line(closed, row);
+    for _ in 0..count {
+        match rows.end < line_count {
+            true => rows = line(closed, rows.end),
+            false => break,
+        }
+    }
+    rows.start
+}
+
+pub(crate) fn is_closed(closed: &[Range<usize>], row: usize) -> bool {
+    closed.iter().any(|r| r.contains(&row))
+}
+
+// The screen rows the line of `row` takes, given those of the lines that are not folded.
+fn screen_rows(closed: &[Range<usize>], row: usize, height: &impl Fn(usize) -> usize) -> usize {
+    match is_closed(closed, row) {
+        true => 1,
+        false => height(row),
+    }
+}
+
+// The rows of the lines shown in `count` screen rows from the line of `row` on, and whether
+// they are a closed fold. The last one may not fit.
+pub(crate) fn lines(
+    closed: &[Range<usize>],
+    row: usize,
+    count: usize,
+    line_count: usize,
+    height: impl Fn(usize) -> usize,
+) -> Vec<(Range<usize>, bool)> {
+    let mut result = Vec::new();
+    let mut row = line(closed, row).start;
+    let mut used = 0;
+    while used < count && row < line_count {
+        let rows = line(closed, row);
+        used += screen_rows(closed, row, &height);
+        row = rows.end;
+        result.push((rows, is_closed(closed, row - 1)));
+    }
+    result
+}
+
+// Where rows are after the rows `start..old_end` are replaced with `start..new_end`.
+pub(crate) fn edit(folds: &mut Vec<Fold>, start: usize, old_end: usize, new_end: usize) {
+    let shift = |row: usize| match row {
+        row if row <= start => row,
+        row if row > old_end => row - old_end + new_end,
+        _ => new_end,
+    };
+    for fold in folds.iter_mut() {
+        fold.rows = shift(fold.rows.start)..shift(fold.rows.end);
+    }
+    folds.retain(|fold| !fold.rows.is_empty());
+}
+
+// The first row shown when starting from `offset` and the line of `row` has to be on the
+// screen, as far as it fits.
+pub(crate) fn scroll(
+    closed: &[Range<usize>],
+    offset: usize,
+    row: usize,
+    count: usize,
+    height: impl Fn(usize) -> usize,
+) -> usize {
+    let (offset, row) = (line(closed, offset).start, line(closed, row).start);
+    if row <= offset {
+        return row;
+    }
+    let mut used = 0;
+    let mut start = offset;
+    while start <= row && used <= count {
+        used += screen_rows(closed, start, &height);
+        start = line(closed, start).end;
+    }
+    if used <= count {
+        return offset;
+    }
+    let mut offset = row;
+    let mut used = screen_rows(closed, row, &height);
+    while offset > 0 {
+        let prev = prev_line(closed, offset, 1);
+        used += screen_rows(closed, prev, &height);
+        if used > count {
+            break;
+        }
+        offset = prev;
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fold(rows: Range<usize>, closed: bool) -> Fold {
+        Fold { rows, closed }
+    }
+
+    #[test]
+    fn merges_nested_closed_folds() {
+        let folds = [
+            fold(4..7, true),
+            fold(2..5, true),
+            fold(3..4, true),
+            fold(7..9, false),
+            fold(9..12, true),
+        ];
+        assert_eq!(closed(&folds), vec![2..7, 9..12]);
+    }
+
+    #[test]
+    fn moves_over_closed_folds() {
+        let closed = [2..4, 6..9];
+        assert_eq!(line(&closed, 3), 2..4);
+        assert_eq!(line(&closed, 5), 5..6);
+
+        assert_eq!(next_line(&closed, 0, 2, 12), 2);
+        assert_eq!(next_line(&closed, 0, 3, 12), 4);
+        assert_eq!(next_line(&closed, 7, 1, 12), 9);
+        assert_eq!(next_line(&closed, 0, usize::MAX, 12), 11);
+        let whole = [Range { start: 0, end: 3 }];
+        assert_eq!(next_line(&whole, 1, usize::MAX, 3), 0);
+
+        assert_eq!(prev_line(&closed, 11, 2), 9);
+        assert_eq!(prev_line(&closed, 11, 3), 6);
+        assert_eq!(prev_line(&closed, 3, 1), 1);
+        assert_eq!(prev_line(&closed, 11, usize::MAX), 0);
+    }
+
+    #[test]
+    fn lists_the_lines_that_fit() {
+        let closed = [2..4, 6..9];
+        let one = |_| 1;
+        let lines_from_3 = vec![(2..4, true), (4..5, false), (5..6, false), (6..9, true)];
+        assert_eq!(lines(&closed, 3, 4, 12, one), lines_from_3);
+        assert_eq!(
+            lines(&[], 10, 5, 12, one),
+            vec![(10..11, false), (11..12, false)]
+        );
+
+        // The last line shows even when it does not fit.
+        let wrapped = |row| if row == 4 { 3 } else { 1 };
+        let expected = vec![(2..4, true), (4..5, false)];
+        assert_eq!(lines(&closed, 3, 3, 12, wrapped), expected);
+        // Closed folds take one row however long their lines are.
+        let long = |_| 10;
+        assert_eq!(lines(&closed, 2, 1, 12, long), vec![(2..4, true)]);
+    }
+
+    #[test]
+    fn shifts_folds_over_edits() {
+        let mut folds = vec![fold(2..4, true), fold(6..9, false), fold(3..8, true)];
+        // Rows 3..5 become 3..4.
+        edit(&mut folds, 3, 5, 4);
+        assert_eq!(
+            folds,
+            vec![fold(2..4, true), fold(5..8, false), fold(3..7, true)]
+        );
+
+        // A fold whose rows are all removed goes away.
+        let mut folds = vec![fold(4..6, true)];
+        edit(&mut folds, 3, 7, 3);
+        assert!(folds.is_empty());
+    }
+
+    #[test]
+    fn scrolls_as_little_as_possible() {
+        let one = |_| 1;
+        assert_eq!(scroll(&[], 4, 2, 5, one), 2);
+        assert_eq!(scroll(&[], 4, 6, 5, one), 4);
+        assert_eq!(scroll(&[], 0, 10, 5, one), 6);
+
+        let wrapped = |row| if row == 3 { 4 } else { 1 };
+        assert_eq!(scroll(&[], 0, 3, 5, wrapped), 2);
+        let fold = [Range { start: 2, end: 8 }];
+        assert_eq!(scroll(&fold, 0, 9, 3, one), 2);
+    }
+}
@@ -0,0 +1,130 @@
+use super::action::FoldKind;
+use super::rows::Fold;
+use crate::action::ActionKind;
+use crate::mode::Mode;
+use crate::selection::{Selection, SelectionKind};
+use crate::store::{RootStore, Store};
+use core::cmp::max;
+
+pub(crate) struct FoldStore<'a> {
+    root: &'a mut RootStore,
+}
+
+impl<'a> Store for FoldStore<'a> {
+    fn root(&self) -> &RootStore {
+        self.root
+    }
+
+    fn root_mut(&mut self) -> &mut RootStore {
+        self.root
+    }
+}
+
+impl<'a> FoldStore<'a> {
+    pub(crate) fn new(root: &'a mut RootStore) -> Self {
+        Self { root }
+    }
+
+    // The folds made so far along with the `folds.scm` ones, which start out open.
+    fn all(&mut self) -> Vec<Fold> {
+        let buffer = self.state().buffer.clone();
+        let mut folds = self.state().folds.clone();
+        for rows in self.highlighter_mut().folds(&buffer) {
+            if !folds.iter().any(|fold| fold.rows == rows) {
+                folds.push(Fold {
+                    rows,
+                    closed: false,
+                });
+            }
+        }
+        folds
+    }
+
+    fn not_found(&mut self) {
+        let message = "E490: No fold found".to_string();
+        self.root_mut().action(ActionKind::Fail(message).once());
+    }
+
+    fn create(&mut self, selection: &Selection) {
+        let range = self.root_mut().measure_selection(selection.clone());
+        let buffer = &self.state().buffer;
+        let start = buffer.0.char_to_line(range.start);
+        let end = buffer.0.char_to_line(max(range.start + 1, range.end) - 1) + 1;
+        self.state_mut().folds.push(Fold {
+            rows: start..end,
+            closed: true,
+        });
+        if selection.kind == SelectionKind::Visual {
+            self.state_mut().mode = Mode::Normal(String::new());
+        }
+        self.state_mut().cursor.row = start;
+    }
+
+    // Opens the outermost closed fold at the cursor, the one that shows.
+    fn open(&mut self) {
+        let row = self.state().cursor.row;
+        let folds = &mut self.state_mut().folds;
+        let outermost = folds
+            .iter_mut()
+            .filter(|fold| fold.closed && fold.rows.contains(&row))
+            .max_by_key(|fold| fold.rows.len());
+        match outermost {
+            Some(fold) => fold.closed = false,
+            None => self.not_found(),
+        }
+    }
+
+    // Closes the innermost open fold at the cursor.
+    fn close(&mut self) {
+        let row = self.state().cursor.row;
+        let mut folds = self.all();
+        let innermost = folds
+            .iter_mut()
+            .filter(|fold| !fold.closed && fold.rows.contains(&row))
+            .min_by_key(|fold| fold.rows.len());
+        match innermost {
+            Some(fold) => fold.closed = true,
+            None => return self.not_found(),
+        }
+        self.state_mut().folds = folds;
+    }
+
+    fn toggle(&mut self) {
+        let row = self.state().cursor.row;
+        let closed = self
+            .state()
+            .folds
+            .iter()
+            .any(|fold| fold.closed && fold.rows.contains(&row));
+        match closed {
+            true => self.open(),
+            false => self.close(),
+        }
+    }
+
+    fn open_all(&mut self) {
+        for fold in &mut self.state_mut().folds {
+            fold.closed = false;
+        }
+    }
+
+    fn close_all(&mut self) {
+        let mut folds = self.all();
+        for fold in &mut folds {
+            fold.closed = true;
+        }
+        self.state_mut().folds = folds;
+    }
+
+    pub(crate) fn action(&mut self, fold: FoldKind) {
+        use FoldKind::*;
+        match &fold {
+            Create(selection) => self.create(selection),
+            Open => self.open(),
+            Close => self.close(),
+            Toggle => self.toggle(),
+            OpenAll => self.open_all(),
+            CloseAll => self.close_all(),
+        }
+    }
+}
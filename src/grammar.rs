@@ -0,0 +1,270 @@
+use anyhow::{anyhow, Result};
+use dirs::home_dir;
+use libloading::{Library, Symbol};
+use once_cell::sync::Lazy;
+use serde_derive::Deserialize;
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tree_sitter::{Language, Parser, Query};
+
+use crate::language;
+
+#[derive(Clone, Debug)]
+pub(super) enum Origin {
+    Builtin,
+    Dynamic(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Grammar {
+    pub(super) name: String,
+    pub(super) language: Language,
+    pub(super) highlights: Cow<'static, str>,
+    pub(super) injections: Cow<'static, str>,
+    pub(super) locals: Cow<'static, str>,
+    pub(super) textobjects: Cow<'static, str>,
+    pub(super) folds: Cow<'static, str>,
+    pub(super) tags: Cow<'static, str>,
+    // The files a dynamic grammar is used for, from its `grammar.toml`.
+    pub(super) extensions: Vec<String>,
+    pub(super) file_names: Vec<String>,
+    origin: Origin,
+}
+
+// `grammar.toml` next to a library: `extensions = ["lua"]` and `file_names = [".luacheckrc"]`.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct GrammarConfig {
+    extensions: Vec<String>,
+    file_names: Vec<String>,
+}
+
+// Grammars under `~/.config/neu/grammars/<lang>/`, along with the errors hit while loading
+// them. Libraries stay loaded until the process exits.
+static DYNAMIC: Lazy<(Vec<Grammar>, Vec<String>)> = Lazy::new(|| {
+    let dir = match home_dir() {
+        Some(home) => home.join(".config/neu/grammars"),
+        None => return (Vec::new(), Vec::new()),
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return (Vec::new(), Vec::new()),
+    };
+
+    let mut grammars = Vec::new();
+    let mut errors = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        match load_dir(&path) {
+            Ok(grammar) => {
+                errors.extend(grammar.check());
+                grammars.push(grammar);
+            }
+            Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    grammars.sort_by(|a, b| a.name.cmp(&b.name));
+    (grammars, errors)
+});
+
+fn read_query(dir: &Path, name: &str) -> Option<Cow<'static, str>> {
+    fs::read_to_string(dir.join(name)).ok().map(Cow::Owned)
+}
+
+fn find_library(dir: &Path) -> Option<PathBuf> {
+    let entries = fs::read_dir(dir).ok()?;
+    entries.flatten().map(|entry| entry.path()).find(|path| {
+        let extension = path.extension().and_then(|e| e.to_str());
+        matches!(extension, Some("so") | Some("dylib") | Some("dll"))
+    })
+}
+
+// Loads `tree_sitter_<lang>` from the shared library in `dir`. Without a library, the
+// query files there replace those of the built-in grammar.
+fn load_dir(dir: &Path) -> Result<Grammar> {
+    let name = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("invalid directory name"))?
+        .to_string();
+    let builtin = builtin(&name);
+    let config: GrammarConfig = match fs::read_to_string(dir.join("grammar.toml")) {
+        Ok(s) => toml::from_str(&s).map_err(|e| anyhow!("grammar.toml: {}", e))?,
+        Err(_) => GrammarConfig::default(),
+    };
+
+    let (language, origin) = match find_library(dir) {
+        Some(path) => {
+            let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+            let language = unsafe {
+                let library = Library::new(&path)?;
+                let f: Symbol<unsafe extern "C" fn() -> Language> =
+                    library.get(symbol.as_bytes())?;
+                let language = f();
+                // The language points into the library.
+                std::mem::forget(library);
+                language
+            };
+            Parser::new().set_language(language)?;
+            (language, Origin::Dynamic(path))
+        }
+        None => match &builtin {
+            Some(builtin) => (builtin.language, Origin::Dynamic(dir.to_path_buf())),
+            None => return Err(anyhow!("no grammar library")),
+        },
+    };
+
+    let query = |file, builtin: Option<&Cow<'static, str>>| {
+        read_query(dir, file)
+            .or_else(|| builtin.cloned())
+            .unwrap_or_default()
+    };
+    Ok(Grammar {
+        highlights: query("highlights.scm", builtin.as_ref().map(|g| &g.highlights)),
+        injections: query("injections.scm", builtin.as_ref().map(|g| &g.injections)),
+        locals: query("locals.scm", builtin.as_ref().map(|g| &g.locals)),
+        textobjects: query("textobjects.scm", builtin.as_ref().map(|g| &g.textobjects)),
+        folds: query("folds.scm", builtin.as_ref().map(|g| &g.folds)),
+        tags: query("tags.scm", builtin.as_ref().map(|g| &g.tags)),
+        extensions: config.extensions,
+        file_names: config.file_names,
+        name,
+        language,
+        origin,
+    })
+}
+
+fn builtin(filetype: &str) -> Option<Grammar> {
+    let def = language::find(filetype)?;
+    Some(Grammar {
+        name: def.name.to_string(),
+        language: (def.grammar?)(),
+        highlights: Cow::Borrowed(def.highlights),
+        injections: Cow::Borrowed(def.injections),
+        locals: Cow::Borrowed(def.locals),
+        textobjects: Cow::Borrowed(def.textobjects),
+        folds: Cow::Borrowed(def.folds),
+        tags: Cow::Borrowed(def.tags),
+        extensions: Vec::new(),
+        file_names: Vec::new(),
+        origin: Origin::Builtin,
+    })
+}
+
+// Grammars loaded at runtime win over the built-in ones.
+pub(super) fn get(filetype: &str) -> Option<Grammar> {
+    DYNAMIC
+        .0
+        .iter()
+        .find(|grammar| grammar.name == filetype)
+        .cloned()
+        .or_else(|| builtin(filetype))
+}
+
+// The name of the first grammar loaded at runtime that `f` holds for.
+pub(super) fn find_dynamic(f: impl Fn(&Grammar) -> bool) -> Option<String> {
+    DYNAMIC
+        .0
+        .iter()
+        .find(|grammar| f(grammar))
+        .map(|grammar| grammar.name.clone())
+}
+
+pub(super) fn load_errors() -> &'static [String] {
+    &DYNAMIC.1
+}
+
+impl Grammar {
+    pub(super) fn queries(&self) -> [(&'static str, &str); 6] {
+        [
+            ("highlights", &self.highlights),
+            ("injections", &self.injections),
+            ("locals", &self.locals),
+            ("textobjects", &self.textobjects),
+            ("folds", &self.folds),
+            ("tags", &self.tags),
+        ]
+    }
+
+    pub(super) fn query(&self, kind: &str, source: &str) -> Result<Query> {
+        Query::new(self.language, source).map_err(|e| anyhow!("{} {}.scm: {}", self.name, kind, e))
+    }
+
+    fn check(&self) -> Vec<String> {
+        self.queries()
+            .iter()
+            .filter(|(_, source)| !source.is_empty())
+            .filter_map(|(kind, source)| self.query(kind, source).err())
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    // `python (~/.config/neu/grammars/python/python.so: highlights, locals)`
+    pub(super) fn describe(&self) -> String {
+        let queries: Vec<_> = self
+            .queries()
+            .iter()
+            .filter(|(_, source)| !source.is_empty())
+            .map(|(kind, _)| *kind)
+            .collect();
+        let origin = match &self.origin {
+            Origin::Builtin => "built-in".to_string(),
+            Origin::Dynamic(path) => path.display().to_string(),
+        };
+        format!("{} ({}: {})", self.name, origin, queries.join(", "))
+    }
+}
+
+// Every grammar that can be used, as `:grammars` lists them.
+pub(super) fn list() -> Vec<String> {
+    let mut names: Vec<_> = language::LANGUAGES
+        .iter()
+        .filter(|def| def.grammar.is_some())
+        .map(|def| def.name.to_string())
+        .collect();
+    names.extend(DYNAMIC.0.iter().map(|grammar| grammar.name.clone()));
+    names.sort();
+    names.dedup();
+    names
+        .iter()
+        .filter_map(|name| get(name))
+        .map(|grammar| grammar.describe())
+        .chain(load_errors().iter().cloned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiles_the_builtin_queries() {
+        for def in language::LANGUAGES {
+            let grammar = match builtin(def.name) {
+                Some(grammar) => grammar,
+                None => {
+                    // Queries without a grammar would never be used.
+                    let queries = [
+                        def.highlights,
+                        def.injections,
+                        def.locals,
+                        def.textobjects,
+                        def.folds,
+                        def.tags,
+                    ];
+                    assert!(queries.iter().all(|q| q.is_empty()), "{}", def.name);
+                    continue;
+                }
+            };
+            for (kind, source) in grammar.queries() {
+                if let Err(e) = grammar.query(kind, source) {
+                    panic!("{}", e);
+                }
+            }
+        }
+    }
+}
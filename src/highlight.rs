@@ -1,158 +1,933 @@
+use crate::backend::Style;
 use crate::buffer::Buffer;
-use crate::compute::{LineRange, Reactor};
-use crate::language::Language;
-use tree_sitter::{InputEdit, Language as TSLanguage, Node, Parser, Point, Query, Tree};
-
-fn get_language_info(lang: &Language) -> Option<(TSLanguage, Query)> {
-    use Language::*;
-    match lang {
-        Rust => {
-            let lang = tree_sitter_rust::language();
-            let query = tree_sitter::Query::new(lang, tree_sitter_rust::HIGHLIGHT_QUERY).unwrap();
-            Some((lang, query))
-        }
-        JavaScript => {
-            let lang = tree_sitter_javascript::language();
-            let query =
-                tree_sitter::Query::new(lang, tree_sitter_javascript::HIGHLIGHT_QUERY).unwrap();
-            Some((lang, query))
-        }
-        _ => None,
-    }
-}
-
-fn get_color(syntax_kind: &str) -> String {
-    use termion::color;
-    match syntax_kind {
-        "keyword" => format!("{}", color::Fg(color::Magenta)),
-        "attribute" => format!("{}", color::Fg(color::Red)),
-        "constant.builtin" => format!("{}", color::Fg(color::Red)),
-        "property" => format!("{}", color::Fg(color::Red)),
-        "function.macro" => format!("{}", color::Fg(color::Red)),
-        "function" => format!("{}", color::Fg(color::Blue)),
-        "function.method" => format!("{}", color::Fg(color::Blue)),
-        "type.builtin" => format!("{}", color::Fg(color::Yellow)),
-        "type" => format!("{}", color::Fg(color::Yellow)),
-        "string" => format!("{}", color::Fg(color::Green)),
-        "variable" => format!("{}", color::Fg(color::White)),
-        "variable.parameter" => format!("{}", color::Fg(color::Red)),
-        "variable.builtin" => format!("{}", color::Fg(color::Cyan)),
-        "punctuation.bracket" => format!("{}", color::Fg(color::LightCyan)),
-        "punctuation.delimiter" => format!("{}", color::Fg(color::LightCyan)),
-        "operator" => format!("{}", color::Fg(color::White)),
-        "label" => format!("{}", color::Fg(color::Yellow)),
-        "comment" => format!("{}", color::Fg(color::LightBlack)),
-        "escape" => format!("{}", color::Fg(color::Magenta)),
-        "number" => format!("{}", color::Fg(color::Magenta)),
-        s => {
-            tracing::debug!("{}", s);
-            format!("{}", color::Fg(color::Red))
+use crate::compute::{Cursor, LineRange, Reactor};
+use crate::diagnostic::{self, Diagnostic};
+use crate::filetype;
+use crate::grammar;
+use crate::inspect::{self, TreeLines, TreeView};
+use crate::locals::{self, Symbol};
+use crate::options::Options;
+use crate::syntax;
+use crate::tags::{self, Tag};
+use crate::theme::Theme;
+use core::cmp::{max, min};
+use core::ops::Range as StdRange;
+use flume::{Receiver, Sender};
+use hashbrown::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tree_sitter::{
+    InputEdit, Language as TSLanguage, Node, Parser, Point, Query, QueryCursor, QueryPredicateArg,
+    Range, Tree,
+};
+
+// Injections inside injections stop at this depth.
+const MAX_INJECTION_DEPTH: usize = 4;
+
+// Parses taking longer give up, leaving the last good tree in place.
+const PARSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A grammar with the queries used to highlight it.
+struct Layer {
+    language: TSLanguage,
+    highlights: Query,
+    injections: Option<Query>,
+    locals: Option<Query>,
+    textobjects: Option<Query>,
+    folds: Option<Query>,
+    tags: Option<Query>,
+}
+
+impl Layer {
+    fn new(filetype: &str) -> Option<Arc<Self>> {
+        let grammar = grammar::get(filetype)?;
+        let query = |kind, source: &str| match grammar.query(kind, source) {
+            Ok(query) => Some(query),
+            Err(e) => {
+                tracing::error!("{}", e);
+                None
+            }
+        };
+        let highlights = query("highlights", &grammar.highlights)?;
+        let optional = |kind, source: &str| {
+            Some(source)
+                .filter(|source| !source.is_empty())
+                .and_then(|source| query(kind, source))
+        };
+        Some(Arc::new(Self {
+            language: grammar.language,
+            highlights,
+            injections: optional("injections", &grammar.injections),
+            locals: optional("locals", &grammar.locals),
+            textobjects: optional("textobjects", &grammar.textobjects),
+            folds: optional("folds", &grammar.folds),
+            tags: optional("tags", &grammar.tags),
+        }))
+    }
+}
+
+// Ranges of the buffer to parse with the grammar of another language.
+struct Injection {
+    language: String,
+    ranges: Vec<Range>,
+}
+
+fn parse(parser: &mut Parser, b: &Buffer, old_tree: Option<&Tree>) -> Option<Tree> {
+    parser.parse_with(
+        &mut |byte, _| {
+            if let Some((s, i, _, _)) = b.get_chunk_at_byte(byte) {
+                return &s.as_bytes()[byte - i..];
+            }
+            &[]
+        },
+        old_tree,
+    )
+}
+
+fn text(b: &Buffer, bytes: StdRange<usize>) -> String {
+    let bytes: Vec<_> = b.bytes_at(bytes.start).take(bytes.len()).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn point(b: &Buffer, byte: usize) -> Point {
+    b.get_point_by_byte(byte)
+}
+
+// Moves the start and the end of `node` by the rows and columns of `#offset!`.
+fn offset_range(b: &Buffer, node: Node, offset: [isize; 4]) -> Range {
+    let shift = |p: Point, row: isize, column: isize| {
+        let p = Point::new(
+            (p.row as isize + row).max(0) as usize,
+            (p.column as isize + column).max(0) as usize,
+        );
+        b.get_byte_by_point(p)
+    };
+    let start_byte = shift(node.start_position(), offset[0], offset[1]);
+    let end_byte = shift(node.end_position(), offset[2], offset[3]).max(start_byte);
+    Range {
+        start_byte,
+        end_byte,
+        start_point: point(b, start_byte),
+        end_point: point(b, end_byte),
+    }
+}
+
+// The parts of `range` not covered by the children of `node`.
+fn exclude_children(node: Node, range: Range) -> Vec<Range> {
+    let mut ranges = Vec::new();
+    let (mut start_byte, mut start_point) = (range.start_byte, range.start_point);
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.start_byte() > start_byte && child.start_byte() <= range.end_byte {
+            ranges.push(Range {
+                start_byte,
+                end_byte: child.start_byte(),
+                start_point,
+                end_point: child.start_position(),
+            });
+        }
+        if child.end_byte() > start_byte {
+            start_byte = child.end_byte();
+            start_point = child.end_position();
         }
     }
+    if range.end_byte > start_byte {
+        ranges.push(Range {
+            start_byte,
+            end_byte: range.end_byte,
+            start_point,
+            end_point: range.end_point,
+        });
+    }
+    ranges
 }
 
-pub(super) struct Highlighter {
+// Runs the injections query of `layer` over `root`. Ranges of `injection.combined`
+// patterns are parsed together as one document.
+fn injections(
+    layer: &Layer,
+    root: Node,
+    b: &Buffer,
+    point_range: StdRange<Point>,
+) -> Vec<Injection> {
+    let query = match &layer.injections {
+        Some(query) => query,
+        None => return vec![],
+    };
+
+    let mut cursor = QueryCursor::new();
+    cursor.set_point_range(point_range);
+    let matches = cursor.matches(query, root, |node: Node| {
+        let start = node.start_byte();
+        if let Some((chunks, _, _, _)) = b.get_chunks_at_byte(start) {
+            return chunks.map(|s| s.as_bytes());
+        }
+        unreachable!()
+    });
+
+    let mut result: Vec<Injection> = Vec::new();
+    let mut combined = HashMap::new();
+    for matched in matches {
+        let mut language = None;
+        let mut content = None;
+        for capture in matched.captures {
+            match query.capture_names()[capture.index as usize].as_str() {
+                "injection.language" | "language" => {
+                    language = Some(text(b, capture.node.byte_range()))
+                }
+                "injection.content" | "content" => content = Some(capture.node),
+                _ => {}
+            }
+        }
+
+        let mut include_children = false;
+        let mut is_combined = false;
+        for property in query.property_settings(matched.pattern_index) {
+            match (&*property.key, &property.value) {
+                ("injection.language" | "language", Some(value)) => {
+                    language = Some(value.to_string())
+                }
+                ("injection.include-children", _) => include_children = true,
+                ("injection.combined", _) => is_combined = true,
+                _ => {}
+            }
+        }
+
+        let (node, language) = match (content, language) {
+            (Some(node), Some(language)) => (node, filetype::from_name(language.trim())),
+            _ => continue,
+        };
+
+        let mut offset = [0; 4];
+        for predicate in query.general_predicates(matched.pattern_index) {
+            if &*predicate.operator != "offset!" {
+                continue;
+            }
+            let numbers = predicate.args.iter().filter_map(|arg| match arg {
+                QueryPredicateArg::String(n) => n.parse().ok(),
+                _ => None,
+            });
+            for (o, n) in offset.iter_mut().zip(numbers) {
+                *o = n;
+            }
+        }
+
+        let range = offset_range(b, node, offset);
+        let ranges = match include_children {
+            true => vec![range],
+            false => exclude_children(node, range),
+        };
+        if ranges.is_empty() {
+            continue;
+        }
+
+        if is_combined {
+            let key = (matched.pattern_index, language.clone());
+            if let Some(&i) = combined.get(&key) {
+                let injection: &mut Injection = &mut result[i];
+                injection.ranges.extend(ranges);
+                continue;
+            }
+            combined.insert(key, result.len());
+        }
+        result.push(Injection { language, ranges });
+    }
+    result
+}
+
+// Highlights of an injected language are clipped to its `included` ranges, as its nodes
+// may span the text in between.
+fn captures(
+    layer: &Layer,
+    root: Node,
+    b: &Buffer,
+    theme: &Theme,
+    line_range: &LineRange,
+    included: Option<&[Range]>,
+    result: &mut Vec<(Point, String, Style)>,
+) {
+    let mut c = QueryCursor::new();
+    c.set_point_range(Point::new(line_range.0.start, 0)..Point::new(line_range.0.end, 0));
+
+    let query = &layer.highlights;
+    let matches = c.captures(query, root, |node: Node| {
+        let start = node.start_byte();
+        if let Some((chunks, _, _, _)) = b.get_chunks_at_byte(start) {
+            return chunks.map(|s| s.as_bytes());
+        }
+        unreachable!()
+    });
+
+    for matched in matches {
+        for capture in matched.0.captures {
+            let position = capture.node.start_position();
+            if line_range.0.start > position.row {
+                break;
+            }
+
+            let syntax_kind = &query.capture_names()[capture.index as usize];
+            let style = theme.get(syntax_kind);
+            if style == Style::default() {
+                continue;
+            }
+
+            let bytes = capture.node.byte_range();
+            let included = match included {
+                Some(included) => included.iter().map(|r| r.start_byte..r.end_byte).collect(),
+                None => vec![bytes.clone()],
+            };
+            for r in included {
+                let clipped = max(r.start, bytes.start)..min(r.end, bytes.end);
+                if clipped.is_empty() {
+                    continue;
+                }
+                let position = match clipped.start == bytes.start {
+                    true => position,
+                    false => point(b, clipped.start),
+                };
+                result.push((position, text(b, clipped), style));
+            }
+        }
+    }
+}
+
+// The style the highlights query gives to the definition of `symbol`.
+fn definition_style(
+    layer: &Layer,
+    root: Node,
+    b: &Buffer,
+    theme: &Theme,
+    symbol: &Symbol,
+) -> Style {
+    let mut c = QueryCursor::new();
+    c.set_byte_range(symbol.definition.clone());
+    let query = &layer.highlights;
+    let matches = c.captures(query, root, |node: Node| {
+        let start = node.start_byte();
+        if let Some((chunks, _, _, _)) = b.get_chunks_at_byte(start) {
+            return chunks.map(|s| s.as_bytes());
+        }
+        unreachable!()
+    });
+
+    let mut style = Style::default();
+    for (matched, index) in matches {
+        let capture = matched.captures[index];
+        if capture.node.byte_range() == symbol.definition {
+            style = theme.get(&query.capture_names()[capture.index as usize]);
+        }
+    }
+    style
+}
+
+// References to locals are drawn in the style of their definition, and all occurrences
+// of the symbol under the cursor with `ui.reference`.
+fn local_captures(
+    layer: &Layer,
+    root: Node,
+    b: &Buffer,
+    theme: &Theme,
+    line_range: &LineRange,
+    cursor: usize,
+    result: &mut Vec<(Point, String, Style)>,
+) {
+    let query = match &layer.locals {
+        Some(query) => query,
+        None => return,
+    };
+    let view = b.0.line_to_byte(line_range.0.start)..b.0.line_to_byte(line_range.0.end);
+    let in_view = |r: &StdRange<usize>| r.start < view.end && view.start < r.end;
+    let reference_style = theme.get("ui.reference");
+
+    for symbol in locals::analyze(query, root, b, view.clone()) {
+        let under_cursor = symbol.contains(cursor);
+        if !under_cursor && !symbol.references.iter().any(in_view) {
+            continue;
+        }
+        let style = definition_style(layer, root, b, theme, &symbol);
+        let ranges: Vec<_> = match under_cursor {
+            true => symbol.occurrences().collect(),
+            false => symbol.references.clone(),
+        };
+        for r in ranges.into_iter().filter(in_view) {
+            let style = match under_cursor {
+                true => style.patch(reference_style),
+                false => style,
+            };
+            if style != Style::default() {
+                result.push((point(b, r.start), text(b, r), style));
+            }
+        }
+    }
+}
+
+// Moves `byte` and `point` by `edit`, like `Tree::edit` does with the nodes.
+fn edit_position(byte: &mut usize, point: &mut Point, edit: &InputEdit) {
+    if *byte >= edit.old_end_byte {
+        *byte = *byte - edit.old_end_byte + edit.new_end_byte;
+        if point.row == edit.old_end_position.row {
+            point.column =
+                point.column - edit.old_end_position.column + edit.new_end_position.column;
+        }
+        point.row = point.row - edit.old_end_position.row + edit.new_end_position.row;
+    } else if *byte > edit.start_byte {
+        *byte = edit.new_end_byte;
+        *point = edit.new_end_position;
+    }
+}
+
+// An injected language parsed in the background. The next parse of the same language at
+// the same place starts from its tree.
+#[derive(Clone)]
+struct InjectedTree {
+    language: String,
+    layer: Arc<Layer>,
+    ranges: Vec<Range>,
+    tree: Tree,
+}
+
+impl InjectedTree {
+    fn edit(&mut self, edit: &InputEdit) {
+        self.tree.edit(edit);
+        for range in &mut self.ranges {
+            edit_position(&mut range.start_byte, &mut range.start_point, edit);
+            edit_position(&mut range.end_byte, &mut range.end_point, edit);
+        }
+    }
+}
+
+// What parses injected languages, which goes to the background with the main parser.
+struct Injector {
     parser: Parser,
-    query: Option<Query>,
+    // Layers of injected languages, `None` for those without a grammar.
+    layers: HashMap<String, Option<Arc<Layer>>>,
+}
+
+impl Injector {
+    fn layer(&mut self, language: &str) -> Option<Arc<Layer>> {
+        self.layers
+            .entry(language.to_string())
+            .or_insert_with(|| Layer::new(language))
+            .clone()
+    }
+
+    // Parses the injections of `layer` found in `root`, and those nested in them, each
+    // before the ones nested in it.
+    fn parse(
+        &mut self,
+        layer: &Layer,
+        root: Node,
+        b: &Buffer,
+        old: &[InjectedTree],
+        depth: usize,
+        result: &mut Vec<InjectedTree>,
+    ) {
+        if depth >= MAX_INJECTION_DEPTH {
+            return;
+        }
+        let whole = Point::new(0, 0)..Point::new(usize::MAX, usize::MAX);
+        for injection in injections(layer, root, b, whole) {
+            let injected = match self.layer(&injection.language) {
+                Some(injected) => injected,
+                None => continue,
+            };
+            self.parser.set_language(injected.language).unwrap();
+            if let Err(e) = self.parser.set_included_ranges(&injection.ranges) {
+                tracing::error!("{}: {:?}", injection.language, e);
+                continue;
+            }
+            let old_tree = old.iter().find(|old| {
+                old.language == injection.language
+                    && old.ranges[0].start_byte == injection.ranges[0].start_byte
+            });
+            let tree = match parse(&mut self.parser, b, old_tree.map(|old| &old.tree)) {
+                Some(tree) => tree,
+                None => {
+                    self.parser.reset();
+                    continue;
+                }
+            };
+
+            let i = result.len();
+            result.push(InjectedTree {
+                language: injection.language,
+                layer: injected.clone(),
+                ranges: injection.ranges,
+                tree,
+            });
+            let root = result[i].tree.clone();
+            self.parse(&injected, root.root_node(), b, old, depth + 1, result);
+        }
+    }
+}
+
+// A tree parsed in the background with its injections, handed back to the store with the
+// parsers.
+pub(super) struct Parsed {
+    parser: Parser,
+    injector: Injector,
     tree: Option<Tree>,
+    injections: Vec<InjectedTree>,
+    generation: usize,
+    // The size of the parsed buffer.
+    len: usize,
+}
+
+pub(super) struct Highlighter {
+    // `None` while the parser is busy in the background.
+    parser: Option<Parser>,
+    layer: Option<Arc<Layer>>,
+    // The last good tree, kept up to date with `edit_tree` until a new one arrives.
+    tree: Option<Tree>,
+    // The buffer changed since `tree` was parsed.
+    dirty: bool,
+    // Bumped when `tree` is replaced, to drop the results of older parses.
+    generation: usize,
+    // Edits made while a parse runs, to be applied to its tree.
+    pending_edits: Vec<InputEdit>,
+    cancellation_flag: Arc<AtomicUsize>,
+    tx: Sender<Parsed>,
+    rx: Receiver<Parsed>,
+    // Away with `parser` while it is busy.
+    injector: Option<Injector>,
+    // The injected languages of `tree`, kept up to date with it.
+    injections: Vec<InjectedTree>,
+    // The `:InspectTree` lines of `tree`, dropped whenever it changes.
+    tree_lines: Option<TreeLines>,
+    // 'maxhighlight': larger buffers are neither parsed nor highlighted. 0 for no limit.
+    limit: usize,
+    // The size of the buffer whose parse timed out. Parsing is off until the buffer is
+    // smaller again, rather than timing out on every edit.
+    timed_out: Option<usize>,
+}
+
+impl Drop for Highlighter {
+    fn drop(&mut self) {
+        self.cancellation_flag.store(1, Ordering::Relaxed);
+    }
 }
 
 impl Highlighter {
-    pub(super) fn new(buffer: &Buffer, lang: &Language) -> Self {
+    pub(super) fn new(filetype: &str) -> Self {
         let mut parser = Parser::new();
+        parser.set_timeout_micros(PARSE_TIMEOUT.as_micros() as u64);
 
-        let mut query = None;
-        if let Some((ts_lang, q)) = get_language_info(lang) {
-            parser.set_language(ts_lang).unwrap();
-            query = Some(q);
+        let layer = Layer::new(filetype);
+        if let Some(layer) = &layer {
+            parser.set_language(layer.language).unwrap();
         }
 
-        let mut highlighter = Self {
-            parser,
-            query,
+        let mut injection_parser = Parser::new();
+        injection_parser.set_timeout_micros(PARSE_TIMEOUT.as_micros() as u64);
+
+        let (tx, rx) = flume::unbounded();
+        Self {
+            parser: Some(parser),
+            layer,
             tree: None,
-        };
-        highlighter.load_buffer(buffer);
-        highlighter
+            dirty: true,
+            generation: 0,
+            pending_edits: Vec::new(),
+            cancellation_flag: Arc::new(AtomicUsize::new(0)),
+            tx,
+            rx,
+            injector: Some(Injector {
+                parser: injection_parser,
+                layers: HashMap::new(),
+            }),
+            injections: Vec::new(),
+            tree_lines: None,
+            limit: 0,
+            timed_out: None,
+        }
+    }
+
+    pub(super) fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    // Whether `b` is small enough to be parsed.
+    fn enabled(&mut self, b: &Buffer) -> bool {
+        let len = b.0.len_bytes();
+        if self.timed_out.is_some_and(|timed_out| len < timed_out) {
+            self.timed_out = None;
+        }
+        self.timed_out.is_none() && (self.limit == 0 || len <= self.limit)
     }
 
     pub(super) fn set_tree(&mut self, tree: Tree) {
-        self.tree = Some(tree);
+        self.replace_tree(Some(tree));
+        self.dirty = true;
     }
 
     pub(super) fn tree(&self) -> Option<&Tree> {
         self.tree.as_ref()
     }
 
-    fn load_buffer(&mut self, b: &Buffer) {
-        if self.parser.language().is_none() {
+    // Drops the parse running in the background, if any.
+    fn replace_tree(&mut self, tree: Option<Tree>) {
+        self.cancellation_flag.store(1, Ordering::Relaxed);
+        self.generation += 1;
+        self.pending_edits.clear();
+        self.tree = tree;
+        self.tree_lines = None;
+        self.injections.clear();
+    }
+
+    fn is_parsing(&self) -> bool {
+        self.parser.is_none()
+    }
+
+    // Where the trees parsed in the background arrive.
+    pub(super) fn parsed(&self) -> Receiver<Parsed> {
+        self.rx.clone()
+    }
+
+    fn parse_in_background(&mut self, b: &Buffer) {
+        let (mut parser, mut injector) = match (self.parser.take(), self.injector.take()) {
+            (Some(parser), Some(injector)) => (parser, injector),
+            _ => return,
+        };
+        self.dirty = false;
+        self.cancellation_flag = Arc::new(AtomicUsize::new(0));
+        let flag = self.cancellation_flag.clone();
+        let buffer = b.clone();
+        let layer = self.layer.clone();
+        let old_tree = self.tree.clone();
+        let old_injections = self.injections.clone();
+        let generation = self.generation;
+        let len = b.0.len_bytes();
+        let tx = self.tx.clone();
+        smol::spawn(smol::unblock(move || {
+            // The flag outlives the parse, and is unset before the parsers are handed back.
+            unsafe { parser.set_cancellation_flag(Some(&flag)) };
+            unsafe { injector.parser.set_cancellation_flag(Some(&flag)) };
+            let tree = parse(&mut parser, &buffer, old_tree.as_ref());
+            let mut injections = Vec::new();
+            match (&tree, &layer) {
+                (Some(tree), Some(layer)) => {
+                    let root = tree.root_node();
+                    injector.parse(layer, root, &buffer, &old_injections, 0, &mut injections);
+                }
+                _ => parser.reset(),
+            }
+            unsafe { parser.set_cancellation_flag(None) };
+            unsafe { injector.parser.set_cancellation_flag(None) };
+            let _ = tx.send(Parsed {
+                parser,
+                injector,
+                tree,
+                injections,
+                generation,
+                len,
+            });
+        }))
+        .detach();
+    }
+
+    pub(super) fn receive(&mut self, parsed: Parsed) {
+        self.parser = Some(parsed.parser);
+        self.injector = Some(parsed.injector);
+        if parsed.generation != self.generation {
             return;
         }
-        self.tree = self.parser.parse_with(
-            &mut |byte, _| {
-                if let Some((s, i, _, _)) = b.get_chunk_at_byte(byte) {
-                    return &s.as_bytes()[byte - i..];
+        match parsed.tree {
+            Some(mut tree) => {
+                let mut injections = parsed.injections;
+                for edit in &self.pending_edits {
+                    tree.edit(edit);
+                    for injection in &mut injections {
+                        injection.edit(edit);
+                    }
                 }
-                &[]
-            },
-            self.tree.as_ref(),
-        )
+                self.tree = Some(tree);
+                self.tree_lines = None;
+                self.injections = injections;
+            }
+            None => {
+                tracing::warn!("parse timed out after {:?}", PARSE_TIMEOUT);
+                self.timed_out = Some(parsed.len);
+            }
+        }
+        self.dirty |= !self.pending_edits.is_empty();
+        self.pending_edits.clear();
     }
 
-    pub(super) fn edit_tree(&mut self, input: &InputEdit) {
-        if let Some(tree) = &mut self.tree {
-            tree.edit(&input);
+    // The tree of `b`, for commands that cannot do with an outdated one. This waits for
+    // the background parse rather than starting another one, and parses what changed
+    // while it ran the same way.
+    pub(super) fn current_tree(&mut self, b: &Buffer) -> Option<&Tree> {
+        self.layer.as_ref()?;
+        if !self.enabled(b) {
+            return None;
+        }
+        loop {
+            if self.dirty {
+                self.parse_in_background(b);
+            }
+            if !self.is_parsing() {
+                break;
+            }
+            match self.rx.recv() {
+                Ok(parsed) => self.receive(parsed),
+                Err(_) => break,
+            }
+        }
+        match self.timed_out {
+            Some(_) => None,
+            None => self.tree.as_ref(),
         }
     }
 
-    pub(super) fn update(&mut self, reactor: &mut Reactor) -> Vec<(Point, String)> {
-        if self.query.is_none() {
-            return vec![];
+    // Byte ranges of the `textobjects.scm` captures named one of `names`, in order.
+    pub(super) fn textobjects(&mut self, b: &Buffer, names: &[&str]) -> Vec<StdRange<usize>> {
+        let layer = match &self.layer {
+            Some(layer) if layer.textobjects.is_some() => layer.clone(),
+            _ => return vec![],
+        };
+        let query = layer.textobjects.as_ref().unwrap();
+        match self.current_tree(b) {
+            Some(tree) => syntax::captures(query, tree.root_node(), b, names),
+            None => vec![],
         }
+    }
 
-        let b = reactor.compute();
-        self.load_buffer(&b);
+    // Rows of the `folds.scm` captures spanning more than one line.
+    pub(super) fn folds(&mut self, b: &Buffer) -> Vec<StdRange<usize>> {
+        let layer = match &self.layer {
+            Some(layer) if layer.folds.is_some() => layer.clone(),
+            _ => return vec![],
+        };
+        let query = layer.folds.as_ref().unwrap();
+        let ranges = match self.current_tree(b) {
+            Some(tree) => syntax::captures(query, tree.root_node(), b, &["fold"]),
+            None => return vec![],
+        };
+        let mut rows: Vec<_> = ranges
+            .into_iter()
+            .map(|r| b.0.byte_to_line(r.start)..b.0.byte_to_line(r.end.saturating_sub(1)) + 1)
+            .filter(|rows| rows.len() > 1)
+            .collect();
+        rows.dedup();
+        rows
+    }
 
-        let mut c = tree_sitter::QueryCursor::new();
-        let line_range: LineRange = reactor.compute();
-        c.set_point_range(Point::new(line_range.0.start, 0)..Point::new(line_range.0.end, 0));
-        let syntax_tree = self.tree.as_mut().unwrap().root_node();
+    // Local symbols around the byte `at`, for `gd` and `:Rename`.
+    pub(super) fn symbols(&mut self, b: &Buffer, at: usize) -> Vec<Symbol> {
+        let layer = match &self.layer {
+            Some(layer) if layer.locals.is_some() => layer.clone(),
+            _ => return vec![],
+        };
+        let query = layer.locals.as_ref().unwrap();
+        match self.current_tree(b) {
+            Some(tree) => locals::analyze(query, tree.root_node(), b, at..at),
+            None => vec![],
+        }
+    }
+
+    // Definitions of the `tags.scm` query in the whole buffer, for `:symbols`.
+    pub(super) fn tags(&mut self, b: &Buffer) -> Vec<Tag> {
+        let layer = match &self.layer {
+            Some(layer) if layer.tags.is_some() => layer.clone(),
+            _ => return vec![],
+        };
+        let query = layer.tags.as_ref().unwrap();
+        match self.current_tree(b) {
+            Some(tree) => tags::find(query, tree.root_node(), b, 0..b.0.len_bytes()),
+            None => vec![],
+        }
+    }
+
+    // Definitions around the byte `at` in the last good tree, outermost first. This runs
+    // as the cursor moves, so it does not wait for a parse.
+    pub(super) fn tags_at(&self, b: &Buffer, at: usize) -> Vec<Tag> {
+        let (query, tree) = match (&self.layer, &self.tree) {
+            (Some(layer), Some(tree)) => match &layer.tags {
+                Some(query) => (query, tree),
+                None => return vec![],
+            },
+            _ => return vec![],
+        };
+        tags::find(query, tree.root_node(), b, at..at + 1)
+            .into_iter()
+            .filter(|tag| tag.definition.start <= at && at < tag.definition.end)
+            .collect()
+    }
+
+    // Syntax errors in the last good tree.
+    pub(super) fn diagnostics(&self, b: &Buffer) -> Vec<Diagnostic> {
+        match &self.tree {
+            Some(tree) => diagnostic::syntax_errors(tree, b),
+            None => vec![],
+        }
+    }
 
-        let query = self.query.as_mut().unwrap();
-        let matches = c.captures(query, syntax_tree, |node: Node| {
+    // The last good tree for `:InspectTree`, with the node at the byte `at` as the current one.
+    pub(super) fn tree_view(&mut self, at: usize) -> Option<TreeView> {
+        let tree = self.tree.as_ref()?;
+        let lines = self.tree_lines.get_or_insert_with(|| TreeLines::new(tree));
+        Some(lines.view(tree, at))
+    }
+
+    // The kinds of the nodes around the byte `at`, and the names of the highlight captures
+    // there in the order they are drawn, for `:Inspect`.
+    pub(super) fn inspect(&mut self, b: &Buffer, at: usize) -> Option<(Vec<String>, Vec<String>)> {
+        let layer = self.layer.clone()?;
+        let tree = self.current_tree(b)?;
+        let kinds = inspect::kinds_at(tree, at);
+
+        let mut c = QueryCursor::new();
+        c.set_byte_range(at..at + 1);
+        let query = &layer.highlights;
+        let matches = c.captures(query, tree.root_node(), |node: Node| {
             let start = node.start_byte();
             if let Some((chunks, _, _, _)) = b.get_chunks_at_byte(start) {
                 return chunks.map(|s| s.as_bytes());
             }
             unreachable!()
         });
+        let mut captures = Vec::new();
+        for (matched, index) in matches {
+            let capture = matched.captures[index];
+            let bytes = capture.node.byte_range();
+            let name = &query.capture_names()[capture.index as usize];
+            if bytes.start <= at && at < bytes.end && !captures.contains(name) {
+                captures.push(name.clone());
+            }
+        }
+        Some((kinds, captures))
+    }
 
-        let mut result = Vec::new();
-        for matched in matches {
-            for capture in matched.0.captures {
-                let start = capture.node.start_byte();
-                let position = capture.node.start_position();
-                if line_range.0.start > position.row {
-                    break;
-                }
+    pub(super) fn edit_tree(&mut self, input: &InputEdit) {
+        if let Some(tree) = &mut self.tree {
+            tree.edit(input);
+            self.tree_lines = None;
+        }
+        for injection in &mut self.injections {
+            injection.edit(input);
+        }
+        if self.is_parsing() {
+            self.pending_edits.push(*input);
+        }
+        self.dirty = true;
+    }
+
+    // Highlights of the visible lines in the last good tree. Those of injected languages
+    // come after the ones of the enclosing language, so that they are drawn over them.
+    pub(super) fn update(&mut self, reactor: &mut Reactor) -> Vec<(Point, String, Style)> {
+        let layer = match &self.layer {
+            Some(layer) => layer.clone(),
+            None => return vec![],
+        };
 
+        let b: Buffer = reactor.compute();
+        self.limit = reactor.compute::<Options>().number("maxhighlight");
+        if !self.enabled(&b) {
+            return vec![];
+        }
+        if self.dirty {
+            self.parse_in_background(&b);
+        }
+        let theme: Theme = reactor.compute();
+        let line_range: LineRange = reactor.compute();
+        let tree = match self.tree.clone() {
+            Some(tree) => tree,
+            None => return vec![],
+        };
 
-                let end = capture.node.end_byte();
-                let syntax_kind = &query.capture_names()[capture.index as usize];
+        let root = tree.root_node();
+        let cursor = reactor.compute::<Cursor>().0;
+        let cursor = b.get_byte_by_position(cursor);
 
-                let bytes: Vec<_> = b.bytes_at(start).take(end - start).collect();
-                let s = std::str::from_utf8(&bytes).unwrap();
-                result.push((
-                    position,
-                    format!(
-                        "{}{}{}",
-                        get_color(syntax_kind),
-                        s,
-                        termion::color::Fg(termion::color::Reset)
-                    ),
-                ));
+        let mut result = Vec::new();
+        captures(&layer, root, &b, &theme, &line_range, None, &mut result);
+        local_captures(&layer, root, &b, &theme, &line_range, cursor, &mut result);
+        let rows = &line_range.0;
+        for injection in &self.injections {
+            let visible = injection
+                .ranges
+                .iter()
+                .any(|r| r.start_point.row < rows.end && rows.start <= r.end_point.row);
+            if visible {
+                let root = injection.tree.root_node();
+                let included = Some(&injection.ranges[..]);
+                captures(
+                    &injection.layer,
+                    root,
+                    &b,
+                    &theme,
+                    &line_range,
+                    included,
+                    &mut result,
+                );
             }
         }
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_only_up_to_the_limit() {
+        let mut highlighter = Highlighter::new("rust");
+        let buffer = Buffer::from("fn main() {}\n");
+        highlighter.set_limit(8);
+        assert!(highlighter.current_tree(&buffer).is_none());
+        highlighter.set_limit(13);
+        assert!(highlighter.current_tree(&buffer).is_some());
+        highlighter.set_limit(0);
+        assert!(highlighter.current_tree(&buffer).is_some());
+    }
+
+    #[test]
+    fn builds_the_tree_view_once_per_tree() {
+        let mut highlighter = Highlighter::new("rust");
+        let buffer = Buffer::from("fn main() {}\n");
+        highlighter.current_tree(&buffer);
+        let view = highlighter.tree_view(0).unwrap();
+        assert_eq!(view.current, Some(1));
+        let moved = highlighter.tree_view(3).unwrap();
+        assert_eq!(moved.current, Some(2));
+        assert!(Arc::ptr_eq(&view.lines, &moved.lines));
+
+        let edit = InputEdit {
+            start_byte: 0,
+            old_end_byte: 0,
+            new_end_byte: 1,
+            start_position: Point::new(0, 0),
+            old_end_position: Point::new(0, 0),
+            new_end_position: Point::new(0, 1),
+        };
+        highlighter.edit_tree(&edit);
+        let edited = highlighter.tree_view(3).unwrap();
+        assert!(!Arc::ptr_eq(&view.lines, &edited.lines));
+    }
+
+    #[test]
+    fn stops_parsing_after_a_timeout() {
+        let mut highlighter = Highlighter::new("rust");
+        let mut buffer = Buffer::from("fn main() {}\n");
+        assert!(highlighter.current_tree(&buffer).is_some());
+
+        // As if parsing a larger buffer had timed out.
+        let parsed = Parsed {
+            parser: highlighter.parser.take().unwrap(),
+            injector: highlighter.injector.take().unwrap(),
+            tree: None,
+            injections: Vec::new(),
+            generation: highlighter.generation,
+            len: 10,
+        };
+        highlighter.receive(parsed);
+        highlighter.dirty = true;
+        assert!(highlighter.current_tree(&buffer).is_none());
+        assert!(!highlighter.is_parsing());
+
+        buffer = Buffer::from("fn f() {}");
+        assert!(highlighter.current_tree(&buffer).is_some());
+    }
+}
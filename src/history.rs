@@ -1,4 +1,5 @@
 use crate::buffer::Buffer;
+use crate::fold::Fold;
 use crate::position::Position;
 use tree_sitter::Tree;
 
@@ -6,6 +7,7 @@ use tree_sitter::Tree;
 pub(super) struct Record {
     pub(super) buffer: Buffer,
     pub(super) cursor: Position,
+    pub(super) folds: Vec<Fold>,
     pub(super) tree: Option<Tree>,
 }
 
@@ -21,10 +23,6 @@ impl History {
         self.back.push(r);
     }
 
-    pub(super) fn pop(&mut self) -> Option<Record> {
-        self.back.pop()
-    }
-
     pub(super) fn redo(&mut self, current: Record, count: usize) -> Option<Record> {
         if self.forward.is_empty() {
             return None;
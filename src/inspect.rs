@@ -0,0 +1,86 @@
+use std::sync::Arc;
+use tree_sitter::{Node, Tree};
+
+// The syntax tree as an S-expression with one named node per line, for `:InspectTree`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct TreeView {
+    pub(super) lines: Arc<Vec<String>>,
+    // The line of the node under the cursor.
+    pub(super) current: Option<usize>,
+}
+
+// The lines of a tree view with the ids of their nodes, built once per tree. Moving the
+// cursor only looks up the current line.
+pub(super) struct TreeLines {
+    lines: Arc<Vec<String>>,
+    ids: Vec<usize>,
+}
+
+fn push_node(
+    node: Node,
+    field: Option<&str>,
+    depth: usize,
+    lines: &mut Vec<String>,
+    ids: &mut Vec<usize>,
+) {
+    ids.push(node.id());
+    let field = field.map(|f| format!("{}: ", f)).unwrap_or_default();
+    let kind = match node.is_missing() {
+        true => format!("MISSING {}", node.kind()),
+        false => node.kind().to_string(),
+    };
+    lines.push(format!("{}{}({}", "  ".repeat(depth), field, kind));
+
+    let mut cursor = node.walk();
+    if cursor.goto_first_child() {
+        loop {
+            let child = cursor.node();
+            if child.is_named() || child.is_missing() {
+                push_node(child, cursor.field_name(), depth + 1, lines, ids);
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+    if let Some(line) = lines.last_mut() {
+        line.push(')');
+    }
+}
+
+impl TreeLines {
+    pub(super) fn new(tree: &Tree) -> Self {
+        let (mut lines, mut ids) = (Vec::new(), Vec::new());
+        push_node(tree.root_node(), None, 0, &mut lines, &mut ids);
+        Self {
+            lines: Arc::new(lines),
+            ids,
+        }
+    }
+
+    // The view of `tree`, which the lines were built from, with the smallest named node
+    // around the byte `at` as the current one.
+    pub(super) fn view(&self, tree: &Tree, at: usize) -> TreeView {
+        let root = tree.root_node();
+        let target = root.named_descendant_for_byte_range(at, at).unwrap_or(root);
+        TreeView {
+            lines: self.lines.clone(),
+            current: self.ids.iter().position(|id| *id == target.id()),
+        }
+    }
+}
+
+// The kinds of the nodes around the byte `at`, outermost first.
+pub(super) fn kinds_at(tree: &Tree, at: usize) -> Vec<String> {
+    let mut node = tree.root_node().descendant_for_byte_range(at, at);
+    let mut kinds = Vec::new();
+    while let Some(n) = node {
+        kinds.push(match n.is_named() {
+            true => n.kind().to_string(),
+            false => format!("\"{}\"", n.kind()),
+        });
+        node = n.parent();
+    }
+    kinds.reverse();
+    kinds
+}
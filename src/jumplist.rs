@@ -0,0 +1,50 @@
+use crate::position::Position;
+
+const MAX_JUMPS: usize = 100;
+
+// Where the cursor was before each jump, for `<C-o>` and `<C-i>`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct JumpList {
+    back: Vec<Position>,
+    forward: Vec<Position>,
+}
+
+impl JumpList {
+    // Keeps one entry per line, the latest.
+    pub(super) fn push(&mut self, pos: Position) {
+        self.forward.clear();
+        self.back.retain(|p| p.row != pos.row);
+        self.back.push(pos);
+        if self.back.len() > MAX_JUMPS {
+            self.back.remove(0);
+        }
+    }
+
+    pub(super) fn back(&mut self, current: Position, count: usize) -> Option<Position> {
+        if self.back.is_empty() {
+            return None;
+        }
+        self.forward.push(current);
+        for _ in 0..count {
+            match self.back.pop() {
+                Some(pos) => self.forward.push(pos),
+                None => break,
+            }
+        }
+        self.forward.pop()
+    }
+
+    pub(super) fn forward(&mut self, current: Position, count: usize) -> Option<Position> {
+        if self.forward.is_empty() {
+            return None;
+        }
+        self.back.push(current);
+        for _ in 0..count {
+            match self.forward.pop() {
+                Some(pos) => self.back.push(pos),
+                None => break,
+            }
+        }
+        self.back.pop()
+    }
+}
@@ -0,0 +1,426 @@
+use anyhow::{anyhow, Result};
+use hashbrown::HashMap;
+use serde_derive::Deserialize;
+use std::time::Duration;
+use termion::event::Key;
+
+use crate::mode::Mode;
+
+const DEFAULT_TIMEOUT: u64 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(super) enum MapMode {
+    Normal,
+    Insert,
+    Visual,
+    CmdLine,
+}
+
+impl MapMode {
+    fn of(mode: &Mode) -> Option<Self> {
+        match mode {
+            // Mappings only apply where a command starts, optionally after a count.
+            Mode::Normal(cmd) if cmd.chars().all(|c| c.is_ascii_digit()) => Some(MapMode::Normal),
+            Mode::Visual(cmd) if cmd.chars().all(|c| c.is_ascii_digit()) => Some(MapMode::Visual),
+            Mode::Normal(_) | Mode::Visual(_) => None,
+            Mode::Insert(_, _) => Some(MapMode::Insert),
+            Mode::CmdLine(_) | Mode::Search => Some(MapMode::CmdLine),
+            Mode::Picker => None,
+        }
+    }
+
+    fn prefix(&self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Insert => 'i',
+            MapMode::Visual => 'v',
+            MapMode::CmdLine => 'c',
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Rhs {
+    Keys(Vec<Key>),
+    Command(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Mapping {
+    pub(super) rhs: Rhs,
+    pub(super) remap: bool,
+}
+
+pub(super) enum Lookup {
+    Found(usize, Mapping),
+    Pending,
+    NotFound,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(super) enum MappingConfig {
+    Keys(String),
+    Command { command: String },
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub(super) struct KeymapConfig {
+    leader: Option<String>,
+    timeout: Option<u64>,
+    normal: std::collections::HashMap<String, MappingConfig>,
+    insert: std::collections::HashMap<String, MappingConfig>,
+    visual: std::collections::HashMap<String, MappingConfig>,
+    cmdline: std::collections::HashMap<String, MappingConfig>,
+}
+
+fn named_key(name: &str) -> Option<Key> {
+    let key = match name.to_lowercase().as_str() {
+        "esc" => Key::Esc,
+        "cr" | "enter" | "return" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "s-tab" => Key::BackTab,
+        "bs" => Key::Backspace,
+        "del" => Key::Delete,
+        "space" => Key::Char(' '),
+        "lt" => Key::Char('<'),
+        "bar" => Key::Char('|'),
+        "bslash" => Key::Char('\\'),
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "insert" => Key::Insert,
+        name => {
+            let n = name.strip_prefix('f')?.parse().ok()?;
+            Key::F(n)
+        }
+    };
+    Some(key)
+}
+
+pub(super) fn key_to_notation(key: &Key) -> String {
+    match key {
+        Key::Char('\n') => "<CR>".to_string(),
+        Key::Char('\t') => "<Tab>".to_string(),
+        Key::Char(' ') => "<Space>".to_string(),
+        Key::Char('<') => "<lt>".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("<C-{}>", c),
+        Key::Alt(c) => format!("<A-{}>", c),
+        Key::F(n) => format!("<F{}>", n),
+        Key::Esc => "<Esc>".to_string(),
+        Key::BackTab => "<S-Tab>".to_string(),
+        Key::Backspace => "<BS>".to_string(),
+        Key::Delete => "<Del>".to_string(),
+        Key::Up => "<Up>".to_string(),
+        Key::Down => "<Down>".to_string(),
+        Key::Left => "<Left>".to_string(),
+        Key::Right => "<Right>".to_string(),
+        Key::Home => "<Home>".to_string(),
+        Key::End => "<End>".to_string(),
+        Key::PageUp => "<PageUp>".to_string(),
+        Key::PageDown => "<PageDown>".to_string(),
+        Key::Insert => "<Insert>".to_string(),
+        _ => String::new(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Keymap {
+    maps: HashMap<MapMode, HashMap<Vec<Key>, Mapping>>,
+    leader: Key,
+    pub(super) timeout: Duration,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            maps: HashMap::new(),
+            leader: Key::Char('\\'),
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+impl Keymap {
+    // Builds the keymap from `[keymap]`, skipping the entries that fail to parse.
+    pub(super) fn from_config(config: &KeymapConfig) -> (Self, Vec<String>) {
+        let mut keymap = Self::default();
+        let mut errors = Vec::new();
+        if let Some(leader) = &config.leader {
+            match keymap.parse_keys(leader).as_deref() {
+                Ok([key]) => keymap.leader = *key,
+                _ => errors.push(format!("invalid leader: {}", leader)),
+            }
+        }
+        if let Some(timeout) = config.timeout {
+            keymap.timeout = Duration::from_millis(timeout);
+        }
+
+        let tables = [
+            (MapMode::Normal, &config.normal),
+            (MapMode::Insert, &config.insert),
+            (MapMode::Visual, &config.visual),
+            (MapMode::CmdLine, &config.cmdline),
+        ];
+        for (mode, table) in tables {
+            for (lhs, rhs) in table {
+                let result = match rhs {
+                    MappingConfig::Keys(keys) => keymap.map(&[mode], lhs, keys, false),
+                    MappingConfig::Command { command } => keymap
+                        .parse_keys(lhs)
+                        .map(|lhs| keymap.insert(mode, lhs, Rhs::Command(command.clone()), false)),
+                };
+                if let Err(e) = result {
+                    errors.push(format!("keymap.{:?}: {}", mode, e).to_lowercase());
+                }
+            }
+        }
+        (keymap, errors)
+    }
+
+    // Parses `<C-x>` notation, e.g. `<leader>w` or `:w<CR>`.
+    pub(super) fn parse_keys(&self, s: &str) -> Result<Vec<Key>> {
+        let mut keys = Vec::new();
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            let notation = rest
+                .strip_prefix('<')
+                .and_then(|s| s.find('>').map(|i| &s[..i]))
+                .filter(|s| !s.is_empty() && !s.contains('<'));
+            let name = match notation {
+                Some(name) => name,
+                None => {
+                    keys.push(Key::Char(c));
+                    rest = &rest[c.len_utf8()..];
+                    continue;
+                }
+            };
+            rest = &rest[name.len() + 2..];
+
+            let mut chars = name.chars();
+            match (chars.next(), chars.next(), chars.next(), chars.next()) {
+                (Some(m), Some('-'), Some(c), None) if "cC".contains(m) => {
+                    keys.push(Key::Ctrl(c.to_ascii_lowercase()));
+                }
+                // Terminals send <A-x> as `<Esc>x`.
+                (Some(m), Some('-'), Some(c), None) if "aAmM".contains(m) => {
+                    keys.push(Key::Esc);
+                    keys.push(Key::Char(c));
+                }
+                _ if name.eq_ignore_ascii_case("leader") => keys.push(self.leader),
+                _ => keys.push(named_key(name).ok_or_else(|| anyhow!("unknown key: <{}>", name))?),
+            }
+        }
+        if keys.is_empty() {
+            return Err(anyhow!("empty key sequence"));
+        }
+        Ok(keys)
+    }
+
+    fn insert(&mut self, mode: MapMode, lhs: Vec<Key>, rhs: Rhs, remap: bool) {
+        self.maps
+            .entry(mode)
+            .or_default()
+            .insert(lhs, Mapping { rhs, remap });
+    }
+
+    pub(super) fn map(
+        &mut self,
+        modes: &[MapMode],
+        lhs: &str,
+        rhs: &str,
+        remap: bool,
+    ) -> Result<()> {
+        let lhs = self.parse_keys(lhs)?;
+        let rhs = self.parse_keys(rhs)?;
+        for mode in modes {
+            self.insert(*mode, lhs.clone(), Rhs::Keys(rhs.clone()), remap);
+        }
+        Ok(())
+    }
+
+    pub(super) fn unmap(&mut self, modes: &[MapMode], lhs: &str) -> Result<()> {
+        let lhs = self.parse_keys(lhs)?;
+        let mut found = false;
+        for mode in modes {
+            if let Some(map) = self.maps.get_mut(mode) {
+                found |= map.remove(&lhs).is_some();
+            }
+        }
+        if !found {
+            return Err(anyhow!("no such mapping"));
+        }
+        Ok(())
+    }
+
+    pub(super) fn list(&self, modes: &[MapMode]) -> Vec<String> {
+        let mut result = Vec::new();
+        for mode in modes {
+            let map = match self.maps.get(mode) {
+                Some(map) => map,
+                None => continue,
+            };
+            for (lhs, mapping) in map {
+                let lhs: String = lhs.iter().map(key_to_notation).collect();
+                let rhs = match &mapping.rhs {
+                    Rhs::Keys(keys) => keys.iter().map(key_to_notation).collect(),
+                    Rhs::Command(command) => format!(":{}<CR>", command),
+                };
+                let star = if mapping.remap { "" } else { "*" };
+                result.push(format!("{} {} {}{}", mode.prefix(), lhs, star, rhs));
+            }
+        }
+        result.sort();
+        result
+    }
+
+    // Resolves the longest mapping at the head of `keys`, or asks for more keys when
+    // `keys` could still become a longer mapping.
+    pub(super) fn lookup(&self, mode: &Mode, keys: &[Key], timed_out: bool) -> Lookup {
+        let map = match MapMode::of(mode).and_then(|mode| self.maps.get(&mode)) {
+            Some(map) => map,
+            None => return Lookup::NotFound,
+        };
+
+        let ambiguous = map
+            .keys()
+            .any(|lhs| lhs.len() > keys.len() && lhs.starts_with(keys));
+        if ambiguous && !timed_out {
+            return Lookup::Pending;
+        }
+
+        for len in (1..=keys.len()).rev() {
+            if let Some(mapping) = map.get(&keys[..len]) {
+                return Lookup::Found(len, mapping.clone());
+            }
+        }
+        Lookup::NotFound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normal(cmd: &str) -> Mode {
+        Mode::Normal(cmd.to_string())
+    }
+
+    #[test]
+    fn parses_key_notation() {
+        let keymap = Keymap::default();
+        let keys = keymap
+            .parse_keys("<leader>w<C-W><A-x><lt>a<CR>é<F12><s-tab>")
+            .unwrap();
+        assert_eq!(
+            keys,
+            [
+                Key::Char('\\'),
+                Key::Char('w'),
+                Key::Ctrl('w'),
+                Key::Esc,
+                Key::Char('x'),
+                Key::Char('<'),
+                Key::Char('a'),
+                Key::Char('\n'),
+                Key::Char('é'),
+                Key::F(12),
+                Key::BackTab,
+            ]
+        );
+        // A `<` that does not start a key stands for itself.
+        let keys = keymap.parse_keys("<<a<>").unwrap();
+        assert_eq!(keys.len(), 5);
+        assert_eq!(
+            keymap.parse_keys("<nope>").unwrap_err().to_string(),
+            "unknown key: <nope>"
+        );
+        assert!(keymap.parse_keys("").is_err());
+    }
+
+    #[test]
+    fn reads_the_config() {
+        let config: KeymapConfig = toml::from_str(
+            r#"
+leader = "<Space>"
+timeout = 300
+normal = { "<leader>w" = { command = "w" }, "<bad>" = "x" }
+insert = { jk = "<Esc>" }
+"#,
+        )
+        .unwrap();
+        let (keymap, errors) = Keymap::from_config(&config);
+        assert_eq!(errors, ["keymap.normal: unknown key: <bad>"]);
+        assert_eq!(keymap.timeout, Duration::from_millis(300));
+        assert_eq!(
+            keymap.list(&[MapMode::Normal, MapMode::Insert]),
+            ["i jk *<Esc>", "n <Space>w *:w<CR>"]
+        );
+    }
+
+    #[test]
+    fn looks_up_the_longest_mapping() {
+        let mut keymap = Keymap::default();
+        keymap.map(&[MapMode::Normal], "g", "G", false).unwrap();
+        keymap.map(&[MapMode::Normal], "gx", "x", true).unwrap();
+        let keys = keymap.parse_keys("gxy").unwrap();
+
+        assert!(matches!(
+            keymap.lookup(&normal(""), &keys[..1], false),
+            Lookup::Pending
+        ));
+        match keymap.lookup(&normal(""), &keys[..1], true) {
+            Lookup::Found(1, mapping) => assert_eq!(mapping.rhs, Rhs::Keys(vec![Key::Char('G')])),
+            _ => panic!("g is mapped"),
+        }
+        match keymap.lookup(&normal(""), &keys, false) {
+            Lookup::Found(2, mapping) => assert!(mapping.remap),
+            _ => panic!("gx is mapped"),
+        }
+        assert!(matches!(
+            keymap.lookup(&normal(""), &keys[2..], false),
+            Lookup::NotFound
+        ));
+    }
+
+    #[test]
+    fn maps_only_where_a_command_starts() {
+        let mut keymap = Keymap::default();
+        keymap
+            .map(&[MapMode::Normal, MapMode::Visual], "x", "d", false)
+            .unwrap();
+        let keys = [Key::Char('x')];
+        assert!(matches!(
+            keymap.lookup(&normal("3"), &keys, false),
+            Lookup::Found(..)
+        ));
+        assert!(matches!(
+            keymap.lookup(&normal("d"), &keys, false),
+            Lookup::NotFound
+        ));
+        let visual = Mode::Visual(String::new());
+        assert!(matches!(
+            keymap.lookup(&visual, &keys, false),
+            Lookup::Found(..)
+        ));
+        let insert = Mode::Insert(crate::mode::InsertKind::Insert(None), Default::default());
+        assert!(matches!(
+            keymap.lookup(&insert, &keys, false),
+            Lookup::NotFound
+        ));
+
+        keymap.unmap(&[MapMode::Normal], "x").unwrap();
+        assert!(matches!(
+            keymap.lookup(&normal(""), &keys, false),
+            Lookup::NotFound
+        ));
+        assert!(keymap.unmap(&[MapMode::Normal], "x").is_err());
+    }
+}
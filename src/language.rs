@@ -1,21 +1,240 @@
-pub(super) enum Language {
-    Rust,
-    JavaScript,
-    Unknown,
+use serde_derive::Deserialize;
+
+use crate::options::{self, OptionValue, Options};
+use tree_sitter::Language;
+
+pub(super) struct LanguageDef {
+    pub(super) name: &'static str,
+    pub(super) extensions: &'static [&'static str],
+    pub(super) file_names: &'static [&'static str],
+    pub(super) interpreters: &'static [&'static str],
+    comment: &'static str,
+    indent_width: usize,
+    use_tabs: bool,
+    pub(super) grammar: Option<fn() -> Language>,
+    pub(super) highlights: &'static str,
+    pub(super) injections: &'static str,
+    pub(super) locals: &'static str,
+    pub(super) textobjects: &'static str,
+    pub(super) folds: &'static str,
+    pub(super) tags: &'static str,
 }
 
-impl Language {
-    pub(super) fn from_extension(extension: &str) -> Self {
-        use Language::*;
-        match extension {
-            "rs" => Rust,
-            "js" => JavaScript,
-            _ => Unknown,
+const DEFAULT: LanguageDef = LanguageDef {
+    name: "",
+    extensions: &[],
+    file_names: &[],
+    interpreters: &[],
+    comment: "#",
+    indent_width: 2,
+    use_tabs: false,
+    grammar: None,
+    highlights: "",
+    injections: "",
+    locals: "",
+    textobjects: "",
+    folds: "",
+    tags: "",
+};
+
+pub(super) const LANGUAGES: &[LanguageDef] = &[
+    LanguageDef {
+        name: "rust",
+        extensions: &["rs"],
+        comment: "//",
+        indent_width: 4,
+        grammar: Some(tree_sitter_rust::language),
+        highlights: tree_sitter_rust::HIGHLIGHT_QUERY,
+        injections: include_str!("../queries/rust/injections.scm"),
+        locals: include_str!("../queries/rust/locals.scm"),
+        textobjects: include_str!("../queries/rust/textobjects.scm"),
+        folds: include_str!("../queries/rust/folds.scm"),
+        tags: include_str!("../queries/rust/tags.scm"),
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "javascript",
+        extensions: &["js", "mjs", "cjs"],
+        interpreters: &["node", "nodejs"],
+        comment: "//",
+        grammar: Some(tree_sitter_javascript::language),
+        highlights: tree_sitter_javascript::HIGHLIGHT_QUERY,
+        injections: tree_sitter_javascript::INJECTION_QUERY,
+        locals: tree_sitter_javascript::LOCALS_QUERY,
+        textobjects: include_str!("../queries/javascript/textobjects.scm"),
+        folds: include_str!("../queries/javascript/folds.scm"),
+        tags: include_str!("../queries/javascript/tags.scm"),
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "typescript",
+        extensions: &["ts"],
+        interpreters: &["deno"],
+        comment: "//",
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "python",
+        extensions: &["py", "pyi"],
+        interpreters: &["python"],
+        indent_width: 4,
+        grammar: Some(tree_sitter_python::language),
+        highlights: tree_sitter_python::HIGHLIGHT_QUERY,
+        textobjects: include_str!("../queries/python/textobjects.scm"),
+        folds: include_str!("../queries/python/folds.scm"),
+        tags: include_str!("../queries/python/tags.scm"),
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "go",
+        extensions: &["go"],
+        comment: "//",
+        indent_width: 8,
+        use_tabs: true,
+        grammar: Some(tree_sitter_go::language),
+        highlights: tree_sitter_go::HIGHLIGHT_QUERY,
+        textobjects: include_str!("../queries/go/textobjects.scm"),
+        folds: include_str!("../queries/go/folds.scm"),
+        tags: include_str!("../queries/go/tags.scm"),
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "c",
+        extensions: &["c", "h"],
+        comment: "//",
+        indent_width: 4,
+        grammar: Some(tree_sitter_c::language),
+        highlights: tree_sitter_c::HIGHLIGHT_QUERY,
+        textobjects: include_str!("../queries/c/textobjects.scm"),
+        folds: include_str!("../queries/c/folds.scm"),
+        tags: include_str!("../queries/c/tags.scm"),
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "toml",
+        extensions: &["toml"],
+        file_names: &["Cargo.lock", "Pipfile"],
+        grammar: Some(tree_sitter_toml::language),
+        highlights: tree_sitter_toml::HIGHLIGHT_QUERY,
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "json",
+        extensions: &["json"],
+        comment: "",
+        grammar: Some(tree_sitter_json::language),
+        highlights: tree_sitter_json::HIGHLIGHT_QUERY,
+        folds: include_str!("../queries/json/folds.scm"),
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "markdown",
+        extensions: &["md", "markdown"],
+        comment: "",
+        grammar: Some(tree_sitter_md::language),
+        highlights: tree_sitter_md::HIGHLIGHTS_QUERY,
+        injections: include_str!("../queries/markdown/injections.scm"),
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "sh",
+        extensions: &["sh", "bash"],
+        file_names: &[".bashrc", ".bash_profile", ".profile"],
+        interpreters: &["sh", "bash", "dash"],
+        grammar: Some(tree_sitter_bash::language),
+        highlights: tree_sitter_bash::HIGHLIGHT_QUERY,
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "yaml",
+        extensions: &["yaml", "yml"],
+        grammar: Some(tree_sitter_yaml::language),
+        highlights: include_str!("../queries/yaml/highlights.scm"),
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "zsh",
+        file_names: &[".zshrc"],
+        interpreters: &["zsh"],
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "make",
+        extensions: &["mk"],
+        file_names: &["Makefile", "makefile", "GNUmakefile"],
+        indent_width: 8,
+        use_tabs: true,
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "dockerfile",
+        file_names: &["Dockerfile"],
+        ..DEFAULT
+    },
+    LanguageDef {
+        name: "cmake",
+        file_names: &["CMakeLists.txt"],
+        ..DEFAULT
+    },
+];
+
+pub(super) fn find(filetype: &str) -> Option<&'static LanguageDef> {
+    LANGUAGES.iter().find(|def| def.name == filetype)
+}
+
+// `[language.<filetype>]` in config.toml.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub(super) struct LanguageConfig {
+    pub(super) indent_width: Option<usize>,
+    pub(super) use_tabs: Option<bool>,
+    pub(super) comment: Option<String>,
+    pub(super) formatter: Option<String>,
+    // The command of the language server, like `rust-analyzer`.
+    pub(super) lsp: Option<String>,
+}
+
+impl LanguageConfig {
+    pub(super) fn builtin(filetype: &str) -> Self {
+        let def = match find(filetype) {
+            Some(def) => def,
+            None => return Self::default(),
+        };
+        Self {
+            indent_width: Some(def.indent_width),
+            use_tabs: Some(def.use_tabs),
+            comment: Some(def.comment.to_string()).filter(|s| !s.is_empty()),
+            formatter: None,
+            lsp: None,
+        }
+    }
+
+    // Fields set in `other` take precedence.
+    pub(super) fn merge(self, other: &LanguageConfig) -> Self {
+        Self {
+            indent_width: other.indent_width.or(self.indent_width),
+            use_tabs: other.use_tabs.or(self.use_tabs),
+            comment: other.comment.clone().or(self.comment),
+            formatter: other.formatter.clone().or(self.formatter),
+            lsp: other.lsp.clone().or(self.lsp),
         }
     }
 
-    pub(super) fn from_path(path: &str) -> Self {
-        let parts = path.split('.');
-        Self::from_extension(parts.last().unwrap())
+    // Buffer option defaults for the language.
+    pub(super) fn options(&self) -> Options {
+        let mut result = Options::default();
+        let set = |result: &mut Options, name, value| {
+            result.set(options::find(name).unwrap(), value);
+        };
+        if let Some(width) = self.indent_width {
+            set(&mut result, "shiftwidth", OptionValue::Number(width));
+            if self.use_tabs == Some(true) {
+                set(&mut result, "tabstop", OptionValue::Number(width));
+            }
+        }
+        if let Some(use_tabs) = self.use_tabs {
+            set(&mut result, "expandtab", OptionValue::Bool(!use_tabs));
+        }
+        result
     }
 }
@@ -0,0 +1,118 @@
+use crate::buffer::Buffer;
+use core::ops::Range;
+use hashbrown::HashMap;
+use tree_sitter::{Node, Query, QueryCursor};
+
+// A local binding and the identifiers that refer to it, as byte ranges.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Symbol {
+    pub(super) name: String,
+    pub(super) definition: Range<usize>,
+    pub(super) references: Vec<Range<usize>>,
+}
+
+impl Symbol {
+    pub(super) fn contains(&self, byte: usize) -> bool {
+        self.occurrences().any(|r| r.start <= byte && byte < r.end)
+    }
+
+    // The definition and the references, in order.
+    pub(super) fn occurrences(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut ranges = vec![self.definition.clone()];
+        ranges.extend(self.references.iter().cloned());
+        ranges.sort_by_key(|r| r.start);
+        ranges.into_iter()
+    }
+}
+
+struct Scope {
+    end: usize,
+    // Symbols defined in the scope, latest last.
+    definitions: Vec<usize>,
+}
+
+fn text(b: &Buffer, node: Node) -> String {
+    let start = node.start_byte();
+    let bytes: Vec<_> = b.bytes_at(start).take(node.end_byte() - start).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// The top-level nodes around `bytes`, which contain every scope that encloses it.
+fn top_level_range(root: Node, bytes: Range<usize>) -> Range<usize> {
+    let mut cursor = root.walk();
+    let children: Vec<_> = root
+        .children(&mut cursor)
+        .filter(|child| child.start_byte() <= bytes.end && bytes.start <= child.end_byte())
+        .collect();
+    match (children.first(), children.last()) {
+        (Some(first), Some(last)) => first.start_byte()..last.end_byte(),
+        _ => bytes,
+    }
+}
+
+// Resolves the `@local.reference` captures of a `locals.scm` query to the innermost
+// `@local.definition` of the same name in an enclosing `@local.scope`. Only the top-level
+// nodes around `bytes` are looked at.
+pub(super) fn analyze(query: &Query, root: Node, b: &Buffer, bytes: Range<usize>) -> Vec<Symbol> {
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(top_level_range(root, bytes));
+    let captures = cursor.captures(query, root, |node: Node| {
+        let start = node.start_byte();
+        if let Some((chunks, _, _, _)) = b.get_chunks_at_byte(start) {
+            return chunks.map(|s| s.as_bytes());
+        }
+        unreachable!()
+    });
+
+    let mut symbols: Vec<Symbol> = Vec::new();
+    let mut definitions = HashMap::new();
+    let mut scopes = vec![Scope {
+        end: usize::MAX,
+        definitions: Vec::new(),
+    }];
+    for (matched, index) in captures {
+        let capture = matched.captures[index];
+        let node = capture.node;
+        while scopes.last().unwrap().end <= node.start_byte() {
+            scopes.pop();
+        }
+
+        let name = &query.capture_names()[capture.index as usize];
+        match name.as_str() {
+            "local.scope" => scopes.push(Scope {
+                end: node.end_byte(),
+                definitions: Vec::new(),
+            }),
+            name if name.starts_with("local.definition") => {
+                if definitions.contains_key(&node.byte_range()) {
+                    continue;
+                }
+                definitions.insert(node.byte_range(), symbols.len());
+                scopes.last_mut().unwrap().definitions.push(symbols.len());
+                symbols.push(Symbol {
+                    name: text(b, node),
+                    definition: node.byte_range(),
+                    references: Vec::new(),
+                });
+            }
+            "local.reference" => {
+                if definitions.contains_key(&node.byte_range()) {
+                    continue;
+                }
+                let name = text(b, node);
+                let found = scopes.iter().rev().find_map(|scope| {
+                    scope
+                        .definitions
+                        .iter()
+                        .rev()
+                        .find(|i| symbols[**i].name == name)
+                });
+                if let Some(&i) = found {
+                    symbols[i].references.push(node.byte_range());
+                }
+            }
+            _ => {}
+        }
+    }
+    symbols
+}
@@ -0,0 +1,9 @@
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum LspKind {
+    Hover,
+    Definition,
+    References,
+    CodeActions,
+    Rename(String),
+    Format,
+}
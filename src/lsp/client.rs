@@ -0,0 +1,274 @@
+use super::convert::Encoding;
+use anyhow::{anyhow, Result};
+use flume::{Receiver, Sender};
+use hashbrown::HashMap;
+use serde_json::{json, Value};
+use smol::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use smol::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::process;
+
+// What a request was sent for, to know what to do with its response.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Request {
+    Initialize,
+    Hover,
+    Definition,
+    References,
+    Rename,
+    Formatting,
+    CodeAction,
+    ResolveCodeAction,
+    ExecuteCommand,
+    // For the completion of the word starting at the char offset.
+    Completion(usize),
+}
+
+// A request waiting for its response, with the version of the document it is about.
+pub(crate) struct Pending {
+    pub(crate) request: Request,
+    pub(crate) document: Option<(String, i64)>,
+}
+
+// What the servers send, with the language of the server.
+pub(crate) enum Event {
+    Message(String, Value),
+    Exited(String),
+}
+
+// A running language server, talked to over its stdin and stdout.
+pub(crate) struct Client {
+    pub(crate) command: String,
+    // Kills the server when the client goes away.
+    _child: Child,
+    tx: Sender<Value>,
+    next_id: u64,
+    pending: HashMap<u64, Pending>,
+    // Messages held back until the server has answered `initialize`.
+    queue: Vec<Value>,
+    pub(crate) initialized: bool,
+    pub(crate) capabilities: Value,
+    pub(crate) encoding: Encoding,
+    // The versions of the open documents by URI.
+    pub(crate) documents: HashMap<String, i64>,
+    // `contentChanges` not sent yet by URI.
+    pub(crate) changes: HashMap<String, Vec<Value>>,
+}
+
+// Frames each message with its `Content-Length` header.
+async fn write(mut stdin: ChildStdin, rx: Receiver<Value>) {
+    while let Ok(message) = rx.recv_async().await {
+        let body = message.to_string();
+        let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        if stdin.write_all(framed.as_bytes()).await.is_err() || stdin.flush().await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn read_body(reader: &mut BufReader<ChildStdout>) -> Result<Vec<u8>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("end of stream"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            length = Some(n.trim().parse::<usize>()?);
+        }
+    }
+    let mut body = vec![0; length.ok_or_else(|| anyhow!("no Content-Length"))?];
+    reader.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+async fn read(stdout: ChildStdout, language: String, events: Sender<Event>) {
+    let mut reader = BufReader::new(stdout);
+    loop {
+        let body = match read_body(&mut reader).await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::debug!("{} server: {}", language, e);
+                break;
+            }
+        };
+        match serde_json::from_slice(&body) {
+            Ok(message) => {
+                if events
+                    .send(Event::Message(language.clone(), message))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Err(e) => tracing::warn!("{} server sent invalid JSON: {}", language, e),
+        }
+    }
+    let _ = events.send(Event::Exited(language));
+}
+
+impl Client {
+    // Starts `command` and sends `initialize` for the workspace at `root_uri`.
+    pub(crate) fn spawn(
+        language: &str,
+        command: &str,
+        root_uri: &str,
+        events: Sender<Event>,
+    ) -> Result<Self> {
+        let mut args = command.split_whitespace();
+        let program = args.next().ok_or_else(|| anyhow!("empty command"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let (tx, rx) = flume::unbounded();
+        smol::spawn(write(child.stdin.take().unwrap(), rx)).detach();
+        smol::spawn(read(
+            child.stdout.take().unwrap(),
+            language.to_string(),
+            events,
+        ))
+        .detach();
+
+        let mut client = Self {
+            command: command.to_string(),
+            _child: child,
+            tx,
+            next_id: 0,
+            pending: HashMap::new(),
+            queue: Vec::new(),
+            initialized: false,
+            capabilities: Value::Null,
+            encoding: Encoding::default(),
+            documents: HashMap::new(),
+            changes: HashMap::new(),
+        };
+        client.initialize(root_uri);
+        Ok(client)
+    }
+
+    fn initialize(&mut self, root_uri: &str) {
+        let params = json!({
+            "processId": process::id(),
+            "clientInfo": { "name": "neu" },
+            "rootUri": root_uri,
+            "workspaceFolders": [{ "uri": root_uri, "name": "root" }],
+            "capabilities": {
+                "general": { "positionEncodings": ["utf-8", "utf-16"] },
+                "textDocument": {
+                    "synchronization": { "didSave": true },
+                    "publishDiagnostics": {},
+                    "completion": { "completionItem": { "snippetSupport": true } },
+                    "hover": { "contentFormat": ["plaintext", "markdown"] },
+                    "definition": { "linkSupport": true },
+                    "references": {},
+                    "rename": {},
+                    "formatting": {},
+                    "codeAction": {
+                        "codeActionLiteralSupport": {
+                            "codeActionKind": { "valueSet": ["", "quickfix", "refactor", "source"] },
+                        },
+                        "resolveSupport": { "properties": ["edit"] },
+                    },
+                },
+                "workspace": {
+                    "applyEdit": true,
+                    "workspaceEdit": { "documentChanges": true },
+                },
+            },
+        });
+        let id = self.next_id();
+        let pending = Pending {
+            request: Request::Initialize,
+            document: None,
+        };
+        self.pending.insert(id, pending);
+        let _ = self.tx.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "initialize",
+            "params": params,
+        }));
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn send(&mut self, message: Value) {
+        match self.initialized {
+            true => {
+                let _ = self.tx.send(message);
+            }
+            false => self.queue.push(message),
+        }
+    }
+
+    // Takes the capabilities of the server and sends what was held back.
+    pub(crate) fn start(&mut self, result: &Value) {
+        self.capabilities = result["capabilities"].clone();
+        if let Some(name) = self.capabilities["positionEncoding"].as_str() {
+            self.encoding = Encoding::from_name(name);
+        }
+        self.initialized = true;
+        self.notify("initialized", json!({}));
+        for message in std::mem::take(&mut self.queue) {
+            self.send(message);
+        }
+    }
+
+    pub(crate) fn request(&mut self, request: Request, method: &str, params: Value) {
+        let id = self.next_id();
+        let document = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| Some((uri.to_string(), *self.documents.get(uri)?)));
+        self.pending.insert(id, Pending { request, document });
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+    }
+
+    pub(crate) fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    // Answers a request of the server, with `Err` for the ones that are not supported.
+    pub(crate) fn respond(&mut self, id: Value, result: Result<Value, String>) {
+        let message = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(message) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": message },
+            }),
+        };
+        let _ = self.tx.send(message);
+    }
+
+    // The request a response is for.
+    pub(crate) fn take(&mut self, id: &Value) -> Option<Pending> {
+        self.pending.remove(&id.as_u64()?)
+    }
+
+    // Whether the server has the capability, which may be `true` or an options object.
+    pub(crate) fn supports(&self, provider: &str) -> bool {
+        match &self.capabilities[provider] {
+            Value::Bool(b) => *b,
+            Value::Null => false,
+            _ => true,
+        }
+    }
+
+    // 1 for full syncs, 2 for incremental ones and 0 for none.
+    pub(crate) fn sync_kind(&self) -> u64 {
+        let sync = &self.capabilities["textDocumentSync"];
+        sync.as_u64()
+            .or_else(|| sync["change"].as_u64())
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,61 @@
+use super::client::{Client, Event};
+use anyhow::Result;
+use flume::{Receiver, Sender};
+use hashbrown::HashMap;
+use serde_json::Value;
+
+// The servers by language, started the first time a buffer of theirs shows.
+pub(crate) struct Clients {
+    // `None` for the languages whose server failed to start or exited.
+    clients: HashMap<String, Option<Client>>,
+    tx: Sender<Event>,
+    rx: Receiver<Event>,
+    // The last diagnostics published by URI.
+    pub(crate) diagnostics: HashMap<String, Vec<Value>>,
+}
+
+impl Clients {
+    pub(crate) fn new() -> Self {
+        let (tx, rx) = flume::unbounded();
+        Self {
+            clients: HashMap::new(),
+            tx,
+            rx,
+            diagnostics: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn events(&self) -> Receiver<Event> {
+        self.rx.clone()
+    }
+
+    // Only the first try to start a server can fail.
+    pub(crate) fn start(&mut self, language: &str, command: &str, root_uri: &str) -> Result<()> {
+        if self.clients.contains_key(language) {
+            return Ok(());
+        }
+        let (client, result) = match Client::spawn(language, command, root_uri, self.tx.clone()) {
+            Ok(client) => (Some(client), Ok(())),
+            Err(e) => (None, Err(e)),
+        };
+        self.clients.insert(language.to_string(), client);
+        result
+    }
+
+    pub(crate) fn get_mut(&mut self, language: &str) -> Option<&mut Client> {
+        self.clients.get_mut(language)?.as_mut()
+    }
+
+    pub(crate) fn get(&self, language: &str) -> Option<&Client> {
+        self.clients.get(language)?.as_ref()
+    }
+
+    // Forgets the server along with its diagnostics, returning its command.
+    pub(crate) fn exited(&mut self, language: &str) -> Option<String> {
+        let client = self.clients.get_mut(language)?.take()?;
+        for uri in client.documents.keys() {
+            self.diagnostics.remove(uri);
+        }
+        Some(client.command)
+    }
+}
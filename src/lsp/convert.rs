@@ -0,0 +1,139 @@
+use crate::buffer::Buffer;
+use crate::position::Position;
+use core::cmp::min;
+use core::ops::Range;
+use serde_json::{json, Value};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+// What the `character` of an LSP position counts, UTF-16 code units unless the server
+// picked UTF-8 bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Encoding {
+    Utf8,
+    #[default]
+    Utf16,
+}
+
+impl Encoding {
+    pub(crate) fn from_name(name: &str) -> Self {
+        match name {
+            "utf-8" => Self::Utf8,
+            _ => Self::Utf16,
+        }
+    }
+
+    fn len(self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+        }
+    }
+}
+
+// The char column of the LSP `character` in `line`, at most the end of the line.
+pub(crate) fn col(line: &str, character: usize, encoding: Encoding) -> usize {
+    let mut units = 0;
+    for (i, c) in line.chars().enumerate() {
+        if units >= character || c == '\n' {
+            return i;
+        }
+        units += encoding.len(c);
+    }
+    line.chars().count()
+}
+
+pub(crate) fn line_character(position: &Value) -> (usize, usize) {
+    let get = |key| position[key].as_u64().unwrap_or(0) as usize;
+    (get("line"), get("character"))
+}
+
+// The LSP position of the char offset `at`.
+pub(crate) fn position(b: &Buffer, at: usize, encoding: Encoding) -> Value {
+    let at = min(at, b.0.len_chars());
+    let row = b.0.char_to_line(at);
+    let col = at - b.0.line_to_char(row);
+    let character: usize = b.line(row).chars().take(col).map(|c| encoding.len(c)).sum();
+    json!({ "line": row, "character": character })
+}
+
+// The char offset of an LSP position, kept within the buffer.
+pub(crate) fn offset(b: &Buffer, position: &Value, encoding: Encoding) -> usize {
+    let (row, character) = line_character(position);
+    if row >= b.count_lines() {
+        return b.0.len_chars();
+    }
+    b.line_range(row).start + col(&b.line(row).as_str(), character, encoding)
+}
+
+pub(crate) fn range(b: &Buffer, range: &Value, encoding: Encoding) -> Range<usize> {
+    let start = offset(b, &range["start"], encoding);
+    let end = offset(b, &range["end"], encoding);
+    start..end.max(start)
+}
+
+pub(crate) fn cursor(b: &Buffer, position: &Value, encoding: Encoding) -> Position {
+    b.get_position_by_offset(offset(b, position, encoding))
+}
+
+// The `TextEdit`s of a response as char ranges with their new text, last ones first so
+// that they can be applied in order.
+pub(crate) fn text_edits(
+    b: &Buffer,
+    edits: &Value,
+    encoding: Encoding,
+) -> Vec<(Range<usize>, String)> {
+    let mut result: Vec<_> = edits
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|edit| {
+            let text = edit["newText"].as_str().unwrap_or_default().to_string();
+            (range(b, &edit["range"], encoding), text)
+        })
+        .collect();
+    // Stable, so edits inserted at the same place keep their order.
+    result.sort_by_key(|(range, _)| (range.start, range.end));
+    result.reverse();
+    result
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"-._~/".contains(&b)
+}
+
+// `file://` URIs have the bytes of the path other than the unreserved ones escaped.
+pub(crate) fn uri(path: &Path) -> String {
+    let mut result = "file://".to_string();
+    for &b in path.as_os_str().as_bytes() {
+        match is_unreserved(b) {
+            true => result.push(b as char),
+            false => result.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    result
+}
+
+pub(crate) fn path(uri: &str) -> Option<PathBuf> {
+    let escaped = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < escaped.len() {
+        let hex = escaped
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (escaped[i], hex) {
+            (b'%', Some(b)) => {
+                bytes.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                bytes.push(b);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(OsStr::from_bytes(&bytes)))
+}
@@ -0,0 +1,10 @@
+mod action;
+mod client;
+mod clients;
+mod convert;
+mod store;
+
+pub(crate) use action::LspKind;
+pub(crate) use client::Event;
+pub(crate) use clients::Clients;
+pub(super) use store::LspStore;
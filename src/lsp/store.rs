@@ -0,0 +1,763 @@
+use super::action::LspKind;
+use super::client::{Event, Request};
+use super::convert::{self, Encoding};
+use crate::action::ActionKind;
+use crate::completion::{Candidate, Source};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::mode::Mode;
+use crate::picker::{Choice, Item, Picker};
+use crate::position::Position;
+use crate::store::{RootStore, Store};
+use core::cmp::min;
+use core::ops::Range;
+use serde_json::{json, Value};
+use std::env::current_dir;
+use std::fs;
+use std::path::PathBuf;
+
+pub(crate) struct LspStore<'a> {
+    root: &'a mut RootStore,
+}
+
+impl<'a> Store for LspStore<'a> {
+    fn root(&self) -> &RootStore {
+        self.root
+    }
+
+    fn root_mut(&mut self) -> &mut RootStore {
+        self.root
+    }
+}
+
+// The text of a hover, without the fences of the code blocks.
+fn hover_lines(contents: &Value) -> Vec<String> {
+    let text = match contents {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .flat_map(hover_lines)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        contents => contents["value"].as_str().unwrap_or_default().to_string(),
+    };
+    let lines: Vec<_> = text
+        .lines()
+        .filter(|line| !line.starts_with("```"))
+        .map(|line| line.trim_end().to_string())
+        .collect();
+    let start = lines
+        .iter()
+        .position(|l| !l.is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(start, |i| i + 1);
+    lines[start..end].to_vec()
+}
+
+// `Location`s and `LocationLink`s as URIs and the positions they point at.
+fn locations(result: &Value) -> Vec<(String, Value)> {
+    let list = match result {
+        Value::Array(list) => list.clone(),
+        Value::Null => Vec::new(),
+        location => vec![location.clone()],
+    };
+    list.iter()
+        .filter_map(|location| match location["targetUri"].as_str() {
+            Some(uri) => Some((
+                uri.to_string(),
+                location["targetSelectionRange"]["start"].clone(),
+            )),
+            None => Some((
+                location["uri"].as_str()?.to_string(),
+                location["range"]["start"].clone(),
+            )),
+        })
+        .collect()
+}
+
+// The name of the `CompletionItemKind`.
+fn completion_kind(kind: &Value) -> &'static str {
+    const KINDS: [&str; 25] = [
+        "text",
+        "method",
+        "function",
+        "constructor",
+        "field",
+        "variable",
+        "class",
+        "interface",
+        "module",
+        "property",
+        "unit",
+        "value",
+        "enum",
+        "keyword",
+        "snippet",
+        "color",
+        "file",
+        "reference",
+        "folder",
+        "enum member",
+        "constant",
+        "struct",
+        "event",
+        "operator",
+        "type parameter",
+    ];
+    kind.as_u64()
+        .and_then(|kind| KINDS.get(kind.checked_sub(1)? as usize))
+        .copied()
+        .unwrap_or_default()
+}
+
+impl<'a> LspStore<'a> {
+    pub(crate) fn new(root: &'a mut RootStore) -> Self {
+        Self { root }
+    }
+
+    fn fail(&mut self, message: String) {
+        self.root_mut().action(ActionKind::Fail(message).once());
+    }
+
+    // The language and the URI of the current buffer when its language has a server.
+    fn document(&self) -> Option<(String, String)> {
+        let state = self.state();
+        let path = state.path.as_ref()?;
+        state.language_config().lsp?;
+        let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        Some((state.filetype(), convert::uri(&path)))
+    }
+
+    fn encoding(&self, language: &str) -> Encoding {
+        self.clients()
+            .get(language)
+            .map(|client| client.encoding)
+            .unwrap_or_default()
+    }
+
+    // Whether the server of the current buffer is running and knows about it.
+    pub(crate) fn attached(&self) -> bool {
+        match self.document() {
+            Some((language, uri)) => self
+                .clients()
+                .get(&language)
+                .is_some_and(|client| client.documents.contains_key(&uri)),
+            None => false,
+        }
+    }
+
+    // Starts the server of the current buffer if needed and opens the buffer in it.
+    pub(crate) fn attach(&mut self) {
+        let (language, uri) = match self.document() {
+            Some(document) => document,
+            None => return,
+        };
+        let command = self.state().language_config().lsp.unwrap_or_default();
+        let root = convert::uri(&current_dir().unwrap());
+        if let Err(e) = self.clients_mut().start(&language, &command, &root) {
+            self.fail(format!("{}: {}", command, e));
+            return;
+        }
+        let text = self.state().buffer.as_str().to_string();
+        if let Some(client) = self.clients_mut().get_mut(&language) {
+            if !client.documents.contains_key(&uri) {
+                client.documents.insert(uri.clone(), 0);
+                let document = json!({
+                    "uri": uri,
+                    "languageId": language,
+                    "version": 0,
+                    "text": text,
+                });
+                client.notify("textDocument/didOpen", json!({ "textDocument": document }));
+            }
+        }
+    }
+
+    // Called before the chars in `range` are replaced with `text`, while the positions
+    // are those the server knows. A change without a range has the whole text sent.
+    pub(crate) fn record_change(&mut self, range: Option<Range<usize>>, text: &str) {
+        let (language, uri) = match self.document() {
+            Some(document) => document,
+            None => return,
+        };
+        let buffer = self.state().buffer.clone();
+        let client = match self.clients_mut().get_mut(&language) {
+            Some(client) if client.documents.contains_key(&uri) => client,
+            _ => return,
+        };
+        let encoding = client.encoding;
+        let change = match (range, client.sync_kind()) {
+            (Some(range), 2) => json!({
+                "range": {
+                    "start": convert::position(&buffer, range.start, encoding),
+                    "end": convert::position(&buffer, range.end, encoding),
+                },
+                "text": text,
+            }),
+            _ => Value::Null,
+        };
+        client.changes.entry(uri).or_default().push(change);
+    }
+
+    // Sends the changes of the current buffer since the last sync.
+    pub(crate) fn sync(&mut self) {
+        let (language, uri) = match self.document() {
+            Some(document) => document,
+            None => return,
+        };
+        let buffer = self.state().buffer.clone();
+        let client = match self.clients_mut().get_mut(&language) {
+            Some(client) if client.initialized && client.changes.contains_key(&uri) => client,
+            _ => return,
+        };
+        let changes = client.changes.remove(&uri).unwrap_or_default();
+        if client.sync_kind() == 0 {
+            return;
+        }
+        let version = client.documents.entry(uri.clone()).or_default();
+        *version += 1;
+        let version = *version;
+        let changes = match changes.iter().any(Value::is_null) {
+            true => json!([{ "text": buffer.as_str() }]),
+            false => Value::Array(changes),
+        };
+        let params = json!({
+            "textDocument": { "uri": uri, "version": version },
+            "contentChanges": changes,
+        });
+        client.notify("textDocument/didChange", params);
+    }
+
+    pub(crate) fn did_save(&mut self) {
+        let (language, uri) = match self.document() {
+            Some(document) => document,
+            None => return,
+        };
+        if let Some(client) = self.clients_mut().get_mut(&language) {
+            if !client.capabilities["textDocumentSync"]["save"].is_null() {
+                let params = json!({ "textDocument": { "uri": uri } });
+                client.notify("textDocument/didSave", params);
+            }
+        }
+    }
+
+    // The diagnostics last published for the current buffer.
+    pub(crate) fn diagnostics(&self) -> Vec<Diagnostic> {
+        let (language, uri) = match self.document() {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+        let encoding = self.encoding(&language);
+        let buffer = &self.state().buffer;
+        let diagnostics = match self.clients().diagnostics.get(&uri) {
+            Some(diagnostics) => diagnostics,
+            None => return Vec::new(),
+        };
+        diagnostics
+            .iter()
+            .map(|d| {
+                let mut start = convert::cursor(buffer, &d["range"]["start"], encoding);
+                start.col = min(start.col, buffer.row_len(start.row).saturating_sub(1));
+                let message = d["message"].as_str().unwrap_or_default();
+                let message = message.lines().next().unwrap_or_default();
+                Diagnostic {
+                    start,
+                    end: convert::cursor(buffer, &d["range"]["end"], encoding),
+                    message: match d["source"].as_str() {
+                        Some(source) => format!("{}: {}", source, message),
+                        None => message.to_string(),
+                    },
+                    severity: Severity::from_lsp(d["severity"].as_u64().unwrap_or(1)),
+                }
+            })
+            .collect()
+    }
+
+    // Sends a request about the current buffer to its server if the server has the
+    // capability.
+    fn request(&mut self, request: Request, method: &str, provider: &str, params: Value) {
+        let (language, uri) = match self.document() {
+            Some(document) if self.attached() => document,
+            _ => {
+                let filetype = self.state().filetype();
+                self.fail(format!("No language server for filetype '{}'", filetype));
+                return;
+            }
+        };
+        let client = self.clients_mut().get_mut(&language).unwrap();
+        if client.initialized && !client.supports(provider) {
+            let message = format!("{} does not support {}", client.command, method);
+            self.fail(message);
+            return;
+        }
+        let mut params = params;
+        params["textDocument"] = json!({ "uri": uri });
+        client.request(request, method, params);
+    }
+
+    fn position(&self) -> Value {
+        let language = self.state().filetype();
+        let offset = self.state().get_cursor_offset();
+        convert::position(&self.state().buffer, offset, self.encoding(&language))
+    }
+
+    // The cursor, or the visual selection.
+    fn range(&self) -> Value {
+        let language = self.state().filetype();
+        let encoding = self.encoding(&language);
+        let buffer = &self.state().buffer;
+        let range = match self.state().mode.is_visual() {
+            true => self.state().visual_range(),
+            false => {
+                let offset = self.state().get_cursor_offset();
+                offset..offset
+            }
+        };
+        json!({
+            "start": convert::position(buffer, range.start, encoding),
+            "end": convert::position(buffer, range.end, encoding),
+        })
+    }
+
+    // The diagnostics of the server on the rows of `range`, for code actions to fix.
+    fn diagnostics_in(&self, range: &Value) -> Vec<Value> {
+        let (start, end) = (
+            range["start"]["line"].as_u64(),
+            range["end"]["line"].as_u64(),
+        );
+        let uri = match self.document() {
+            Some((_, uri)) => uri,
+            None => return Vec::new(),
+        };
+        let diagnostics = self.clients().diagnostics.get(&uri).cloned();
+        diagnostics
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|d| {
+                d["range"]["start"]["line"].as_u64() <= end
+                    && start <= d["range"]["end"]["line"].as_u64()
+            })
+            .collect()
+    }
+
+    pub(crate) fn action(&mut self, kind: LspKind) {
+        use LspKind::*;
+        let position = self.position();
+        match kind {
+            Hover => self.request(
+                Request::Hover,
+                "textDocument/hover",
+                "hoverProvider",
+                json!({ "position": position }),
+            ),
+            Definition => self.request(
+                Request::Definition,
+                "textDocument/definition",
+                "definitionProvider",
+                json!({ "position": position }),
+            ),
+            References => self.request(
+                Request::References,
+                "textDocument/references",
+                "referencesProvider",
+                json!({ "position": position, "context": { "includeDeclaration": true } }),
+            ),
+            CodeActions => {
+                let range = self.range();
+                let diagnostics = self.diagnostics_in(&range);
+                if self.state().mode.is_visual() {
+                    self.state_mut().mode = Mode::Normal(String::new());
+                }
+                self.request(
+                    Request::CodeAction,
+                    "textDocument/codeAction",
+                    "codeActionProvider",
+                    json!({ "range": range, "context": { "diagnostics": diagnostics } }),
+                )
+            }
+            Rename(name) => self.request(
+                Request::Rename,
+                "textDocument/rename",
+                "renameProvider",
+                json!({ "position": position, "newName": name }),
+            ),
+            Format => {
+                let options = self.state().options();
+                let options = json!({
+                    "tabSize": options.number("shiftwidth"),
+                    "insertSpaces": options.bool("expandtab"),
+                });
+                self.request(
+                    Request::Formatting,
+                    "textDocument/formatting",
+                    "documentFormattingProvider",
+                    json!({ "options": options }),
+                )
+            }
+        }
+    }
+
+    pub(crate) fn receive(&mut self, event: Event) {
+        let (language, message) = match event {
+            Event::Message(language, message) => (language, message),
+            Event::Exited(language) => {
+                if let Some(command) = self.clients_mut().exited(&language) {
+                    self.state_mut().message = Some(format!("{} exited", command));
+                }
+                return;
+            }
+        };
+        match (message["method"].as_str(), message.get("id")) {
+            (Some(method), Some(id)) => {
+                let result = self.server_request(&language, method, &message["params"]);
+                if let Some(client) = self.clients_mut().get_mut(&language) {
+                    client.respond(id.clone(), result);
+                }
+            }
+            (Some(method), None) => self.notification(method, &message["params"]),
+            (None, Some(id)) => self.response(&language, id, &message),
+            (None, None) => {}
+        }
+    }
+
+    fn server_request(
+        &mut self,
+        language: &str,
+        method: &str,
+        params: &Value,
+    ) -> Result<Value, String> {
+        match method {
+            "workspace/applyEdit" => {
+                self.apply_workspace_edit(language, &params["edit"]);
+                Ok(json!({ "applied": true }))
+            }
+            "workspace/configuration" => {
+                let len = params["items"].as_array().map_or(0, Vec::len);
+                Ok(Value::Array(vec![Value::Null; len]))
+            }
+            "window/workDoneProgress/create"
+            | "client/registerCapability"
+            | "client/unregisterCapability" => Ok(Value::Null),
+            method => Err(format!("Unhandled method {}", method)),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) {
+        match method {
+            "textDocument/publishDiagnostics" => {
+                let uri = params["uri"].as_str().unwrap_or_default().to_string();
+                let diagnostics = params["diagnostics"].as_array().cloned();
+                self.clients_mut()
+                    .diagnostics
+                    .insert(uri, diagnostics.unwrap_or_default());
+            }
+            "window/showMessage" => {
+                let message = params["message"].as_str().unwrap_or_default();
+                let message = message.lines().next().unwrap_or_default().to_string();
+                self.state_mut().message = Some(message);
+            }
+            method => tracing::debug!("ignored notification {}", method),
+        }
+    }
+
+    fn response(&mut self, language: &str, id: &Value, message: &Value) {
+        let current = self.document();
+        let current_version = current.as_ref().and_then(|(_, uri)| {
+            let client = self.clients().get(language)?;
+            Some((uri.clone(), *client.documents.get(uri)?))
+        });
+        let client = match self.clients_mut().get_mut(language) {
+            Some(client) => client,
+            None => return,
+        };
+        let pending = match client.take(id) {
+            Some(pending) => pending,
+            None => return,
+        };
+        if let Some(error) = message.get("error") {
+            let error = error["message"].as_str().unwrap_or_default();
+            let message = format!("{}: {}", client.command, error);
+            self.fail(message);
+            return;
+        }
+        // The answers about a document that changed since are out of date, except for
+        // completions, which still fit while the same word is typed.
+        let stale = match pending.request {
+            Request::Completion(_) => {
+                pending.document.map(|(uri, _)| uri) != current_version.map(|(uri, _)| uri)
+            }
+            _ => pending.document.is_some() && pending.document != current_version,
+        };
+        if stale {
+            return;
+        }
+        let result = &message["result"];
+        match pending.request {
+            Request::Initialize => client.start(result),
+            Request::Hover => self.hover(result),
+            Request::Definition => self.definition(language, result),
+            Request::References => self.references(language, result),
+            Request::Rename if result.is_null() => self.fail("Nothing to rename".to_string()),
+            Request::Rename => self.apply_workspace_edit(language, result),
+            Request::Formatting => {
+                let encoding = self.encoding(language);
+                self.apply_edits(result, encoding);
+            }
+            Request::CodeAction => self.code_actions(result),
+            Request::ResolveCodeAction => self.run_code_action(language, result, true),
+            Request::ExecuteCommand => {}
+            Request::Completion(start) => self.completion(start, result),
+        }
+    }
+
+    // Asks for the completions at the cursor, quietly when no server offers them.
+    pub(crate) fn complete(&mut self, start: usize, trigger: Option<char>) {
+        let language = match self.document() {
+            Some((language, _)) if self.attached() => language,
+            _ => return,
+        };
+        let supported = self
+            .clients()
+            .get(&language)
+            .is_some_and(|client| client.supports("completionProvider"));
+        if !supported {
+            return;
+        }
+        // The server has to know about the char just typed.
+        self.sync();
+        let context = match trigger {
+            Some(c) => json!({ "triggerKind": 2, "triggerCharacter": c.to_string() }),
+            None => json!({ "triggerKind": 1 }),
+        };
+        self.request(
+            Request::Completion(start),
+            "textDocument/completion",
+            "completionProvider",
+            json!({ "position": self.position(), "context": context }),
+        );
+    }
+
+    // Whether typing `c` asks the server for completions.
+    pub(crate) fn is_trigger(&self, c: char) -> bool {
+        let language = match self.document() {
+            Some((language, _)) if self.attached() => language,
+            _ => return false,
+        };
+        let client = match self.clients().get(&language) {
+            Some(client) => client,
+            None => return false,
+        };
+        let triggers = &client.capabilities["completionProvider"]["triggerCharacters"];
+        triggers
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|t| t.as_str() == Some(c.to_string().as_str()))
+    }
+
+    // `CompletionItem`s, as a list or in a `CompletionList`.
+    fn completion(&mut self, start: usize, result: &Value) {
+        let items = match result {
+            Value::Array(items) => items,
+            result => match result["items"].as_array() {
+                Some(items) => items,
+                None => return,
+            },
+        };
+        let candidates = items
+            .iter()
+            .filter_map(|item| {
+                let text = item["textEdit"]["newText"]
+                    .as_str()
+                    .or_else(|| item["insertText"].as_str())
+                    .or_else(|| item["label"].as_str())?;
+                // Snippets show their label.
+                let (word, snippet) = match item["insertTextFormat"].as_u64() {
+                    Some(2) => (item["label"].as_str()?, Some(text.to_string())),
+                    _ => (text, None),
+                };
+                Some(Candidate {
+                    word: word.to_string(),
+                    source: Source::Lsp,
+                    detail: completion_kind(&item["kind"]).to_string(),
+                    snippet,
+                })
+            })
+            .collect();
+        self.root_mut().completion().extend(start, candidates);
+    }
+
+    fn hover(&mut self, result: &Value) {
+        let lines = hover_lines(&result["contents"]);
+        match lines.is_empty() {
+            true => self.state_mut().message = Some("No hover information".to_string()),
+            false => self.state_mut().popup = lines,
+        }
+    }
+
+    // The path of a location outside of the current buffer, and the position in it along
+    // with its line.
+    fn location(
+        &self,
+        encoding: Encoding,
+        uri: &str,
+        position: &Value,
+    ) -> Option<(Option<PathBuf>, Position, String)> {
+        let (row, character) = convert::line_character(position);
+        if self.document().is_some_and(|(_, current)| current == uri) {
+            let buffer = &self.state().buffer;
+            let line = buffer.line(min(row, buffer.count_lines().saturating_sub(1)));
+            let line = line.as_str().to_string();
+            let col = convert::col(&line, character, encoding);
+            return Some((None, Position { row, col }, line));
+        }
+        let path = convert::path(uri)?;
+        let text = fs::read_to_string(&path).unwrap_or_default();
+        let line = text.lines().nth(row).unwrap_or_default().to_string();
+        let col = convert::col(&line, character, encoding);
+        Some((Some(path), Position { row, col }, line))
+    }
+
+    // Jumps to the only location, or lists them all.
+    fn definition(&mut self, language: &str, result: &Value) {
+        let encoding = self.encoding(language);
+        let locations = locations(result);
+        match locations.as_slice() {
+            [] => self.fail("No definition found".to_string()),
+            [(uri, position)] => {
+                if let Some((path, position, _)) = self.location(encoding, uri, position) {
+                    self.root_mut().jump_to_location(path.as_deref(), position);
+                }
+            }
+            _ => self.pick_locations("definitions", encoding, &locations),
+        }
+    }
+
+    fn references(&mut self, language: &str, result: &Value) {
+        let encoding = self.encoding(language);
+        let locations = locations(result);
+        match locations.is_empty() {
+            true => self.fail("No references found".to_string()),
+            false => self.pick_locations("references", encoding, &locations),
+        }
+    }
+
+    // `file:line: text` for each location.
+    fn pick_locations(&mut self, title: &str, encoding: Encoding, locations: &[(String, Value)]) {
+        let current = current_dir().unwrap_or_default();
+        let items = locations
+            .iter()
+            .filter_map(|(uri, position)| {
+                let (path, position, line) = self.location(encoding, uri, position)?;
+                let name = match &path {
+                    Some(path) => path.strip_prefix(&current).unwrap_or(path).to_path_buf(),
+                    None => PathBuf::from(self.state().file_name()),
+                };
+                let label = format!("{}:{}: {}", name.display(), position.row + 1, line.trim());
+                Some(Item {
+                    text: label.clone(),
+                    label,
+                    choice: Choice::Jump(path, position),
+                })
+            })
+            .collect();
+        self.state_mut().picker = Picker::new(title, items);
+        self.state_mut().mode = Mode::Picker;
+    }
+
+    fn code_actions(&mut self, result: &Value) {
+        let items: Vec<_> = result
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|action| {
+                let title = action["title"].as_str().unwrap_or_default().to_string();
+                Item {
+                    label: title.clone(),
+                    text: title,
+                    choice: Choice::CodeAction(action.clone()),
+                }
+            })
+            .collect();
+        if items.is_empty() {
+            self.state_mut().message = Some("No code actions available".to_string());
+            return;
+        }
+        self.state_mut().picker = Picker::new("code actions", items);
+        self.state_mut().mode = Mode::Picker;
+    }
+
+    // Applies the edit of a code action and runs its command. Actions with neither are
+    // resolved first.
+    pub(crate) fn run_code_action(&mut self, language: &str, action: &Value, resolved: bool) {
+        let command = match &action["command"] {
+            Value::String(_) => Some(action.clone()),
+            Value::Object(_) => Some(action["command"].clone()),
+            _ => None,
+        };
+        let edit = &action["edit"];
+        let client = match self.clients_mut().get_mut(language) {
+            Some(client) => client,
+            None => return,
+        };
+        if edit.is_null() && command.is_none() {
+            if !resolved && !client.capabilities["codeActionProvider"]["resolveProvider"].is_null()
+            {
+                client.request(
+                    Request::ResolveCodeAction,
+                    "codeAction/resolve",
+                    action.clone(),
+                );
+            }
+            return;
+        }
+        if let Some(command) = command {
+            let params = json!({
+                "command": command["command"],
+                "arguments": command["arguments"],
+            });
+            client.request(Request::ExecuteCommand, "workspace/executeCommand", params);
+        }
+        if !edit.is_null() {
+            self.apply_workspace_edit(language, edit);
+        }
+    }
+
+    // The documents not open yet are opened as buffers, and the edits applied there.
+    fn apply_workspace_edit(&mut self, language: &str, edit: &Value) {
+        let mut documents: Vec<(String, Value)> = Vec::new();
+        if let Some(changes) = edit["changes"].as_object() {
+            documents.extend(
+                changes
+                    .iter()
+                    .map(|(uri, edits)| (uri.clone(), edits.clone())),
+            );
+        }
+        for change in edit["documentChanges"].as_array().into_iter().flatten() {
+            if let Some(uri) = change["textDocument"]["uri"].as_str() {
+                documents.push((uri.to_string(), change["edits"].clone()));
+            }
+        }
+        let encoding = self.encoding(language);
+        let current = self.root().current_buffer();
+        for (uri, edits) in documents {
+            let path = match convert::path(&uri) {
+                Some(path) => path,
+                None => continue,
+            };
+            let i = self.root_mut().open_buffer(&path);
+            self.root_mut().switch_buffer(i);
+            self.apply_edits(&edits, encoding);
+        }
+        self.root_mut().switch_buffer(current);
+    }
+
+    fn apply_edits(&mut self, edits: &Value, encoding: Encoding) {
+        let edits = convert::text_edits(&self.state().buffer, edits, encoding);
+        if !edits.is_empty() {
+            self.root_mut().edit().apply_text_edits(&edits);
+        }
+    }
+}
@@ -1,32 +1,57 @@
 use std::env::temp_dir;
 use std::fs;
+use std::io::{empty, Cursor, Read};
 use std::panic;
+use std::process;
 use std::sync::Arc;
+use std::thread;
 
 use backtrace::Backtrace;
 use clap::Parser;
 use dirs::home_dir;
+use signal_hook::consts::SIGWINCH;
+use signal_hook::iterator::Signals;
+use termion::terminal_size;
 
 mod action;
+mod backend;
 mod buffer;
 mod cmd;
 mod cmdline;
+mod completion;
 mod compute;
 mod config;
+mod diagnostic;
 mod edit;
 mod editor;
+mod filetype;
+mod fold;
+mod grammar;
 mod highlight;
 mod history;
+mod inspect;
+mod jumplist;
+mod keymap;
 mod language;
+mod locals;
+mod lsp;
 mod mode;
 mod movement;
+mod options;
+mod picker;
 mod position;
 mod renderer;
 mod search;
 mod selection;
+mod snippet;
 mod state;
 mod store;
+mod syntax;
+mod tags;
+mod target;
+mod theme;
 
+use crate::action::{Action, ActionKind};
 use crate::editor::Editor;
 use crate::renderer::Renderer;
 use crate::store::RootStore;
@@ -34,7 +59,33 @@ use crate::store::RootStore;
 #[derive(Parser)]
 #[clap(version, author)]
 struct Opts {
-    filename: Option<String>,
+    /// Open files read-only
+    #[clap(short = 'R')]
+    read_only: bool,
+    /// Run without a terminal, quitting after the commands and script are done
+    #[clap(long)]
+    headless: bool,
+    /// Execute an Ex command after the first file has been read
+    #[clap(short = 'c', multiple_occurrences = true)]
+    commands: Vec<String>,
+    /// Read keys from a script file before reading from the terminal
+    #[clap(short = 's')]
+    script: Option<String>,
+    /// Files to open, `+N`/`+/pattern` to jump for the next file, `-` for stdin
+    files: Vec<String>,
+}
+
+fn watch_resize(tx: flume::Sender<Action>) {
+    let mut signals = Signals::new([SIGWINCH]).unwrap();
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if let Ok((width, height)) = terminal_size() {
+                if tx.send(ActionKind::Resize(width, height).once()).is_err() {
+                    break;
+                }
+            }
+        }
+    });
 }
 
 fn main() {
@@ -62,20 +113,58 @@ fn main() {
         tracing::error!("{}", e);
     }));
 
-    smol::block_on(async {
-        let opts: Opts = Opts::parse();
-        let renderer = Renderer::new();
+    let opts: Opts = Opts::parse();
 
-        let (tx, rx) = flume::unbounded();
+    let mut commands = String::new();
+    for c in &opts.commands {
+        if !c.starts_with(':') {
+            commands.push(':');
+        }
+        commands.push_str(c);
+        commands.push('\n');
+    }
+    let script: Box<dyn Read + Send> = match &opts.script {
+        Some(path) => match fs::File::open(path) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => Box::new(empty()),
+    };
+    let input = Cursor::new(commands).chain(script);
 
-        let mut store = if let Some(filename) = opts.filename {
-            RootStore::open_file(&filename, rx, renderer)
+    let status = smol::block_on(async {
+        let renderer = if opts.headless {
+            Renderer::headless()
         } else {
-            RootStore::new(rx, renderer)
+            Renderer::new()
         };
 
+        let (tx, rx) = flume::unbounded();
+
+        let targets = target::parse(&opts.files);
+        let mut store = RootStore::open(&targets, opts.read_only, rx, renderer);
+        store.configure(&config);
+        if opts.headless {
+            store.set_headless();
+        } else {
+            watch_resize(tx.clone());
+        }
+
         let editor = Editor::new(tx);
-        smol::spawn(async move { editor.run().await }).detach();
-        store.run().await;
-    })
+        let input: Box<dyn Read + Send> = if opts.headless {
+            Box::new(input)
+        } else {
+            Box::new(input.chain(termion::get_tty().unwrap()))
+        };
+        smol::spawn(async move {
+            editor.run(input).await;
+            editor.quit();
+        })
+        .detach();
+        store.run().await
+    });
+    process::exit(status);
 }
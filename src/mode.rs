@@ -1,12 +1,15 @@
+use crate::edit::Inserted;
 use crate::movement::MovementKind;
 use crate::selection::Selection;
 
 #[derive(Clone, Debug, PartialEq)]
 pub(super) enum Mode {
     Normal(String),
-    Insert(InsertKind, String),
+    Visual(String),
+    Insert(InsertKind, Inserted),
     CmdLine(String),
     Search,
+    Picker,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -30,6 +33,10 @@ impl Mode {
         false
     }
 
+    pub(super) fn is_visual(&self) -> bool {
+        matches!(self, Mode::Visual(_))
+    }
+
     pub(super) fn get_cmd(&self) -> &String {
         if let Mode::Normal(cmd) = self {
             return cmd;
@@ -16,4 +16,50 @@ pub(crate) enum MovementKind {
     AsSeenOnView,
     NextMatch,
     PrevMatch,
+    Definition,
+    NextSibling,
+    PrevSibling,
+    NextFunctionStart,
+    PrevFunctionStart,
+    NextFunctionEnd,
+    PrevFunctionEnd,
+    NextItem,
+    PrevItem,
+    NextComment,
+    PrevComment,
+    NextDiagnostic,
+    PrevDiagnostic,
+    JumpBack,
+    JumpForward,
+}
+
+impl MovementKind {
+    // Movements that leave an entry in the jump list.
+    pub(crate) fn is_jump(&self) -> bool {
+        use MovementKind::*;
+        matches!(
+            self,
+            Line | Tail
+                | AsSeenOnView
+                | NextMatch
+                | PrevMatch
+                | Definition
+                | NextFunctionStart
+                | PrevFunctionStart
+                | NextFunctionEnd
+                | PrevFunctionEnd
+                | NextItem
+                | PrevItem
+                | NextComment
+                | PrevComment
+        )
+    }
+
+    // As an operator target, whether the char the movement lands on is included.
+    pub(crate) fn is_inclusive(&self) -> bool {
+        matches!(
+            self,
+            MovementKind::NextFunctionEnd | MovementKind::PrevFunctionEnd
+        )
+    }
 }
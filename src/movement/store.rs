@@ -1,22 +1,32 @@
 use super::action::MovementKind;
-use crate::compute::{CursorView, MatchPositions};
+use crate::compute::{CursorView, MatchPositions, WrapScan};
+use crate::fold;
+use crate::mode::Mode;
+use crate::position::Position;
 use crate::search;
 use crate::state::SearchDirection;
 use crate::store::{RootStore, Store};
+use crate::syntax;
 
 use core::cmp::min;
 
+enum TextObject {
+    Function,
+    Item,
+    Comment,
+}
+
 pub(crate) struct MovementStore<'a> {
     root: &'a mut RootStore,
 }
 
 impl<'a> Store for MovementStore<'a> {
     fn root(&self) -> &RootStore {
-        &self.root
+        self.root
     }
 
     fn root_mut(&mut self) -> &mut RootStore {
-        &mut self.root
+        self.root
     }
 }
 
@@ -34,19 +44,20 @@ impl<'a> MovementStore<'a> {
         self.move_col(self.state().cursor.col.saturating_sub(count));
     }
 
+    // A closed fold counts as one line.
     pub(crate) fn down(&mut self, count: usize) {
         let state = self.state_mut();
-        state.cursor.row += count;
-        state.cursor.row = min(
-            state.buffer.count_lines().saturating_sub(1),
-            state.cursor.row,
-        );
+        let closed = state.closed_folds();
+        let line_count = state.buffer.count_lines();
+        state.cursor.row = fold::next_line(&closed, state.cursor.row, count, line_count);
+        state.cursor.row = min(line_count.saturating_sub(1), state.cursor.row);
         state.cursor.col = state.max_column;
     }
 
     fn up(&mut self, count: usize) {
         let state = self.state_mut();
-        state.cursor.row = state.cursor.row.saturating_sub(count);
+        let closed = state.closed_folds();
+        state.cursor.row = fold::prev_line(&closed, state.cursor.row, count);
         state.cursor.col = state.max_column;
     }
 
@@ -71,7 +82,8 @@ impl<'a> MovementStore<'a> {
     }
 
     fn line(&mut self, count: usize) {
-        self.state_mut().cursor.row = min(count, self.state().buffer.count_lines()) - 1;
+        self.state_mut().cursor.row =
+            min(count, self.state().buffer.count_lines()).saturating_sub(1);
     }
 
     fn tail(&mut self) {
@@ -79,7 +91,7 @@ impl<'a> MovementStore<'a> {
     }
 
     fn screen_up(&mut self) {
-        let textarea_row = (self.state().size.1 - 2) as usize;
+        let textarea_row = self.state().textarea_height();
         self.state_mut().row_offset = self.state().row_offset.saturating_sub(textarea_row);
         self.state_mut().cursor.row = min(
             self.state().cursor.row,
@@ -88,7 +100,7 @@ impl<'a> MovementStore<'a> {
     }
 
     fn screen_down(&mut self) {
-        let textarea_row = (self.state().size.1 - 2) as usize;
+        let textarea_row = self.state().textarea_height();
         self.state_mut().row_offset += textarea_row;
         self.state_mut().row_offset = min(
             self.state().buffer.count_lines().saturating_sub(1),
@@ -134,19 +146,204 @@ impl<'a> MovementStore<'a> {
 
     fn next_match(&mut self) {
         let matches = self.reactor_mut().compute::<MatchPositions>().0;
+        let wrap = self.reactor_mut().compute::<WrapScan>().0;
 
-        self.right(1);
-        let cursor = &mut self.state_mut().cursor;
-        let pos = search::get_next(cursor, &matches);
-        *cursor = *pos;
+        let mut cursor = self.state().cursor;
+        cursor.col += 1;
+        match search::get_next(&cursor, &matches, wrap) {
+            Some(pos) => self.state_mut().cursor = *pos,
+            None => self.search_failed("E385: Search hit BOTTOM"),
+        }
     }
 
     fn prev_match(&mut self) {
         let matches = self.reactor_mut().compute::<MatchPositions>().0;
-        let cursor = &mut self.state_mut().cursor;
+        let wrap = self.reactor_mut().compute::<WrapScan>().0;
+
+        match search::get_prev(&self.state().cursor, &matches, wrap) {
+            Some(pos) => self.state_mut().cursor = *pos,
+            None => self.search_failed("E384: Search hit TOP"),
+        }
+    }
+
+    fn definition(&mut self) {
+        let buffer = self.state().buffer.clone();
+        let cursor = buffer.get_byte_by_position(self.state().cursor);
+        let symbols = self.highlighter_mut().symbols(&buffer, cursor);
+        match symbols.iter().find(|symbol| symbol.contains(cursor)) {
+            Some(symbol) => {
+                let pos = buffer.get_position_by_byte(symbol.definition.start);
+                self.state_mut().cursor.row = pos.row;
+                self.move_col(pos.col);
+            }
+            None => self.state_mut().message = Some("No local definition found".to_string()),
+        }
+    }
+
+    // In visual mode the sibling is selected, otherwise the cursor goes to its start.
+    fn sibling(&mut self, forward: bool, count: usize) {
+        let buffer = self.state().buffer.clone();
+        let bytes = self.state().selected_bytes();
+        let tree = self.highlighter_mut().current_tree(&buffer);
+        let range = tree.and_then(|tree| {
+            let mut node = syntax::node_at(tree, bytes)?;
+            for _ in 0..count {
+                node = syntax::sibling(node, forward)?;
+            }
+            Some(node.byte_range())
+        });
+        match range {
+            Some(range) if self.state().mode.is_visual() => {
+                self.state_mut().expanded.clear();
+                self.state_mut().select_bytes(range);
+            }
+            Some(range) => {
+                let pos = buffer.get_position_by_byte(range.start);
+                self.state_mut().cursor.row = pos.row;
+                self.move_col(pos.col);
+            }
+            None => self.state_mut().message = Some("No sibling node".to_string()),
+        }
+    }
+
+    // Moves to the `count`th start, or last char, of a `textobjects.scm` capture after or
+    // before the cursor, or as far as there are any.
+    fn textobject(&mut self, kind: TextObject, end: bool, forward: bool, count: usize) {
+        let buffer = self.state().buffer.clone();
+        let cursor = buffer.get_byte_by_position(self.state().cursor);
+        let ranges = match kind {
+            TextObject::Function => self
+                .highlighter_mut()
+                .textobjects(&buffer, &["function.outer"]),
+            TextObject::Item => syntax::outermost(
+                self.highlighter_mut()
+                    .textobjects(&buffer, &["function.outer", "class.outer"]),
+            ),
+            TextObject::Comment => syntax::join_lines(
+                self.highlighter_mut()
+                    .textobjects(&buffer, &["comment.outer"]),
+                &buffer,
+            ),
+        };
+
+        let mut bytes: Vec<_> = ranges
+            .iter()
+            .filter(|r| !r.is_empty())
+            .map(|r| match end {
+                true => buffer.0.char_to_byte(buffer.0.byte_to_char(r.end) - 1),
+                false => r.start,
+            })
+            .collect();
+        bytes.sort_unstable();
+        bytes.dedup();
+        let target = match forward {
+            true => bytes.iter().filter(|b| **b > cursor).take(count).last(),
+            false => bytes
+                .iter()
+                .rev()
+                .filter(|b| **b < cursor)
+                .take(count)
+                .last(),
+        };
+        if let Some(byte) = target {
+            let pos = buffer.get_position_by_byte(*byte);
+            self.state_mut().cursor.row = pos.row;
+            self.move_col(pos.col);
+        }
+    }
+
+    fn jump_back(&mut self, count: usize) {
+        let cursor = self.state().cursor;
+        if let Some(pos) = self.state_mut().jumps.back(cursor, count) {
+            self.jump_to(pos);
+        }
+    }
+
+    fn jump_forward(&mut self, count: usize) {
+        let cursor = self.state().cursor;
+        if let Some(pos) = self.state_mut().jumps.forward(cursor, count) {
+            self.jump_to(pos);
+        }
+    }
+
+    // Lines may have gone away since the position was recorded.
+    pub(crate) fn jump_to(&mut self, pos: Position) {
+        let last = self.state().buffer.count_lines().saturating_sub(1);
+        self.state_mut().cursor.row = min(pos.row, last);
+        self.move_col(pos.col);
+    }
 
-        let pos = search::get_prev(cursor, &matches);
-        *cursor = *pos;
+    // Starts visual mode on the named node at the cursor.
+    pub(crate) fn select_node(&mut self) {
+        let buffer = self.state().buffer.clone();
+        let bytes = self.state().selected_bytes();
+        let tree = self.highlighter_mut().current_tree(&buffer);
+        match tree.and_then(|tree| syntax::node_at(tree, bytes)) {
+            Some(node) => {
+                let range = node.byte_range();
+                let state = self.state_mut();
+                state.expanded.clear();
+                state.mode = Mode::Visual(String::new());
+                state.select_bytes(range);
+            }
+            None => self.state_mut().message = Some("No syntax tree".to_string()),
+        }
+    }
+
+    // Grows the selection to the enclosing named node.
+    pub(crate) fn expand_selection(&mut self) {
+        let buffer = self.state().buffer.clone();
+        let bytes = self.state().selected_bytes();
+        let tree = self.highlighter_mut().current_tree(&buffer);
+        if let Some(range) = tree.and_then(|tree| syntax::expand(tree, bytes.clone())) {
+            let state = self.state_mut();
+            state.expanded.push(bytes);
+            state.select_bytes(range);
+        }
+    }
+
+    // Goes back to the selection before the last expansion.
+    pub(crate) fn shrink_selection(&mut self) {
+        if let Some(bytes) = self.state_mut().expanded.pop() {
+            self.state_mut().select_bytes(bytes);
+        }
+    }
+
+    // Goes to the start of the `count`th diagnostic after or before the cursor, wrapping
+    // around the buffer.
+    fn diagnostic(&mut self, forward: bool, count: usize) {
+        let key = |p: &Position| (p.row, p.col);
+        let mut starts: Vec<_> = self.state().diagnostics.iter().map(|d| d.start).collect();
+        starts.sort_by_key(key);
+        starts.dedup();
+        let (first, last) = match (starts.first(), starts.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => {
+                self.state_mut().message = Some("No diagnostics".to_string());
+                return;
+            }
+        };
+        let wrapped = match forward {
+            true => first,
+            false => last,
+        };
+        let mut pos = self.state().cursor;
+        for _ in 0..count {
+            pos = match forward {
+                true => starts.iter().find(|p| key(p) > key(&pos)).copied(),
+                false => starts.iter().rev().find(|p| key(p) < key(&pos)).copied(),
+            }
+            .unwrap_or(wrapped);
+        }
+        self.jump_to(pos);
+    }
+
+    fn search_failed(&mut self, error: &str) {
+        let state = self.state_mut();
+        state.message = Some(match state.search_pattern.is_empty() {
+            true => "E35: No previous regular expression".to_string(),
+            false => format!("{} without match for: {}", error, state.search_pattern),
+        });
     }
 
     pub(crate) fn action(&mut self, movement: MovementKind, count: usize) {
@@ -168,6 +365,21 @@ impl<'a> MovementStore<'a> {
             AsSeenOnView => self.as_seen_on_view(),
             NextMatch => self.jump_match(SearchDirection::Forward),
             PrevMatch => self.jump_match(SearchDirection::Reverse),
+            Definition => self.definition(),
+            NextSibling => self.sibling(true, count),
+            PrevSibling => self.sibling(false, count),
+            NextFunctionStart => self.textobject(TextObject::Function, false, true, count),
+            PrevFunctionStart => self.textobject(TextObject::Function, false, false, count),
+            NextFunctionEnd => self.textobject(TextObject::Function, true, true, count),
+            PrevFunctionEnd => self.textobject(TextObject::Function, true, false, count),
+            NextItem => self.textobject(TextObject::Item, false, true, count),
+            PrevItem => self.textobject(TextObject::Item, false, false, count),
+            NextComment => self.textobject(TextObject::Comment, false, true, count),
+            PrevComment => self.textobject(TextObject::Comment, false, false, count),
+            NextDiagnostic => self.diagnostic(true, count),
+            PrevDiagnostic => self.diagnostic(false, count),
+            JumpBack => self.jump_back(count),
+            JumpForward => self.jump_forward(count),
         }
     }
 }
@@ -0,0 +1,191 @@
+use anyhow::{anyhow, Result};
+use hashbrown::HashMap;
+use once_cell::sync::Lazy;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Scope {
+    Global,
+    Buffer,
+    // Buffer options that `:set` does not make the default of other buffers either.
+    BufferOnly,
+    Window,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum OptionValue {
+    Bool(bool),
+    Number(usize),
+    String(String),
+}
+
+pub(super) struct OptionDef {
+    pub(super) name: &'static str,
+    short: &'static str,
+    pub(super) scope: Scope,
+    default: OptionValue,
+}
+
+static DEFINITIONS: Lazy<Vec<OptionDef>> = Lazy::new(|| {
+    use OptionValue::*;
+    use Scope::*;
+    let def = |name, short, scope, default| OptionDef {
+        name,
+        short,
+        scope,
+        default,
+    };
+    vec![
+        def("tabstop", "ts", Buffer, Number(8)),
+        def("shiftwidth", "sw", Buffer, Number(8)),
+        def("expandtab", "et", Buffer, Bool(false)),
+        // Window options are remembered per buffer, as there is only the one window.
+        def("number", "nu", Window, Bool(true)),
+        // Without it, long lines are cut off at the edge of the text area.
+        def("wrap", "wrap", Window, Bool(true)),
+        def("ignorecase", "ic", Global, Bool(false)),
+        def("smartcase", "scs", Global, Bool(false)),
+        def("hlsearch", "hls", Global, Bool(true)),
+        def("wrapscan", "ws", Global, Bool(true)),
+        def("filetype", "ft", BufferOnly, String("".to_string())),
+        // Files larger than this many bytes are not highlighted; 0 for no limit.
+        def("maxhighlight", "mhl", Buffer, Number(10 * 1024 * 1024)),
+    ]
+});
+
+pub(super) fn find(name: &str) -> Option<&'static OptionDef> {
+    DEFINITIONS
+        .iter()
+        .find(|def| def.name == name || def.short == name)
+}
+
+impl OptionDef {
+    pub(super) fn parse_value(&self, s: &str) -> Result<OptionValue> {
+        match self.default {
+            OptionValue::Bool(_) => match s {
+                "true" => Ok(OptionValue::Bool(true)),
+                "false" => Ok(OptionValue::Bool(false)),
+                _ => Err(anyhow!("E474: Invalid argument: {}={}", self.name, s)),
+            },
+            OptionValue::Number(_) => s
+                .parse()
+                .map(OptionValue::Number)
+                .map_err(|_| anyhow!("E521: Number required after =: {}={}", self.name, s)),
+            OptionValue::String(_) => Ok(OptionValue::String(s.to_string())),
+        }
+    }
+
+    pub(super) fn display(&self, value: &OptionValue) -> String {
+        match value {
+            OptionValue::Bool(true) => self.name.to_string(),
+            OptionValue::Bool(false) => format!("no{}", self.name),
+            value => format!("{}={}", self.name, value),
+        }
+    }
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionValue::Bool(b) => write!(f, "{}", b),
+            OptionValue::Number(n) => write!(f, "{}", n),
+            OptionValue::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// Values set in one scope; unset options fall back to the next scope and finally to the
+// registry default.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct Options {
+    values: HashMap<&'static str, OptionValue>,
+}
+
+impl Options {
+    pub(super) fn get(&self, name: &str) -> OptionValue {
+        let def = find(name).unwrap();
+        self.values
+            .get(def.name)
+            .cloned()
+            .unwrap_or_else(|| def.default.clone())
+    }
+
+    pub(super) fn bool(&self, name: &str) -> bool {
+        matches!(self.get(name), OptionValue::Bool(true))
+    }
+
+    pub(super) fn number(&self, name: &str) -> usize {
+        match self.get(name) {
+            OptionValue::Number(n) => n,
+            _ => 0,
+        }
+    }
+
+    pub(super) fn string(&self, name: &str) -> String {
+        match self.get(name) {
+            OptionValue::String(s) => s,
+            _ => String::new(),
+        }
+    }
+
+    pub(super) fn set(&mut self, def: &OptionDef, value: OptionValue) {
+        self.values.insert(def.name, value);
+    }
+
+    pub(super) fn merge(&self, other: &Options) -> Options {
+        let mut values = self.values.clone();
+        values.extend(other.values.iter().map(|(k, v)| (*k, v.clone())));
+        Options { values }
+    }
+
+    // Options that differ from their defaults, as `:set` lists them.
+    pub(super) fn changed(&self) -> Vec<String> {
+        DEFINITIONS
+            .iter()
+            .filter_map(|def| {
+                let value = self.values.get(def.name)?;
+                Some(def.display(value)).filter(|_| *value != def.default)
+            })
+            .collect()
+    }
+}
+
+pub(super) enum SetArg {
+    Show(&'static OptionDef),
+    Assign(&'static OptionDef, OptionValue),
+    Toggle(&'static OptionDef),
+}
+
+// Parses one argument of `:set`: `opt`, `noopt`, `invopt`, `opt!`, `opt?` or `opt=val`.
+pub(super) fn parse_arg(arg: &str) -> Result<SetArg> {
+    let unknown = || anyhow!("E518: Unknown option: {}", arg);
+    if let Some((name, value)) = arg.split_once(['=', ':']) {
+        let def = find(name).ok_or_else(unknown)?;
+        return Ok(SetArg::Assign(def, def.parse_value(value)?));
+    }
+    if let Some(name) = arg.strip_suffix('?') {
+        return Ok(SetArg::Show(find(name).ok_or_else(unknown)?));
+    }
+    if let Some(def) = find(arg) {
+        return match def.default {
+            OptionValue::Bool(_) => Ok(SetArg::Assign(def, OptionValue::Bool(true))),
+            _ => Ok(SetArg::Show(def)),
+        };
+    }
+
+    let is_bool = |def: &&OptionDef| matches!(def.default, OptionValue::Bool(_));
+    let toggle = arg
+        .strip_prefix("inv")
+        .or_else(|| arg.strip_suffix('!'))
+        .and_then(find)
+        .filter(is_bool);
+    if let Some(def) = toggle {
+        return Ok(SetArg::Toggle(def));
+    }
+    let def = arg
+        .strip_prefix("no")
+        .and_then(find)
+        .filter(is_bool)
+        .ok_or_else(unknown)?;
+    Ok(SetArg::Assign(def, OptionValue::Bool(false)))
+}
@@ -0,0 +1,84 @@
+use crate::position::Position;
+use serde_json::Value;
+use std::path::PathBuf;
+
+// What picking an item does.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Choice {
+    // To a position in the current buffer, or in the file at the path.
+    Jump(Option<PathBuf>, Position),
+    // An LSP `CodeAction` or `Command`.
+    CodeAction(Value),
+}
+
+// An entry of a picker, shown as `label` and matched against `text`.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Item {
+    pub(super) label: String,
+    pub(super) text: String,
+    pub(super) choice: Choice,
+}
+
+// A list narrowed down by typing, to pick one of its items.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct Picker {
+    pub(super) title: String,
+    pub(super) items: Vec<Item>,
+    pub(super) query: String,
+    // Index into the matching items.
+    pub(super) selected: usize,
+}
+
+// Whether the chars of `query` are in `text` in order, ignoring case.
+fn is_match(text: &str, query: &str) -> bool {
+    let mut chars = text.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|q| chars.any(|c| c == q))
+}
+
+impl Picker {
+    pub(super) fn new(title: &str, items: Vec<Item>) -> Self {
+        Self {
+            title: title.to_string(),
+            items,
+            ..Default::default()
+        }
+    }
+
+    // The items matching the query, in their order.
+    pub(super) fn matches(&self) -> Vec<&Item> {
+        self.items
+            .iter()
+            .filter(|item| is_match(&item.text, &self.query))
+            .collect()
+    }
+
+    pub(super) fn current(&self) -> Option<&Item> {
+        self.matches().get(self.selected).copied()
+    }
+
+    pub(super) fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.selected = 0;
+    }
+
+    pub(super) fn pop(&mut self) {
+        self.query.pop();
+        self.selected = 0;
+    }
+
+    // Moves the selection by `count` items, wrapping around.
+    pub(super) fn select(&mut self, forward: bool, count: usize) {
+        let len = self.matches().len();
+        if len == 0 {
+            return;
+        }
+        let count = count % len;
+        self.selected = match forward {
+            true => (self.selected + count) % len,
+            false => (self.selected + len - count) % len,
+        };
+    }
+}
@@ -1,51 +1,108 @@
+use crate::backend::{Backend, CursorShape, Grid, MemoryBackend, Style, TermionBackend};
 use crate::buffer::Buffer;
 use crate::compute::{
-    Compute, CurrentLine, CursorView, LineRange, MatchPositionsInView, MaxLineDigit, Reactor,
-    RowOffsetView, SearchPattern, TerminalHeight,
+    expand_tabs, CompletionView, Compute, CurrentLine, CursorLineView, CursorView, Diagnostics,
+    FileStatus, GutterWidth, InspectedTree, MatchPositionsInView, Message, NumberWidth, PickerView,
+    Popup, Reactor, ScreenLine, SearchPattern, SignWidth, SplitWidth, SymbolPath, TabStop,
+    TerminalHeight, TerminalWidth, TextareaHeight, TextareaWidth, VisibleLines, VisualSelection,
 };
+use crate::diagnostic::Diagnostic;
+use crate::inspect::TreeView;
 use crate::mode::Mode;
 use crate::position::Position;
 use crate::search::Match;
 use crate::state::SearchDirection;
-use core::cmp::min;
-use std::io::{stdout, BufWriter, Stdout, Write};
-use std::ops::Range;
-use termion::raw::{IntoRawMode, RawTerminal};
+use crate::theme::Theme;
+use core::cmp::{max, min};
 use tree_sitter::Point;
-use unicode_width::UnicodeWidthStr;
+use unicode_width::UnicodeWidthChar;
 
 #[derive(PartialEq, Clone, Debug)]
 struct TextAreaProps {
-    line_range: Range<usize>,
+    lines: Vec<ScreenLine>,
     buffer: Buffer,
-    max_line_digit: usize,
+    gutter_width: usize,
+    width: usize,
     matches: Vec<Match>,
+    search_style: Style,
+    tab_stop: usize,
+    selection: Option<(Position, Position)>,
+    selection_style: Style,
+    fold_style: Style,
+    diagnostics: Vec<Diagnostic>,
+    diagnostic_style: Style,
 }
 
 impl Compute for TextAreaProps {
-    type Source = (LineRange, Buffer, MaxLineDigit, MatchPositionsInView);
+    type Source = (
+        VisibleLines,
+        Buffer,
+        GutterWidth,
+        MatchPositionsInView,
+        Theme,
+        TabStop,
+        VisualSelection,
+        Diagnostics,
+        TextareaWidth,
+    );
     fn compute(source: &Self::Source) -> Self {
         Self {
-            line_range: source.0 .0.clone(),
+            lines: source.0 .0.clone(),
             buffer: source.1.clone(),
-            max_line_digit: source.2 .0,
+            gutter_width: source.2 .0,
+            width: source.8 .0,
             matches: source.3 .0.clone(),
+            search_style: source.4.get("ui.search"),
+            tab_stop: source.5 .0,
+            selection: source.6 .0,
+            selection_style: source.4.get("ui.selection"),
+            fold_style: source.4.get("ui.fold"),
+            diagnostics: source.7 .0.clone(),
+            diagnostic_style: source.4.get("ui.diagnostic"),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+struct SignColumnProps {
+    sign_width: usize,
+    lines: Vec<ScreenLine>,
+    diagnostics: Vec<Diagnostic>,
+    // By severity.
+    styles: Vec<Style>,
+}
+
+impl Compute for SignColumnProps {
+    type Source = (SignWidth, VisibleLines, Diagnostics, Theme);
+    fn compute(source: &Self::Source) -> Self {
+        Self {
+            sign_width: source.0 .0,
+            lines: source.1 .0.clone(),
+            diagnostics: source.2 .0.clone(),
+            styles: ["error", "warning", "info", "hint"]
+                .iter()
+                .map(|name| source.3.get(&format!("ui.sign.{}", name)))
+                .collect(),
         }
     }
 }
 
 #[derive(PartialEq, Clone, Debug)]
 struct LineNumberProps {
-    max_line_digit: usize,
-    line_range: Range<usize>,
+    sign_width: usize,
+    number_width: usize,
+    lines: Vec<ScreenLine>,
+    style: Style,
 }
 
 impl Compute for LineNumberProps {
-    type Source = (MaxLineDigit, LineRange);
+    type Source = (SignWidth, NumberWidth, VisibleLines, Theme);
     fn compute(source: &Self::Source) -> Self {
         Self {
-            max_line_digit: source.0 .0,
-            line_range: source.1 .0.clone(),
+            sign_width: source.0 .0,
+            number_width: source.1 .0,
+            lines: source.2 .0.clone(),
+            style: source.3.get("ui.linenr"),
         }
     }
 }
@@ -54,18 +111,29 @@ impl Compute for LineNumberProps {
 struct CursorProps {
     cursor: Position,
     current_line: String,
-    max_line_digit: usize,
-    row_offset: usize,
+    gutter_width: usize,
+    line: CursorLineView,
+    tab_stop: usize,
+    width: usize,
 }
 
 impl Compute for CursorProps {
-    type Source = (CursorView, CurrentLine, MaxLineDigit, RowOffsetView);
+    type Source = (
+        CursorView,
+        CurrentLine,
+        GutterWidth,
+        CursorLineView,
+        TabStop,
+        TextareaWidth,
+    );
     fn compute(source: &Self::Source) -> Self {
         Self {
             cursor: source.0 .0,
             current_line: source.1 .0.clone(),
-            max_line_digit: source.2 .0,
-            row_offset: source.3 .0,
+            gutter_width: source.2 .0,
+            line: source.3.clone(),
+            tab_stop: source.4 .0,
+            width: source.5 .0,
         }
     }
 }
@@ -76,232 +144,624 @@ struct StatusLineProps {
     terminal_height: usize,
     search_pattern: String,
     search_direction: SearchDirection,
+    file_status: FileStatus,
+    symbol_path: Vec<String>,
+    message: Option<String>,
+    style: Style,
 }
 
 impl Compute for StatusLineProps {
-    type Source = (Mode, TerminalHeight, SearchPattern, SearchDirection);
+    type Source = (
+        Mode,
+        TerminalHeight,
+        SearchPattern,
+        SearchDirection,
+        FileStatus,
+        SymbolPath,
+        Message,
+        Theme,
+    );
     fn compute(source: &Self::Source) -> Self {
         Self {
             mode: source.0.clone(),
             terminal_height: source.1 .0,
             search_pattern: source.2 .0.clone(),
             search_direction: source.3,
+            file_status: source.4.clone(),
+            symbol_path: source.5 .0.clone(),
+            message: source.6 .0.clone(),
+            style: source.7.get("ui.statusline"),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+struct TreeSplitProps {
+    view: Option<TreeView>,
+    width: usize,
+    textarea_height: usize,
+    split_style: Style,
+    current_style: Style,
+}
+
+impl Compute for TreeSplitProps {
+    type Source = (InspectedTree, SplitWidth, TextareaHeight, Theme);
+    fn compute(source: &Self::Source) -> Self {
+        Self {
+            view: source.0 .0.clone(),
+            width: source.1 .0,
+            textarea_height: source.2 .0,
+            split_style: source.3.get("ui.split"),
+            current_style: source.3.get("ui.selection"),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+struct PopupProps {
+    lines: Vec<String>,
+    cursor: CursorProps,
+    textarea_height: usize,
+    style: Style,
+}
+
+impl Compute for PopupProps {
+    type Source = (Popup, CursorProps, TextareaHeight, Theme);
+    fn compute(source: &Self::Source) -> Self {
+        Self {
+            lines: source.0 .0.clone(),
+            cursor: source.1.clone(),
+            textarea_height: source.2 .0,
+            style: source.3.get("ui.popup"),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+struct MenuProps {
+    view: CompletionView,
+    cursor: CursorProps,
+    textarea_height: usize,
+    style: Style,
+    selected_style: Style,
+}
+
+impl Compute for MenuProps {
+    type Source = (CompletionView, CursorProps, TextareaHeight, Theme);
+    fn compute(source: &Self::Source) -> Self {
+        Self {
+            view: source.0.clone(),
+            cursor: source.1.clone(),
+            textarea_height: source.2 .0,
+            style: source.3.get("ui.menu"),
+            selected_style: source.3.get("ui.menu.selected"),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+struct PickerProps {
+    view: PickerView,
+    textarea_height: usize,
+    style: Style,
+    selected_style: Style,
+}
+
+impl Compute for PickerProps {
+    type Source = (PickerView, TextareaHeight, Theme);
+    fn compute(source: &Self::Source) -> Self {
+        Self {
+            view: source.0.clone(),
+            textarea_height: source.1 .0,
+            style: source.2.get("ui.picker"),
+            selected_style: source.2.get("ui.picker.selected"),
+        }
+    }
+}
+
+// Display width of `s` when it starts at display column `col`.
+fn text_width(s: &str, col: usize, tab_stop: usize) -> usize {
+    let mut width = col;
+    for c in s.chars() {
+        if c == '\t' {
+            width += tab_stop - width % tab_stop;
+        } else {
+            width += c.width().unwrap_or(0);
+        }
+    }
+    width - col
+}
+
+// The text of each screen row of `line`, which starts them at the display columns
+// `starts`, cut off at `width`.
+fn split_rows(line: &str, starts: &[usize], width: usize) -> Vec<String> {
+    let mut rows = vec![String::new(); starts.len()];
+    let mut col = 0;
+    for c in line.chars() {
+        let w = c.width().unwrap_or(0);
+        let i = starts.partition_point(|start| *start <= col) - 1;
+        if col + w <= starts[i] + width {
+            rows[i].push(c);
         }
+        col += w;
     }
+    rows
+}
+
+// The cells of the `len` display columns from `col` on in `line`, as the column, the row
+// and the width on each screen row they are on.
+fn cells(line: &ScreenLine, col: usize, len: usize, width: usize) -> Vec<(u16, u16, u16)> {
+    let mut cells = Vec::new();
+    for (i, start) in line.starts.iter().enumerate() {
+        let end = match line.starts.get(i + 1) {
+            Some(next) => min(*next, start + width),
+            None => start + width,
+        };
+        let (from, to) = (max(col, *start), min(col + len, end));
+        if from < to {
+            let y = line.top + i;
+            cells.push(((from - start) as u16, y as u16, (to - from) as u16));
+        }
+    }
+    cells
 }
 
 pub(super) struct Renderer {
-    stdout: BufWriter<RawTerminal<Stdout>>,
+    backend: Box<dyn Backend>,
+    // `front` is what the backend currently shows, `back` is the frame being drawn.
+    front: Grid,
+    back: Grid,
+    highlights: Vec<(Point, String, Style)>,
+    cursor_shape: CursorShape,
 }
 
 impl Renderer {
     pub(super) fn new() -> Self {
-        let mut stdout = BufWriter::new(stdout().into_raw_mode().unwrap());
-        write!(stdout, "{}", termion::screen::ToAlternateScreen).unwrap();
-        write!(stdout, "{}", termion::clear::All).unwrap();
-        stdout.flush().unwrap();
-        Self { stdout }
+        Self::with_backend(Box::new(TermionBackend::new()))
+    }
+
+    pub(super) fn headless() -> Self {
+        Self::with_backend(Box::new(MemoryBackend::new(80, 24)))
+    }
+
+    pub(super) fn with_backend(backend: Box<dyn Backend>) -> Self {
+        Self {
+            backend,
+            front: Grid::default(),
+            back: Grid::default(),
+            highlights: Vec::new(),
+            cursor_shape: CursorShape::Block,
+        }
+    }
+
+    pub(super) fn size(&self) -> (u16, u16) {
+        self.backend.size()
     }
 }
 
 impl Renderer {
-    pub(super) fn render(&mut self, reactor: &mut Reactor, highlights: Vec<(Point, String)>) {
-        write!(self.stdout, "{}", termion::clear::All).unwrap();
+    pub(super) fn render(
+        &mut self,
+        reactor: &mut Reactor,
+        highlights: Vec<(Point, String, Style)>,
+    ) {
+        let width = reactor.compute::<TerminalWidth>().0 as u16;
+        let height = reactor.compute::<TerminalHeight>().0 as u16;
+        let full = self.back.size() != (width, height);
+        if full {
+            self.back = Grid::new(width, height);
+            self.front = Grid::new(width, height);
+            self.backend.resize(width, height);
+        }
+
+        let highlights_changed = highlights != self.highlights;
+        // The picker and the popups are drawn over the rest, which shows again when they
+        // change.
+        let picker_changed = reactor.get_update::<PickerProps>().is_some();
+        let popup_changed = reactor.get_update::<PopupProps>().is_some();
+        let menu_changed = reactor.get_update::<MenuProps>().is_some();
+        let overlay_changed = picker_changed || popup_changed || menu_changed;
+        let props = match reactor.get_update::<TextAreaProps>() {
+            None if full || highlights_changed || overlay_changed => Some(reactor.compute()),
+            props => props,
+        };
+        if let Some(props) = props {
+            self.render_text_area(props, &highlights);
+        }
+        self.highlights = highlights;
+
+        let props = match reactor.get_update::<SignColumnProps>() {
+            None if full || overlay_changed => Some(reactor.compute()),
+            props => props,
+        };
+        if let Some(props) = props {
+            self.render_sign_column(props);
+        }
+
+        let props = match reactor.get_update::<LineNumberProps>() {
+            None if full || overlay_changed => Some(reactor.compute()),
+            props => props,
+        };
+        if let Some(props) = props {
+            self.render_line_number(props);
+        }
+
+        let props = reactor.compute();
+        self.render_tree_split(props);
+
+        let props = match reactor.get_update::<StatusLineProps>() {
+            None if full || picker_changed => Some(reactor.compute()),
+            props => props,
+        };
+        if let Some(props) = props {
+            self.render_status_line(props);
+        }
 
         let props = reactor.compute();
-        self.render_text_area(props, highlights);
+        self.render_picker(props);
 
         let props = reactor.compute();
-        self.render_line_number(props);
+        self.render_popup(props);
 
         let props = reactor.compute();
-        self.render_status_line(props);
+        self.render_menu(props);
+
+        self.backend.draw(self.back.diff(&self.front));
+        self.front = self.back.clone();
 
         let props = reactor.compute();
         self.render_cursor(props);
 
-        self.stdout.flush().unwrap();
+        self.backend.flush();
     }
 
-    fn render_text_area(&mut self, props: TextAreaProps, highlights: Vec<(Point, String)>) {
-        let max_line_digit = props.max_line_digit;
-        for (i, line) in props
-            .buffer
-            .lines_at(props.line_range.start)
-            .take(props.line_range.end - props.line_range.start)
-            .enumerate()
-        {
-            write!(
-                self.stdout,
-                "{}",
-                termion::cursor::Goto(max_line_digit as u16 + 2, (i + 1) as u16),
-            )
-            .unwrap();
-            write!(self.stdout, "{}", line.as_str()).unwrap();
-        }
-
-        for highlight in highlights {
-            let position = highlight.0;
+    fn render_text_area(&mut self, props: TextAreaProps, highlights: &[(Point, String, Style)]) {
+        let left = props.gutter_width as u16;
+        let tab_stop = props.tab_stop;
+        let line_range = match (props.lines.first(), props.lines.last()) {
+            (Some(first), Some(last)) => first.rows.start..last.rows.end,
+            _ => 0..0,
+        };
+        let height = self.back.size().1;
+        self.back
+            .clear_region(left, 0, props.width as u16, height.saturating_sub(2));
+        // Where the rows shown as text are on the screen.
+        let mut screen_rows = vec![None; line_range.len()];
+        for line in &props.lines {
+            let y = line.top as u16;
+            let text = props.buffer.line(line.rows.start);
+            let text = text.as_str();
+            if line.folded {
+                let summary = format!("+--{:>3} lines: {}", line.rows.len(), text.trim());
+                let summary = split_rows(&expand_tabs(&summary, tab_stop), &[0], props.width);
+                self.back.set_str(left, y, &summary[0], props.fold_style);
+                self.back
+                    .set_style(left, y, props.width as u16, props.fold_style);
+                continue;
+            }
+            screen_rows[line.rows.start - line_range.start] = Some(line);
+            let rows = split_rows(&expand_tabs(&text, tab_stop), &line.starts, props.width);
+            for (i, row) in rows.iter().enumerate() {
+                self.back.set_str(left, y + i as u16, row, Style::default());
+            }
+        }
+        let cells = |row: usize, col: usize, len: usize| {
+            let line = row
+                .checked_sub(line_range.start)
+                .and_then(|i| screen_rows.get(i).copied().flatten());
+            match line {
+                Some(line) => cells(line, col, len, props.width),
+                None => Vec::new(),
+            }
+        };
+
+        for (position, text, style) in highlights {
+            let (position, style) = (*position, *style);
+            if position.row < line_range.start {
+                continue;
+            }
             let line = props.buffer.line(position.row);
             let s: Vec<u8> = line.bytes().take(position.column).collect();
-            let width = UnicodeWidthStr::width(std::str::from_utf8(&s).unwrap());
-            write!(
-                self.stdout,
-                "{}",
-                termion::cursor::Goto(
-                    max_line_digit as u16 + 2 + width as u16,
-                    position.row as u16 - props.line_range.start as u16 + 1
-                ),
-            )
-            .unwrap();
-            for (i, l) in highlight.1.lines().enumerate() {
-                if position.row + i + 1 > props.line_range.end {
-                    write!(self.stdout, "{}", termion::color::Fg(termion::color::Reset)).unwrap();
+            let mut col = text_width(std::str::from_utf8(&s).unwrap(), 0, tab_stop);
+            for (i, l) in text.lines().enumerate() {
+                let row = position.row + i;
+                if row >= line_range.end {
                     break;
                 }
-                write!(self.stdout, "{}", l,).unwrap();
-                write!(
-                    self.stdout,
-                    "{}",
-                    termion::cursor::Goto(
-                        max_line_digit as u16 + 2,
-                        position.row as u16 - props.line_range.start as u16 + 2 + i as u16
-                    ),
-                )
-                .unwrap();
+                for (x, y, width) in cells(row, col, text_width(l, col, tab_stop)) {
+                    self.back.set_style(left + x, y, width, style);
+                }
+                col = 0;
             }
         }
 
-        let matches = props.matches;
-        for m in matches {
+        if let Some((start, end)) = props.selection {
+            let rows = max(start.row, line_range.start)..min(end.row + 1, line_range.end);
+            for row in rows {
+                let line = props.buffer.line(row);
+                let head_len = if row == start.row { start.col } else { 0 };
+                let body_len = match row == end.row {
+                    true => (end.col + 1).saturating_sub(head_len),
+                    false => usize::MAX,
+                };
+                let head: String = line.chars().take(head_len).collect();
+                let body: String = line.chars().skip(head_len).take(body_len).collect();
+                let col = text_width(&head, 0, tab_stop);
+                // The line break of a selected line shows as one cell.
+                let len = max(text_width(&body, col, tab_stop), 1);
+                for (x, y, width) in cells(row, col, len) {
+                    self.back
+                        .set_style(left + x, y, width, props.selection_style);
+                }
+            }
+        }
+
+        for d in &props.diagnostics {
+            let rows = max(d.start.row, line_range.start)..min(d.end.row + 1, line_range.end);
+            for row in rows {
+                let line = props.buffer.line(row);
+                let head_len = if row == d.start.row { d.start.col } else { 0 };
+                let body_len = match row == d.end.row {
+                    true => d.end.col.saturating_sub(head_len),
+                    false => usize::MAX,
+                };
+                let head: String = line.chars().take(head_len).collect();
+                let body: String = line.chars().skip(head_len).take(body_len).collect();
+                let body = body.trim_end_matches('\n');
+                let col = text_width(&head, 0, tab_stop);
+                // A missing token is marked on the char it is missing before.
+                let len = max(text_width(body, col, tab_stop), 1);
+                for (x, y, width) in cells(row, col, len) {
+                    self.back
+                        .patch_style(left + x, y, width, props.diagnostic_style);
+                }
+            }
+        }
+
+        for m in &props.matches {
             let position = m.pos;
-            let length = m.len;
-            let line = props.buffer.line(position.row + props.line_range.start);
-            let s: String = line.chars().take(position.col + length).collect();
-            let width = UnicodeWidthStr::width(&s[..min(s.len() - 1, position.col)]);
-            write!(
-                self.stdout,
-                "{}{}{}{}",
-                termion::cursor::Goto(
-                    max_line_digit as u16 + 2 + width as u16,
-                    position.row as u16 + 1
-                ),
-                termion::color::Bg(termion::color::Green),
-                &s[min(s.len(), position.col)..],
-                termion::color::Bg(termion::color::Reset)
-            )
-            .unwrap();
+            let line = props.buffer.line(position.row);
+            let head: String = line.chars().take(position.col).collect();
+            let body: String = line.chars().skip(position.col).take(m.len).collect();
+            let col = text_width(&head, 0, tab_stop);
+            for (x, y, width) in cells(position.row, col, text_width(&body, col, tab_stop)) {
+                self.back.set_style(left + x, y, width, props.search_style);
+            }
+        }
+    }
+
+    // A closed fold gets a sign for the diagnostics inside it, the most severe one.
+    fn render_sign_column(&mut self, props: SignColumnProps) {
+        let height = self.back.size().1;
+        self.back
+            .clear_region(0, 0, props.sign_width as u16, height.saturating_sub(2));
+        if props.sign_width == 0 {
+            return;
+        }
+        for line in &props.lines {
+            let severity = props
+                .diagnostics
+                .iter()
+                .filter(|d| line.rows.contains(&d.start.row))
+                .map(|d| d.severity)
+                .min();
+            if let Some(severity) = severity {
+                let style = props.styles[severity as usize];
+                self.back
+                    .set_str(0, line.top as u16, severity.sign(), style);
+            }
         }
     }
 
     fn render_line_number(&mut self, props: LineNumberProps) {
-        let max_line_digit = props.max_line_digit;
-        let line_range = props.line_range;
-        write!(
-            self.stdout,
-            "{}{}",
-            termion::color::Bg(termion::color::LightGreen),
-            termion::color::Fg(termion::color::LightBlue),
-        )
-        .unwrap();
-        for (i, line_index) in line_range.enumerate() {
-            write!(
-                self.stdout,
-                "{}{:max_line_digit$}",
-                termion::cursor::Goto(1, i as u16 + 1),
-                line_index + 1,
+        let height = self.back.size().1;
+        let left = props.sign_width as u16;
+        self.back
+            .clear_region(left, 0, props.number_width as u16, height.saturating_sub(2));
+        if props.number_width == 0 {
+            return;
+        }
+        let max_line_digit = props.number_width - 1;
+        let style = props.style;
+        for line in &props.lines {
+            let s = format!(
+                "{:max_line_digit$}",
+                line.rows.start + 1,
                 max_line_digit = max_line_digit
-            )
-            .unwrap();
-        }
-        write!(
-            self.stdout,
-            "{}{}",
-            termion::color::Bg(termion::color::Reset),
-            termion::color::Fg(termion::color::Reset),
-        )
-        .unwrap();
+            );
+            self.back.set_str(left, line.top as u16, &s, style);
+        }
     }
 
     fn render_status_line(&mut self, props: StatusLineProps) {
-        write!(
-            self.stdout,
-            "{}",
-            termion::cursor::Goto(1, props.terminal_height as u16 - 1)
-        )
-        .unwrap();
-        match &props.mode {
+        let height = props.terminal_height as u16;
+        let (mode, shape) = match &props.mode {
             Mode::Normal(cmd) => {
                 if cmd.is_empty() {
-                    write!(self.stdout, "{}NORMAL", termion::cursor::SteadyBlock).unwrap();
+                    ("NORMAL", CursorShape::Block)
                 } else {
-                    write!(self.stdout, "{}NORMAL", termion::cursor::SteadyUnderline).unwrap();
+                    ("NORMAL", CursorShape::Underline)
                 }
             }
-            Mode::Insert(_, _) => {
-                write!(self.stdout, "{}INSERT", termion::cursor::SteadyBar).unwrap();
-            }
+            Mode::Visual(_) => ("VISUAL", CursorShape::Block),
+            Mode::Insert(_, _) => ("INSERT", CursorShape::Bar),
+            Mode::CmdLine(_) => ("COMMAND", CursorShape::Block),
+            Mode::Search => ("SEARCH", CursorShape::Block),
+            Mode::Picker => ("PICKER", CursorShape::Block),
+        };
+        self.cursor_shape = shape;
+        self.back
+            .clear_region(0, height.saturating_sub(2), self.back.size().0, 2);
+
+        let mut status = format!("{} {}", mode, props.file_status.name);
+        if props.file_status.read_only {
+            status.push_str(" [RO]");
+        }
+        if !props.symbol_path.is_empty() {
+            status.push(' ');
+            status.push_str(&props.symbol_path.join(" > "));
+        }
+        if let Some(message) = &props.message {
+            status.push(' ');
+            status.push_str(message);
+        }
+        let y = height.saturating_sub(2);
+        self.back.set_str(0, y, &status, props.style);
+        self.back.set_style(0, y, self.back.size().0, props.style);
+
+        match &props.mode {
             Mode::CmdLine(cmd) => {
-                write!(
-                    self.stdout,
-                    "{}COMMAND{}:{}",
-                    termion::cursor::SteadyBlock,
-                    termion::cursor::Goto(0, props.terminal_height as u16),
-                    cmd
-                )
-                .unwrap();
+                let s = format!(":{}", cmd);
+                self.back
+                    .set_str(0, height.saturating_sub(1), &s, Style::default());
             }
             Mode::Search => {
                 let c = match props.search_direction {
                     SearchDirection::Forward => '/',
                     SearchDirection::Reverse => '?',
                 };
-                write!(
-                    self.stdout,
-                    "{}SEARCH{}{}{}",
-                    termion::cursor::SteadyBlock,
-                    termion::cursor::Goto(0, props.terminal_height as u16 + 1),
-                    c,
-                    props.search_pattern
-                )
-                .unwrap();
+                let s = format!("{}{}", c, props.search_pattern);
+                self.back
+                    .set_str(0, height.saturating_sub(1), &s, Style::default());
             }
+            _ => (),
+        };
+    }
+
+    // The right of the text area, with the current node kept in the middle.
+    fn render_tree_split(&mut self, props: TreeSplitProps) {
+        let view = match props.view {
+            Some(view) => view,
+            None => return,
         };
+        let width = self.back.size().0;
+        let left = width - props.width as u16;
+        let height = props.textarea_height;
+        let top = match view.current {
+            Some(current) => min(
+                current.saturating_sub(height / 2),
+                view.lines.len().saturating_sub(height),
+            ),
+            None => 0,
+        };
+        self.back
+            .clear_region(left, 0, props.width as u16, height as u16);
+        for y in 0..height {
+            self.back.set_str(left, y as u16, "│", props.split_style);
+            let style = match view.current == Some(top + y) {
+                true => props.current_style,
+                false => Style::default(),
+            };
+            if let Some(line) = view.lines.get(top + y) {
+                self.back.set_str(left + 1, y as u16, line, style);
+            }
+        }
+    }
+
+    fn render_picker(&mut self, props: PickerProps) {
+        let view = props.view;
+        if view.prompt.is_empty() {
+            return;
+        }
+        let width = self.back.size().0;
+        let top = props.textarea_height.saturating_sub(view.lines.len());
+        for (i, line) in view.lines.iter().enumerate() {
+            let y = (top + i) as u16;
+            let style = match i == view.selected {
+                true => props.selected_style,
+                false => props.style,
+            };
+            self.back.clear_region(0, y, width, 1);
+            self.back.set_str(0, y, line, style);
+            self.back.set_style(0, y, width, style);
+        }
+        let y = props.textarea_height as u16 + 1;
+        self.back.clear_region(0, y, width, 1);
+        self.back.set_str(0, y, &view.prompt, Style::default());
+    }
+
+    fn render_popup(&mut self, props: PopupProps) {
+        if props.lines.is_empty() {
+            return;
+        }
+        let (x, row) = cursor_cell(&props.cursor);
+        let (top, len) = popup_rows(row, props.lines.len(), props.textarea_height);
+        let width = self.back.size().0 as usize;
+        let popup_width = props.lines.iter().map(|l| text_width(l, 0, 8)).max();
+        let popup_width = min(popup_width.unwrap_or(0) + 2, width);
+        let left = min(x, width - popup_width);
+        for (i, line) in props.lines.iter().take(len).enumerate() {
+            let y = (top + i) as u16;
+            self.back
+                .clear_region(left as u16, y, popup_width as u16, 1);
+            self.back
+                .set_str(left as u16 + 1, y, &expand_tabs(line, 8), props.style);
+            self.back
+                .set_style(left as u16, y, popup_width as u16, props.style);
+        }
+    }
+
+    // Lined up with the start of the completed word.
+    fn render_menu(&mut self, props: MenuProps) {
+        let view = props.view;
+        if view.lines.is_empty() {
+            return;
+        }
+        let (x, row) = cursor_cell(&props.cursor);
+        let x = max(
+            x.saturating_sub(view.prefix_width),
+            props.cursor.gutter_width,
+        );
+        let (top, len) = popup_rows(row, view.lines.len(), props.textarea_height);
+        let width = self.back.size().0 as usize;
+        let menu_width = view.lines.iter().map(|l| text_width(l, 0, 8)).max();
+        let menu_width = min(menu_width.unwrap_or(0) + 2, width);
+        let left = min(x.saturating_sub(1), width - menu_width);
+        for (i, line) in view.lines.iter().take(len).enumerate() {
+            let y = (top + i) as u16;
+            let style = match view.selected == Some(i) {
+                true => props.selected_style,
+                false => props.style,
+            };
+            self.back.clear_region(left as u16, y, menu_width as u16, 1);
+            self.back.set_str(left as u16 + 1, y, line, style);
+            self.back
+                .set_style(left as u16, y, menu_width as u16, style);
+        }
     }
 
     fn render_cursor(&mut self, props: CursorProps) {
-        let cursor = props.cursor;
-        let row_offset = props.row_offset;
-        let col = cursor.col;
-        let row = cursor.row - row_offset;
-
-        let current_line = props.current_line;
-        let s: String = current_line
-            .chars()
-            .take(min(col + 1, current_line.chars().count() - 1))
-            .collect();
-        let width = UnicodeWidthStr::width(s.as_str());
-        let col_pos = width + (col + 1 - s.chars().count());
-
-        let max_line_digit = props.max_line_digit;
-        write!(
-            self.stdout,
-            "{}",
-            termion::cursor::Goto((max_line_digit + 1 + col_pos) as u16, row as u16 + 1)
-        )
-        .unwrap();
+        let (x, y) = cursor_cell(&props);
+        self.backend
+            .set_cursor(x as u16, y as u16, self.cursor_shape);
     }
 }
 
-impl Drop for Renderer {
-    fn drop(&mut self) {
-        write!(
-            self.stdout,
-            "{}{}",
-            termion::clear::All,
-            termion::screen::ToMainScreen
-        )
-        .unwrap();
-        self.stdout.flush().unwrap();
+// The first screen row and the number of rows for `len` lines below the cursor row, or
+// above it when there is more room there.
+fn popup_rows(row: usize, len: usize, height: usize) -> (usize, usize) {
+    let below = height.saturating_sub(row + 1);
+    match below >= len || below >= row {
+        true => (row + 1, min(below, len)),
+        false => (row.saturating_sub(len), min(row, len)),
     }
 }
+
+// The screen column and row of the cursor, on the screen row of its line that it wraps to.
+fn cursor_cell(props: &CursorProps) -> (usize, usize) {
+    let cursor = props.cursor;
+    let current_line = &props.current_line;
+    let len = current_line.chars().count().saturating_sub(1);
+    let s: String = current_line.chars().take(min(cursor.col, len)).collect();
+    let col = match props.line.folded {
+        true => 0,
+        false => text_width(&s, 0, props.tab_stop) + cursor.col.saturating_sub(len),
+    };
+    let starts = &props.line.starts;
+    let i = max(starts.partition_point(|start| *start <= col), 1) - 1;
+    let x = col - starts.get(i).copied().unwrap_or(0);
+    (
+        props.gutter_width + min(x, props.width - 1),
+        props.line.row + i,
+    )
+}
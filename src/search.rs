@@ -12,28 +12,28 @@ impl Match {
     }
 }
 
-pub(crate) fn get_next<'a>(p: &'a Position, matches: &'a [Match]) -> &'a Position {
+pub(crate) fn get_next<'a>(p: &Position, matches: &'a [Match], wrap: bool) -> Option<&'a Position> {
     for m in matches {
         if m.pos.row == p.row && m.pos.col >= p.col {
-            return &m.pos;
+            return Some(&m.pos);
         }
 
         if m.pos.row > p.row {
-            return &m.pos;
+            return Some(&m.pos);
         }
     }
-    p
+    matches.first().map(|m| &m.pos).filter(|_| wrap)
 }
 
-pub(crate) fn get_prev<'a>(p: &'a Position, matches: &'a [Match]) -> &'a Position {
+pub(crate) fn get_prev<'a>(p: &Position, matches: &'a [Match], wrap: bool) -> Option<&'a Position> {
     for m in matches.iter().rev() {
         if m.pos.row == p.row && m.pos.col < p.col {
-            return &m.pos;
+            return Some(&m.pos);
         }
 
         if m.pos.row < p.row {
-            return &m.pos;
+            return Some(&m.pos);
         }
     }
-    p
+    matches.last().map(|m| &m.pos).filter(|_| wrap)
 }
@@ -1,3 +1,5 @@
+use crate::movement::MovementKind;
+
 #[derive(Clone, Debug, PartialEq)]
 pub(super) struct Selection {
     pub(super) count: usize,
@@ -16,6 +18,9 @@ pub(super) enum SelectionKind {
     Word,
     Line,
     LineRemain,
+    Visual,
+    // From the cursor to where the movement goes.
+    Motion(MovementKind),
 }
 
 impl SelectionKind {
@@ -25,11 +30,4 @@ impl SelectionKind {
             kind: self,
         }
     }
-
-    pub(super) fn nth(self, n: usize) -> Selection {
-        Selection {
-            count: n,
-            kind: self,
-        }
-    }
 }
@@ -0,0 +1,7 @@
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum SnippetKind {
+    // Expands the trigger before the cursor or goes to the next tabstop, or else types a
+    // tab.
+    Next,
+    Prev,
+}
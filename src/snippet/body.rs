@@ -0,0 +1,317 @@
+use super::session::Stop;
+use hashbrown::HashMap;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while},
+    character::complete::{digit1, satisfy},
+    combinator::{map, map_res, recognize},
+    error::{Error, ErrorKind},
+    multi::{many0, separated_list1},
+    sequence::{delimited, pair, preceded, tuple},
+    IResult,
+};
+use std::collections::BTreeMap;
+
+// A snippet in the LSP snippet syntax, like `fn ${1:name}($2) {\n\t$0\n}`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Node {
+    Text(String),
+    // A tabstop with its placeholder, which may be empty.
+    Tabstop(usize, Vec<Node>),
+    Choice(usize, Vec<String>),
+    // A variable with its default.
+    Variable(String, Vec<Node>),
+}
+
+fn number(input: &str) -> IResult<&str, usize> {
+    map_res(digit1, str::parse)(input)
+}
+
+fn name(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        satisfy(|c| c.is_ascii_alphabetic() || c == '_'),
+        take_while(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+    ))(input)
+}
+
+// Reads up to one of the `stop` chars. A `\` escapes them, `$`, `}` and itself.
+fn text<'a>(input: &'a str, stop: &str) -> IResult<&'a str, String> {
+    let mut result = String::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some(&(_, c)) if "\\$}".contains(c) || stop.contains(c) => {
+                    result.push(c);
+                    chars.next();
+                }
+                _ => result.push('\\'),
+            },
+            c if stop.contains(c) => {
+                if result.is_empty() {
+                    break;
+                }
+                return Ok((&input[i..], result));
+            }
+            c => result.push(c),
+        }
+    }
+    match result.is_empty() {
+        true => Err(nom::Err::Error(Error::new(input, ErrorKind::Many1))),
+        false => Ok(("", result)),
+    }
+}
+
+fn choice(input: &str) -> IResult<&str, String> {
+    text(input, ",|")
+}
+
+fn nodes<'a>(stop: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<Node>> {
+    many0(move |input| node(input, stop))
+}
+
+// A `$` that starts nothing is just a `$`.
+fn node<'a>(input: &'a str, stop: &'static str) -> IResult<&'a str, Node> {
+    alt((
+        map(preceded(tag("$"), number), |n| Node::Tabstop(n, Vec::new())),
+        map(delimited(tag("${"), number, tag("}")), |n| {
+            Node::Tabstop(n, Vec::new())
+        }),
+        map(
+            tuple((tag("${"), number, tag(":"), nodes("$}"), tag("}"))),
+            |(_, n, _, placeholder, _)| Node::Tabstop(n, placeholder),
+        ),
+        map(
+            tuple((
+                tag("${"),
+                number,
+                tag("|"),
+                separated_list1(tag(","), choice),
+                tag("|}"),
+            )),
+            |(_, n, _, choices, _)| Node::Choice(n, choices),
+        ),
+        map(preceded(tag("$"), name), |name| {
+            Node::Variable(name.to_string(), Vec::new())
+        }),
+        map(delimited(tag("${"), name, tag("}")), |name| {
+            Node::Variable(name.to_string(), Vec::new())
+        }),
+        map(
+            tuple((tag("${"), name, tag(":"), nodes("$}"), tag("}"))),
+            |(_, name, _, default, _)| Node::Variable(name.to_string(), default),
+        ),
+        map(move |input| text(input, stop), Node::Text),
+        map(tag("$"), |s: &str| Node::Text(s.to_string())),
+    ))(input)
+}
+
+pub(crate) fn parse(body: &str) -> Vec<Node> {
+    match nodes("$")(body) {
+        Ok(("", nodes)) => nodes,
+        _ => vec![Node::Text(body.to_string())],
+    }
+}
+
+// The first placeholder of each tabstop, which its mirrors show too.
+fn placeholders(nodes: &[Node], result: &mut HashMap<usize, Vec<Node>>) {
+    for node in nodes {
+        match node {
+            Node::Tabstop(n, placeholder) if !placeholder.is_empty() => {
+                result.entry(*n).or_insert_with(|| placeholder.clone());
+                placeholders(placeholder, result);
+            }
+            Node::Variable(_, default) => placeholders(default, result),
+            _ => {}
+        }
+    }
+}
+
+struct Expansion<'a> {
+    text: String,
+    len: usize,
+    stops: BTreeMap<usize, Stop>,
+    placeholders: HashMap<usize, Vec<Node>>,
+    indent: &'a str,
+    tab: &'a str,
+    variable: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl<'a> Expansion<'a> {
+    fn push(&mut self, s: &str) {
+        for c in s.chars() {
+            match c {
+                '\n' => {
+                    self.text.push('\n');
+                    self.text.push_str(self.indent);
+                    self.len += 1 + self.indent.chars().count();
+                }
+                '\t' => {
+                    self.text.push_str(self.tab);
+                    self.len += self.tab.chars().count();
+                }
+                c => {
+                    self.text.push(c);
+                    self.len += 1;
+                }
+            }
+        }
+    }
+
+    fn walk(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            let start = self.len;
+            match node {
+                Node::Text(s) => self.push(s),
+                Node::Tabstop(n, _) => {
+                    let placeholder = self.placeholders.get(n).cloned().unwrap_or_default();
+                    self.walk(&placeholder);
+                    let stop = self.stops.entry(*n).or_default();
+                    stop.ranges.push(start..self.len);
+                }
+                Node::Choice(n, choices) => {
+                    self.push(&choices[0]);
+                    let stop = self.stops.entry(*n).or_default();
+                    stop.ranges.push(start..self.len);
+                    stop.choices = choices.clone();
+                }
+                // Unknown variables without a default show their name.
+                Node::Variable(name, default) => match (self.variable)(name) {
+                    Some(value) if !value.is_empty() => self.push(&value),
+                    Some(_) => self.walk(default),
+                    None if default.is_empty() => self.push(name),
+                    None => self.walk(default),
+                },
+            }
+        }
+    }
+}
+
+// The text of a snippet, with new lines indented by `indent` and tabs made `tab`, and
+// its tabstops in the order they are jumped to. The end is the last one if there is no
+// `$0`.
+pub(crate) fn expand(
+    nodes: &[Node],
+    indent: &str,
+    tab: &str,
+    variable: &dyn Fn(&str) -> Option<String>,
+) -> (String, Vec<Stop>) {
+    let mut expansion = Expansion {
+        text: String::new(),
+        len: 0,
+        stops: BTreeMap::new(),
+        placeholders: HashMap::new(),
+        indent,
+        tab,
+        variable,
+    };
+    placeholders(nodes, &mut expansion.placeholders);
+    expansion.walk(nodes);
+
+    let mut end = Stop::default();
+    end.ranges.push(expansion.len..expansion.len);
+    let last = expansion.stops.remove(&0).unwrap_or(end);
+    let mut stops: Vec<_> = expansion.stops.into_values().collect();
+    stops.push(last);
+    (expansion.text, stops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Node::*;
+
+    fn text(s: &str) -> Node {
+        Text(s.to_string())
+    }
+
+    fn ranges(stops: &[Stop]) -> Vec<Vec<(usize, usize)>> {
+        let pairs = |stop: &Stop| stop.ranges.iter().map(|r| (r.start, r.end)).collect();
+        stops.iter().map(pairs).collect()
+    }
+
+    fn expand_text(body: &str) -> (String, Vec<Stop>) {
+        expand(&parse(body), "  ", "    ", &|_| None)
+    }
+
+    #[test]
+    fn parses_tabstops_and_placeholders() {
+        assert_eq!(
+            parse("fn ${1:name}($2) {\n\t$0\n}"),
+            vec![
+                text("fn "),
+                Tabstop(1, vec![text("name")]),
+                text("("),
+                Tabstop(2, vec![]),
+                text(") {\n\t"),
+                Tabstop(0, vec![]),
+                text("\n}"),
+            ]
+        );
+        assert_eq!(
+            parse("${1:a ${2:b}}${3}"),
+            vec![
+                Tabstop(1, vec![text("a "), Tabstop(2, vec![text("b")])]),
+                Tabstop(3, vec![]),
+            ]
+        );
+        assert_eq!(
+            parse("${1|one,two\\,three|}"),
+            vec![Choice(1, vec!["one".to_string(), "two,three".to_string()])]
+        );
+        assert_eq!(
+            parse("$TM_FILENAME ${HOME:~}"),
+            vec![
+                Variable("TM_FILENAME".to_string(), vec![]),
+                text(" "),
+                Variable("HOME".to_string(), vec![text("~")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_escapes_and_stray_dollars() {
+        assert_eq!(parse("\\$1 \\} \\\\ \\n"), vec![text("$1 } \\ \\n")]);
+        assert_eq!(parse("${1:a\\}b}"), vec![Tabstop(1, vec![text("a}b")])]);
+        assert_eq!(
+            parse("cost: $ 5"),
+            vec![text("cost: "), text("$"), text(" 5")]
+        );
+        assert_eq!(expand_text("${1:oops").0, "${1:oops");
+    }
+
+    #[test]
+    fn expands_the_text_and_the_stops() {
+        let (text, stops) = expand_text("fn ${1:name}($2) -> $1 {\n\t$0\n}");
+        assert_eq!(text, "fn name() -> name {\n      \n  }");
+        assert_eq!(
+            ranges(&stops),
+            vec![vec![(3, 7), (13, 17)], vec![(8, 8)], vec![(26, 26)]]
+        );
+
+        // Without `$0`, the end is the last stop.
+        let (text, stops) = expand_text("a$2b$1");
+        assert_eq!(text, "ab");
+        assert_eq!(
+            ranges(&stops),
+            vec![vec![(2, 2)], vec![(1, 1)], vec![(2, 2)]]
+        );
+
+        let (text, stops) = expand_text("${1|x,y|}");
+        assert_eq!(text, "x");
+        assert_eq!(ranges(&stops)[0], vec![(0, 1)]);
+        assert_eq!(stops[0].choices, vec!["x", "y"]);
+    }
+
+    #[test]
+    fn expands_variables() {
+        let variable = |name: &str| match name {
+            "A" => Some("a".to_string()),
+            "B" => Some(String::new()),
+            _ => None,
+        };
+        let nodes = parse("$A ${B:empty} ${C} ${D:unknown}");
+        let (text, _) = expand(&nodes, "", "\t", &variable);
+        assert_eq!(text, "a empty C unknown");
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::{anyhow, Result};
+use dirs::home_dir;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Body {
+    Line(String),
+    Lines(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct SnippetConfig {
+    body: Body,
+    #[serde(default)]
+    description: String,
+}
+
+// A snippet of `~/.config/neu/snippets/<lang>.toml`, where each table is named after
+// its trigger:
+//
+// [fn]
+// body = ["fn ${1:name}($2) {", "\t$0", "}"]
+// description = "function"
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Snippet {
+    pub(crate) trigger: String,
+    pub(crate) body: String,
+    pub(crate) description: String,
+}
+
+// The snippets for `language`, none when it has no file.
+pub(crate) fn load(language: &str) -> Result<Vec<Snippet>> {
+    let path = home_dir()
+        .ok_or_else(|| anyhow!("no home directory"))?
+        .join(".config/neu/snippets")
+        .join(format!("{}.toml", language));
+    let s = match fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let table: BTreeMap<String, SnippetConfig> =
+        toml::from_str(&s).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    Ok(table
+        .into_iter()
+        .map(|(trigger, config)| Snippet {
+            trigger,
+            body: match config.body {
+                Body::Line(line) => line,
+                Body::Lines(lines) => lines.join("\n"),
+            },
+            description: config.description,
+        })
+        .collect())
+}
@@ -0,0 +1,9 @@
+mod action;
+mod body;
+mod config;
+mod session;
+mod store;
+
+pub(crate) use action::SnippetKind;
+pub(crate) use session::Session;
+pub(super) use store::SnippetStore;
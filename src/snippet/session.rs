@@ -0,0 +1,122 @@
+use core::ops::Range;
+
+// A tabstop as the char ranges it covers, the first one being typed in and the others
+// mirroring it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Stop {
+    pub(crate) ranges: Vec<Range<usize>>,
+    pub(crate) choices: Vec<String>,
+}
+
+// An expanded snippet while its tabstops are gone through.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Session {
+    // In the order they are jumped to, `$0` last.
+    pub(crate) stops: Vec<Stop>,
+    pub(crate) current: usize,
+    // Whether the placeholder of the current stop is untouched, so that typing replaces
+    // it.
+    pub(crate) fresh: bool,
+}
+
+impl Session {
+    pub(crate) fn primary(&self) -> Range<usize> {
+        self.stops[self.current].ranges[0].clone()
+    }
+
+    pub(crate) fn is_last(&self) -> bool {
+        self.current + 1 == self.stops.len()
+    }
+
+    // Text typed at the edge of a range goes into it only for the current stop, so that
+    // the stops next to it stay as they are.
+    pub(crate) fn inserted(&mut self, at: usize, len: usize) {
+        for (i, stop) in self.stops.iter_mut().enumerate() {
+            let current = i == self.current;
+            for range in &mut stop.ranges {
+                let grows = match current {
+                    true => range.start <= at && at <= range.end,
+                    false => range.start < at && at < range.end,
+                };
+                if grows {
+                    range.end += len;
+                } else if range.start >= at {
+                    range.start += len;
+                    range.end += len;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn removed(&mut self, removed: Range<usize>) {
+        let shift = |at: usize| match at {
+            at if at <= removed.start => at,
+            at if at >= removed.end => at - removed.len(),
+            _ => removed.start,
+        };
+        for stop in &mut self.stops {
+            for range in &mut stop.ranges {
+                *range = shift(range.start)..shift(range.end);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `fn name() -> name {}` with the name, the parameters and the end as the stops.
+    fn session(current: usize) -> Session {
+        let stop = |ranges: &[(usize, usize)]| Stop {
+            ranges: ranges.iter().map(|&(start, end)| start..end).collect(),
+            choices: Vec::new(),
+        };
+        Session {
+            stops: vec![
+                stop(&[(3, 7), (13, 17)]),
+                stop(&[(8, 8)]),
+                stop(&[(20, 20)]),
+            ],
+            current,
+            fresh: false,
+        }
+    }
+
+    fn ranges(session: &Session) -> Vec<Vec<(usize, usize)>> {
+        let pairs = |stop: &Stop| stop.ranges.iter().map(|r| (r.start, r.end)).collect();
+        session.stops.iter().map(pairs).collect()
+    }
+
+    #[test]
+    fn grows_only_the_current_stop_at_its_edges() {
+        let mut s = session(1);
+        s.inserted(8, 2);
+        let expected = vec![vec![(3, 7), (15, 19)], vec![(8, 10)], vec![(22, 22)]];
+        assert_eq!(ranges(&s), expected);
+        assert_eq!(s.primary(), 8..10);
+
+        // Typing after the name, with another stop current, leaves the name as it is.
+        let mut s = session(1);
+        s.inserted(7, 1);
+        let expected = vec![vec![(3, 7), (14, 18)], vec![(9, 9)], vec![(21, 21)]];
+        assert_eq!(ranges(&s), expected);
+
+        let mut s = session(0);
+        s.inserted(7, 1);
+        let expected = vec![vec![(3, 8), (14, 18)], vec![(9, 9)], vec![(21, 21)]];
+        assert_eq!(ranges(&s), expected);
+        s.inserted(5, 1);
+        assert_eq!(s.primary(), 3..9);
+    }
+
+    #[test]
+    fn shrinks_the_stops_over_removals() {
+        let mut s = session(0);
+        s.removed(5..9);
+        let expected = vec![vec![(3, 5), (9, 13)], vec![(5, 5)], vec![(16, 16)]];
+        assert_eq!(ranges(&s), expected);
+        assert!(!s.is_last());
+        assert!(session(2).is_last());
+    }
+}
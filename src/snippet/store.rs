@@ -0,0 +1,255 @@
+use super::action::SnippetKind;
+use super::body;
+use super::config::{self, Snippet};
+use super::session::Session;
+use crate::action::ActionKind;
+use crate::completion::{Candidate, Completion, Source};
+use crate::edit::EditKind;
+use crate::state::State;
+use crate::store::{RootStore, Store};
+use core::ops::Range;
+use std::ffi::OsStr;
+use std::mem;
+use std::path::Path;
+
+pub(crate) struct SnippetStore<'a> {
+    root: &'a mut RootStore,
+}
+
+impl<'a> Store for SnippetStore<'a> {
+    fn root(&self) -> &RootStore {
+        self.root
+    }
+
+    fn root_mut(&mut self) -> &mut RootStore {
+        self.root
+    }
+}
+
+// The values of the variables of the LSP snippet syntax that make sense here.
+fn variable(state: &State, name: &str) -> Option<String> {
+    let path = state.path.as_ref().map(Path::new);
+    let part = |f: fn(&Path) -> Option<&OsStr>| {
+        path.and_then(f)
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    let line = state.buffer.line(state.cursor.row).as_str().to_string();
+    Some(match name {
+        "TM_FILENAME" => part(Path::file_name),
+        "TM_FILENAME_BASE" => part(Path::file_stem),
+        "TM_DIRECTORY" => path
+            .and_then(Path::parent)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        "TM_FILEPATH" => path
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        "TM_LINE_INDEX" => state.cursor.row.to_string(),
+        "TM_LINE_NUMBER" => (state.cursor.row + 1).to_string(),
+        "TM_CURRENT_LINE" => line.trim_end_matches('\n').to_string(),
+        "TM_SELECTED_TEXT" => String::new(),
+        "CLIPBOARD" => state.yanked.clone(),
+        _ => return None,
+    })
+}
+
+impl<'a> SnippetStore<'a> {
+    pub(crate) fn new(root: &'a mut RootStore) -> Self {
+        Self { root }
+    }
+
+    fn snippets(&mut self) -> Vec<Snippet> {
+        match config::load(&self.state().filetype()) {
+            Ok(snippets) => snippets,
+            Err(e) => {
+                let message = format!("snippets: {}", e);
+                self.root_mut().action(ActionKind::Fail(message).once());
+                Vec::new()
+            }
+        }
+    }
+
+    // The snippets of the language for the completion menu.
+    pub(crate) fn candidates(&mut self) -> Vec<Candidate> {
+        self.snippets()
+            .into_iter()
+            .map(|snippet| Candidate {
+                word: snippet.trigger,
+                source: Source::Snippet,
+                detail: snippet.description,
+                snippet: Some(snippet.body),
+            })
+            .collect()
+    }
+
+    // The longest trigger that ends the text before the cursor and does not start in the
+    // middle of a word.
+    fn trigger(&mut self) -> Option<(Range<usize>, Snippet)> {
+        let cursor = self.state().get_cursor_offset();
+        let head = self.state().current_line().start;
+        let before = self.state().buffer.slice(head..cursor).as_str().to_string();
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        self.snippets()
+            .into_iter()
+            .filter(|snippet| !snippet.trigger.is_empty())
+            .filter(|snippet| {
+                let start = match before.strip_suffix(snippet.trigger.as_str()) {
+                    Some(start) => start,
+                    None => return false,
+                };
+                let starts_word = snippet.trigger.chars().next().is_some_and(is_word);
+                !(starts_word && start.chars().next_back().is_some_and(is_word))
+            })
+            .max_by_key(|snippet| snippet.trigger.len())
+            .map(|snippet| {
+                let len = snippet.trigger.chars().count();
+                (cursor - len..cursor, snippet)
+            })
+    }
+
+    // Replaces `range` with the snippet, indented like the line, and goes to its first
+    // tabstop.
+    pub(crate) fn expand(&mut self, range: Range<usize>, body: &str) {
+        let state = self.state();
+        let line = state.buffer.line(state.cursor.row).as_str().to_string();
+        let indent: String = line
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect();
+        let tab = state.indent_unit();
+        let nodes = body::parse(body);
+        let (text, mut stops) = body::expand(&nodes, &indent, &tab, &|name| variable(state, name));
+
+        self.root_mut().edit().complete(range.clone(), &text);
+        for stop in &mut stops {
+            for r in &mut stop.ranges {
+                *r = range.start + r.start..range.start + r.end;
+            }
+        }
+        self.state_mut().snippet = Some(Session {
+            stops,
+            current: 0,
+            fresh: false,
+        });
+        self.enter();
+    }
+
+    // Puts the cursor after the placeholder of the current tabstop and offers its
+    // choices. The session is over at the last one.
+    fn enter(&mut self) {
+        let session = match &mut self.state_mut().snippet {
+            Some(session) => session,
+            None => return,
+        };
+        let primary = session.primary();
+        let stop = session.stops[session.current].clone();
+        session.fresh = !primary.is_empty();
+        if session.is_last() {
+            self.state_mut().snippet = None;
+        }
+        self.root_mut().movement().offset(primary.end);
+        if stop.choices.is_empty() {
+            return;
+        }
+        let candidates = stop
+            .choices
+            .into_iter()
+            .map(|word| Candidate {
+                word,
+                source: Source::Snippet,
+                detail: "choice".to_string(),
+                snippet: None,
+            })
+            .collect();
+        self.state_mut().completion = Some(Completion {
+            start: primary.start,
+            prefix: String::new(),
+            candidates,
+            selected: None,
+        });
+    }
+
+    fn jump(&mut self, forward: bool) {
+        if let Some(session) = &mut self.state_mut().snippet {
+            session.current = match forward {
+                true => session.current + 1,
+                false => session.current.saturating_sub(1),
+            };
+        }
+        self.enter();
+    }
+
+    // Typing over an untouched placeholder replaces it.
+    pub(crate) fn typing(&mut self) {
+        let cursor = self.state().get_cursor_offset();
+        let session = match &mut self.state_mut().snippet {
+            Some(session) => session,
+            None => return,
+        };
+        let fresh = mem::replace(&mut session.fresh, false);
+        let primary = session.primary();
+        if fresh && primary.end == cursor {
+            self.root_mut().edit().replace(primary.clone(), "");
+            self.root_mut().movement().offset(primary.start);
+        }
+    }
+
+    // Mirrors show what is typed at their tabstop. The session is over out of insert
+    // mode.
+    pub(crate) fn update(&mut self) {
+        let session = match &self.state().snippet {
+            Some(session) => session.clone(),
+            None => return,
+        };
+        if !self.state().mode.is_insert() {
+            self.state_mut().snippet = None;
+            return;
+        }
+        let text = self
+            .state()
+            .buffer
+            .slice(session.primary())
+            .as_str()
+            .to_string();
+        let len = text.chars().count();
+        let cursor = self.state().get_cursor_offset();
+        let mut moved = cursor;
+        for i in 1..session.stops[session.current].ranges.len() {
+            // Replacing a mirror moves the ones after it.
+            let range = match &self.state().snippet {
+                Some(session) => session.stops[session.current].ranges[i].clone(),
+                None => return,
+            };
+            if self.state().buffer.slice(range.clone()).as_str() == text {
+                continue;
+            }
+            if range.end <= moved {
+                moved = moved + len - range.len();
+            }
+            self.root_mut().edit().replace(range, &text);
+        }
+        if moved != cursor {
+            self.root_mut().movement().offset(moved);
+        }
+    }
+
+    pub(crate) fn action(&mut self, kind: SnippetKind) {
+        if !self.state().mode.is_insert() {
+            return;
+        }
+        let active = self.state().snippet.is_some();
+        match kind {
+            SnippetKind::Next => match self.trigger() {
+                Some((range, snippet)) => self.expand(range, &snippet.body),
+                None if active => self.jump(true),
+                None => {
+                    let tab = EditKind::InsertChar('\t');
+                    self.root_mut().action(ActionKind::from(tab).once());
+                }
+            },
+            SnippetKind::Prev if active => self.jump(false),
+            SnippetKind::Prev => {}
+        }
+    }
+}
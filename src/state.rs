@@ -1,26 +1,43 @@
+use core::cmp::{max, min};
 use core::ops::Range;
 use std::env::current_dir;
 use std::ffi::OsString;
 use std::fs;
+use std::io::{stdin, ErrorKind, Read};
+use std::path::Path;
+use std::sync::Arc;
 
 use crate::buffer::Buffer;
+use crate::completion::Completion;
+use crate::diagnostic::Diagnostic;
 use crate::edit::EditKind;
+use crate::filetype;
+use crate::fold::{self, Fold};
+use crate::inspect::TreeView;
+use crate::jumplist::JumpList;
+use crate::keymap::Keymap;
+use crate::language::LanguageConfig;
 use crate::mode::Mode;
+use crate::options::{self, OptionDef, OptionValue, Options, Scope, SetArg};
+use crate::picker::Picker;
 use crate::position::Position;
 use crate::selection::{Selection, SelectionKind};
+use crate::snippet::Session;
+use crate::theme::Theme;
 
+use hashbrown::HashMap;
 use termion::terminal_size;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
 pub(super) enum SearchDirection {
+    #[default]
     Forward,
     Reverse,
 }
 
-impl Default for SearchDirection {
-    fn default() -> SearchDirection {
-        SearchDirection::Forward
-    }
+// Falls back to a fixed size when there is no terminal, e.g. in headless mode.
+fn size() -> (u16, u16) {
+    terminal_size().unwrap_or((80, 24))
 }
 
 #[derive(Default, Clone, Debug, PartialEq)]
@@ -29,18 +46,46 @@ pub(super) struct State {
     pub(super) row_offset: usize,
     pub(super) cursor: Position,
     pub(super) max_column: usize,
+    // The other end of the visual selection.
+    pub(super) anchor: Position,
+    // Byte ranges the visual selection was expanded from, for shrinking it back.
+    pub(super) expanded: Vec<Range<usize>>,
+    pub(super) jumps: JumpList,
+    pub(super) folds: Vec<Fold>,
+    pub(super) picker: Picker,
+    // The definitions around the cursor, outermost first.
+    pub(super) symbol_path: Vec<String>,
+    pub(super) diagnostics: Vec<Diagnostic>,
+    // Shown at the cursor until the next key, like a hover.
+    pub(super) popup: Vec<String>,
+    // The insert-mode completion menu, while it is open.
+    pub(super) completion: Option<Completion>,
+    // The snippet whose tabstops are being gone through.
+    pub(super) snippet: Option<Session>,
+    // Shown in a split while `:InspectTree` is on.
+    pub(super) tree_view: Option<TreeView>,
     pub(super) mode: Mode,
+    // Where <C-o> left insert mode for one normal command.
+    pub(super) one_shot: Option<Position>,
     pub(super) yanked: String,
     pub(super) size: (u16, u16),
     pub(super) buffer: Buffer,
     pub(super) prev_edit: Option<(EditKind, usize)>,
     pub(super) search_pattern: String,
     pub(super) search_direction: SearchDirection,
+    pub(super) read_only: bool,
+    pub(super) message: Option<String>,
+    pub(super) theme: Arc<Theme>,
+    pub(super) keymap: Arc<Keymap>,
+    pub(super) global_options: Arc<Options>,
+    pub(super) window_options: Options,
+    pub(super) buffer_options: Options,
+    pub(super) languages: Arc<HashMap<String, LanguageConfig>>,
 }
 
 impl State {
     pub(super) fn new() -> Self {
-        let size = terminal_size().unwrap();
+        let size = size();
 
         Self {
             size,
@@ -50,8 +95,14 @@ impl State {
     }
 
     pub(super) fn open_file(filename: &str) -> Self {
-        let size = terminal_size().unwrap();
-        let s = fs::read_to_string(filename).unwrap();
+        let size = size();
+        // A file that cannot be read opens empty and read-only, so that writing does not
+        // lose it.
+        let (s, error) = match fs::read_to_string(filename) {
+            Ok(s) => (s, None),
+            Err(e) if e.kind() == ErrorKind::NotFound => ("\n".to_string(), None),
+            Err(e) => ("\n".to_string(), Some(e)),
+        };
         let buffer = Buffer::from(s.as_str());
         let path = current_dir().unwrap().join(filename).into_os_string();
 
@@ -59,10 +110,128 @@ impl State {
             size,
             buffer,
             path: Some(path),
+            read_only: error.is_some(),
+            message: error.map(|e| format!("\"{}\" {}", filename, e)),
+            ..Default::default()
+        }
+    }
+
+    // Input that is not UTF-8 opens empty and read-only, like a file that cannot be read.
+    pub(super) fn from_stdin() -> Self {
+        let size = size();
+        let mut s = String::new();
+        let error = stdin().read_to_string(&mut s).err();
+        if error.is_some() {
+            s.clear();
+        }
+        if !s.ends_with('\n') {
+            s.push('\n');
+        }
+
+        Self {
+            size,
+            buffer: s.as_str().into(),
+            read_only: error.is_some(),
+            message: error.map(|e| format!("stdin: {}", e)),
             ..Default::default()
         }
     }
 
+    // Takes over the state that is shared between buffers from `other`. The message of this
+    // buffer, like why it opened read-only, wins over the one on screen.
+    pub(super) fn with_globals_of(self, other: &State) -> Self {
+        let message = self.message.clone().or_else(|| other.message.clone());
+        Self {
+            mode: other.mode.clone(),
+            one_shot: other.one_shot,
+            yanked: other.yanked.clone(),
+            size: other.size,
+            prev_edit: other.prev_edit.clone(),
+            search_pattern: other.search_pattern.clone(),
+            search_direction: other.search_direction,
+            message,
+            theme: other.theme.clone(),
+            keymap: other.keymap.clone(),
+            global_options: other.global_options.clone(),
+            languages: other.languages.clone(),
+            ..self
+        }
+    }
+
+    // Modelines win over the file name and the shebang.
+    pub(super) fn detect_filetype(&mut self, path: Option<&str>) {
+        if let Some(filetype) = filetype::detect(path, &self.buffer) {
+            let def = options::find("filetype").unwrap();
+            self.set_option(def, OptionValue::String(filetype), true);
+        }
+        for arg in filetype::modeline(&self.buffer) {
+            match options::parse_arg(&arg) {
+                Ok(SetArg::Assign(def, value)) => self.set_option(def, value, true),
+                _ => tracing::debug!("ignored modeline argument: {}", arg),
+            }
+        }
+    }
+
+    pub(super) fn filetype(&self) -> String {
+        self.buffer_options.string("filetype")
+    }
+
+    pub(super) fn language_config(&self) -> LanguageConfig {
+        let filetype = self.filetype();
+        let builtin = LanguageConfig::builtin(&filetype);
+        match self.languages.get(&filetype) {
+            Some(config) => builtin.merge(config),
+            None => builtin,
+        }
+    }
+
+    // Language settings sit between the global and the buffer-local values.
+    pub(super) fn options(&self) -> Options {
+        self.global_options
+            .merge(&self.window_options)
+            .merge(&self.language_config().options())
+            .merge(&self.buffer_options)
+    }
+
+    // `:set` changes the global value too, `:setlocal` only the local one.
+    pub(super) fn set_option(&mut self, def: &OptionDef, value: OptionValue, local: bool) {
+        if def.scope == Scope::Global || (!local && def.scope != Scope::BufferOnly) {
+            Arc::make_mut(&mut self.global_options).set(def, value.clone());
+        }
+        match def.scope {
+            Scope::Buffer | Scope::BufferOnly => self.buffer_options.set(def, value),
+            Scope::Window => self.window_options.set(def, value),
+            Scope::Global => {}
+        }
+    }
+
+    // One level of indent, a tab unless 'expandtab' is set.
+    pub(super) fn indent_unit(&self) -> String {
+        let options = self.options();
+        match options.bool("expandtab") {
+            true => " ".repeat(options.number("shiftwidth")),
+            false => "\t".to_string(),
+        }
+    }
+
+    pub(super) fn file_name(&self) -> String {
+        match &self.path {
+            Some(p) => {
+                let path = Path::new(p);
+                let current = current_dir().unwrap();
+                path.strip_prefix(current)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .to_string()
+            }
+            None => "[No Name]".to_string(),
+        }
+    }
+
+    pub(super) fn textarea_height(&self) -> usize {
+        max(1, self.size.1.saturating_sub(2) as usize)
+    }
+
     pub(super) fn get_cursor_offset(&self) -> usize {
         self.buffer.get_offset_by_position(self.cursor)
     }
@@ -96,22 +265,19 @@ impl State {
 
         use SelectionKind::*;
         match s.kind {
-            Left => {
-                unimplemented!();
-                // self.store.do_send(store::CursorLeft(cmd.count)).unwrap();
-            }
+            Left => cursor_offset - min(s.count, self.cursor.col)..cursor_offset,
             Down => {
-                unimplemented!();
-                // self.store.do_send(store::CursorDown(cmd.count)).unwrap();
+                let closed = self.closed_folds();
+                let line_count = self.buffer.count_lines();
+                let end = fold::next_line(&closed, self.cursor.row, s.count, line_count);
+                self.shown_lines(self.cursor.row, end)
             }
             Up => {
-                unimplemented!();
-                // self.store.do_send(store::CursorUp(cmd.count)).unwrap();
-            }
-            Right => {
-                unimplemented!();
-                // self.store.do_send(store::CursorRight(cmd.count)).unwrap();
+                let closed = self.closed_folds();
+                let start = fold::prev_line(&closed, self.cursor.row, s.count);
+                self.shown_lines(start, self.cursor.row)
             }
+            Right => cursor_offset..min(cursor_offset + s.count, self.current_line_remain().end),
             WordEnd => {
                 let count = self.count_word_end();
                 cursor_offset..cursor_offset + count
@@ -129,8 +295,63 @@ impl State {
                 let back_count = self.count_word_start();
                 cursor_offset - back_count..cursor_offset + end_count
             }
-            Line => self.current_line(),
+            Line => self.shown_lines(self.cursor.row, self.cursor.row),
             LineRemain => self.current_line_remain(),
+            Visual => self.visual_range(),
+            Motion(_) => unreachable!("motions are measured by the store"),
+        }
+    }
+
+    // The chars of the lines shown from the line of `from` to the line of `to`, so that
+    // linewise operators take closed folds whole.
+    fn shown_lines(&self, from: usize, to: usize) -> Range<usize> {
+        let closed = self.closed_folds();
+        let start = fold::line(&closed, from).start;
+        let end = fold::line(&closed, to).end;
+        self.buffer.line_range(start).start..self.buffer.line_range(end - 1).end
+    }
+
+    pub(super) fn closed_folds(&self) -> Vec<Range<usize>> {
+        fold::closed(&self.folds)
+    }
+
+    // Jumps and edits show where they go.
+    pub(super) fn open_folds_at_cursor(&mut self) {
+        let row = self.cursor.row;
+        for fold in &mut self.folds {
+            if fold.rows.contains(&row) {
+                fold.closed = false;
+            }
         }
     }
+
+    // The chars from the anchor to the cursor, both included.
+    pub(super) fn visual_range(&self) -> Range<usize> {
+        let len = self.buffer.0.len_chars();
+        let anchor = min(self.buffer.get_offset_by_position(self.anchor), len);
+        let cursor = min(self.get_cursor_offset(), len);
+        min(anchor, cursor)..min(max(anchor, cursor) + 1, len)
+    }
+
+    // The visual selection, or the char under the cursor, in bytes.
+    pub(super) fn selected_bytes(&self) -> Range<usize> {
+        let range = match self.mode.is_visual() {
+            true => self.visual_range(),
+            false => {
+                let len = self.buffer.0.len_chars();
+                let cursor = min(self.get_cursor_offset(), len);
+                cursor..min(cursor + 1, len)
+            }
+        };
+        self.buffer.0.char_to_byte(range.start)..self.buffer.0.char_to_byte(range.end)
+    }
+
+    // Selects the chars in `bytes`, with the cursor on the last one.
+    pub(super) fn select_bytes(&mut self, bytes: Range<usize>) {
+        let start = self.buffer.0.byte_to_char(bytes.start);
+        let end = self.buffer.0.byte_to_char(bytes.end);
+        self.anchor = self.buffer.get_position_by_offset(start);
+        self.cursor = self.buffer.get_position_by_offset(max(start + 1, end) - 1);
+        self.max_column = self.cursor.col;
+    }
 }
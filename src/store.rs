@@ -1,19 +1,45 @@
 use crate::action::{Action, ActionKind};
-use crate::compute::Reactor;
-use crate::edit::{EditKind, EditStore};
-use crate::highlight::Highlighter;
+use crate::backend::Style;
+use crate::buffer::Buffer;
+use crate::cmdline::CmdRange;
+use crate::completion::CompletionStore;
+use crate::compute::{MatchPositions, Reactor, RowOffsetView, WrapScan};
+use crate::config::NeuConfig;
+use crate::edit::{EditKind, EditStore, Inserted};
+use crate::fold::{self, FoldStore};
+use crate::grammar;
+use crate::highlight::{Highlighter, Parsed};
 use crate::history::{History, Record};
-use crate::language::Language;
+use crate::keymap::Keymap;
+use crate::lsp::{self, Clients, LspKind, LspStore};
 use crate::mode::{InsertKind, Mode};
-use crate::movement::MovementStore;
+use crate::movement::{MovementKind, MovementStore};
+use crate::options::{self, OptionValue, SetArg};
+use crate::picker::{Choice, Item, Picker};
+use crate::position::Position;
 use crate::renderer::Renderer;
+use crate::search;
+use crate::selection::{Selection, SelectionKind};
+use crate::snippet::SnippetStore;
 use crate::state::State;
+use crate::tags::Tag;
+use crate::target::{Jump, Source, Target};
+use crate::theme::Theme;
 
+use anyhow::{anyhow, Result};
 use core::cmp::{max, min};
+use core::ops::Range;
 use flume::Receiver;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use regex::Regex;
+use smol::future;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
 use std::mem;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
 
 pub(crate) trait Store {
     fn state(&self) -> &State {
@@ -24,30 +50,94 @@ pub(crate) trait Store {
         &mut self.root_mut().state
     }
 
-    fn highlighter(&self) -> &Highlighter {
-        &self.root().highlighter
-    }
-
     fn highlighter_mut(&mut self) -> &mut Highlighter {
-        &mut self.root_mut().highlighter
+        self.root_mut().highlighter_mut()
     }
 
     fn history_mut(&mut self) -> &mut History {
         &mut self.root_mut().history
     }
 
-    fn reactor(&self) -> &Reactor {
-        &self.root().reactor
-    }
-
     fn reactor_mut(&mut self) -> &mut Reactor {
         &mut self.root_mut().reactor
     }
 
+    fn clients(&self) -> &Clients {
+        &self.root().clients
+    }
+
+    fn clients_mut(&mut self) -> &mut Clients {
+        &mut self.root_mut().clients
+    }
+
     fn root(&self) -> &RootStore;
     fn root_mut(&mut self) -> &mut RootStore;
 }
 
+enum Event {
+    Action(Action),
+    Parsed(Parsed),
+    Lsp(lsp::Event),
+}
+
+struct Background {
+    state: State,
+    highlighter: Highlighter,
+    history: History,
+}
+
+impl Background {
+    fn open(target: &Target, read_only: bool) -> Self {
+        let mut state = match &target.source {
+            Source::Path(filename) => State::open_file(filename),
+            Source::Stdin => State::from_stdin(),
+        };
+        let path = match &target.source {
+            Source::Path(filename) => Some(filename.as_str()),
+            Source::Stdin => None,
+        };
+        state.detect_filetype(path);
+        state.read_only |= read_only;
+        let highlighter = Highlighter::new(&state.filetype());
+
+        Self {
+            state,
+            highlighter,
+            history: History::default(),
+        }
+    }
+}
+
+// Pipes `input` through `sh -c command`.
+fn run_formatter(command: &str, input: String) -> Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer.join().unwrap()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let line = stderr.lines().next().unwrap_or_default().to_string();
+        return Err(anyhow!("exited with {}: {}", output.status, line));
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+// Whether `path` and `other` are the same file.
+fn same_file(path: &Path, other: &Path) -> bool {
+    match (fs::canonicalize(path), fs::canonicalize(other)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => path == other,
+    }
+}
+
 pub(super) struct RootStore {
     pub(crate) state: State,
     renderer: Renderer,
@@ -55,12 +145,19 @@ pub(super) struct RootStore {
     rx: Receiver<Action>,
     pub(crate) reactor: Reactor,
     pub(crate) history: History,
+    pub(crate) clients: Clients,
+    buffers: Vec<Option<Background>>,
+    current_buffer: usize,
+    status: i32,
+    // Failed commands make a headless run exit with 1, as scripts rely on.
+    headless: bool,
 }
 
 impl RootStore {
     pub(super) fn new(rx: Receiver<Action>, renderer: Renderer) -> Self {
-        let state = State::new();
-        let highlighter = Highlighter::new(&state.buffer, &Language::Unknown);
+        let mut state = State::new();
+        state.size = renderer.size();
+        let highlighter = Highlighter::new("");
 
         let mut store = Self {
             rx,
@@ -69,46 +166,313 @@ impl RootStore {
             state,
             history: History::default(),
             reactor: Reactor::new(),
+            clients: Clients::new(),
+            buffers: vec![None],
+            current_buffer: 0,
+            status: 0,
+            headless: false,
         };
         store.refresh();
         store
     }
 
-    pub(super) fn open_file(filename: &str, rx: Receiver<Action>, renderer: Renderer) -> Self {
-        let state = State::open_file(filename);
-        let lang = Language::from_path(filename);
-        let highlighter = Highlighter::new(&state.buffer, &lang);
+    pub(super) fn open(
+        targets: &[Target],
+        read_only: bool,
+        rx: Receiver<Action>,
+        renderer: Renderer,
+    ) -> Self {
+        if targets.is_empty() {
+            return Self::new(rx, renderer);
+        }
+
+        let mut buffers: Vec<_> = targets
+            .iter()
+            .map(|t| Some(Background::open(t, read_only)))
+            .collect();
+        let mut first = buffers[0].take().unwrap();
+        first.state.size = renderer.size();
 
         let mut store = Self {
             rx,
             renderer,
-            highlighter,
-            history: History::default(),
+            highlighter: first.highlighter,
+            history: first.history,
             reactor: Reactor::new(),
-            state,
+            clients: Clients::new(),
+            state: first.state,
+            buffers,
+            current_buffer: 0,
+            status: 0,
+            headless: false,
         };
+
+        for (i, target) in targets.iter().enumerate() {
+            if let Some(jump) = &target.jump {
+                store.switch_buffer(i);
+                store.jump(jump.clone());
+            }
+        }
+        store.switch_buffer(0);
         store.refresh();
         store
     }
 
-    pub(super) async fn run(&mut self) {
+    pub(super) fn configure(&mut self, config: &NeuConfig) {
+        let mut errors = Vec::new();
+        if let Some(name) = &config.theme {
+            match Theme::load(name) {
+                Ok(theme) => self.state.theme = Arc::new(theme),
+                Err(e) => errors.push(format!("theme {}: {}", name, e)),
+            }
+        }
+
+        // These are the defaults, which the settings of the languages and modelines go over.
+        for (name, value) in &config.options {
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                value => value.to_string(),
+            };
+            let result = options::find(name)
+                .ok_or_else(|| anyhow!("E518: Unknown option: {}", name))
+                .and_then(|def| Ok((def, def.parse_value(&value)?)));
+            match result {
+                Ok((def, value)) => Arc::make_mut(&mut self.state.global_options).set(def, value),
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+
+        let languages = config.language.clone().into_iter().collect();
+        self.state.languages = Arc::new(languages);
+
+        let (keymap, keymap_errors) = Keymap::from_config(&config.keymap);
+        self.state.keymap = Arc::new(keymap);
+        errors.extend(keymap_errors);
+
+        let mut messages = Vec::new();
+        if !errors.is_empty() {
+            messages.push(format!("config.toml: {}", errors.join(", ")));
+        }
+        messages.extend(grammar::load_errors().iter().cloned());
+        if !messages.is_empty() {
+            self.state.message = Some(messages.join(", "));
+        }
+        self.refresh();
+    }
+
+    pub(super) fn set_headless(&mut self) {
+        self.headless = true;
+    }
+
+    pub(super) async fn run(&mut self) -> i32 {
         loop {
-            let action = smol::block_on(async { self.rx.recv_async().await.unwrap() });
+            let parsed = self.highlighter.parsed();
+            let lsp = self.clients.events();
+            let event = smol::block_on(future::or(
+                async { Event::Action(self.rx.recv_async().await.unwrap()) },
+                future::or(
+                    async { Event::Parsed(parsed.recv_async().await.unwrap()) },
+                    async { Event::Lsp(lsp.recv_async().await.unwrap()) },
+                ),
+            ));
+            let action = match event {
+                Event::Action(action) => action,
+                Event::Parsed(parsed) => {
+                    self.highlighter.receive(parsed);
+                    self.refresh();
+                    continue;
+                }
+                Event::Lsp(event) => {
+                    self.lsp().receive(event);
+                    self.refresh();
+                    continue;
+                }
+            };
+            // Reading the state changes nothing on screen.
+            let read_only = matches!(action.kind, ActionKind::GetState(_));
+            if !read_only {
+                self.state.popup.clear();
+            }
+            // The completion menu stays while the word it completes is typed.
+            let completes = matches!(
+                action.kind,
+                ActionKind::Completion(_) | ActionKind::Edit(EditKind::InsertChar(_))
+            );
+            if !read_only && !completes {
+                self.state.completion = None;
+            }
+            // A normal command ends with one of these.
+            let ends_command = matches!(
+                action.kind,
+                ActionKind::ClearCmd | ActionKind::IntoNormalMode
+            );
             if !self.action(action) {
                 break;
             }
-            self.refresh();
+            if ends_command {
+                self.resume_insert();
+            }
+            if !read_only {
+                self.snippet().update();
+                self.refresh();
+            }
         }
+        self.status
     }
 
+    fn jump(&mut self, jump: Jump) {
+        match jump {
+            Jump::Line(n) => {
+                self.action(ActionKind::from(MovementKind::Line).nth(n));
+            }
+            Jump::LineCol(n, col) => {
+                self.action(ActionKind::from(MovementKind::Line).nth(n));
+                self.movement().right(col.saturating_sub(1));
+            }
+            Jump::Search(pattern) => {
+                self.state.search_pattern = pattern;
+                self.reactor.load_state(self.state.clone());
+                let matches = self.reactor.compute::<MatchPositions>().0;
+                let wrap = self.reactor.compute::<WrapScan>().0;
+                if let Some(pos) = search::get_next(&self.state.cursor, &matches, wrap) {
+                    self.state.cursor = *pos;
+                }
+            }
+        }
+        self.scroll();
+    }
+
+    pub(crate) fn current_buffer(&self) -> usize {
+        self.current_buffer
+    }
+
+    pub(crate) fn other_buffers(&self) -> impl Iterator<Item = &Buffer> {
+        self.buffers.iter().flatten().map(|b| &b.state.buffer)
+    }
+
+    // The buffer of the file at `path`, opened if it is not yet.
+    pub(crate) fn open_buffer(&mut self, path: &Path) -> usize {
+        let is_open = |state: &State| {
+            state
+                .path
+                .as_ref()
+                .is_some_and(|p| same_file(Path::new(p), path))
+        };
+        if is_open(&self.state) {
+            return self.current_buffer;
+        }
+        let open = self
+            .buffers
+            .iter()
+            .position(|b| b.as_ref().is_some_and(|b| is_open(&b.state)));
+        if let Some(i) = open {
+            return i;
+        }
+        let target = Target {
+            source: Source::Path(path.to_string_lossy().to_string()),
+            jump: None,
+        };
+        self.buffers.push(Some(Background::open(&target, false)));
+        self.buffers.len() - 1
+    }
+
+    // The server is told about the changes of the buffer before it goes to the background.
+    pub(crate) fn switch_buffer(&mut self, i: usize) {
+        if i == self.current_buffer || i >= self.buffers.len() {
+            return;
+        }
+        self.lsp().sync();
+
+        let next = self.buffers[i].take().unwrap();
+        let state = next.state.with_globals_of(&self.state);
+        let mut prev = Background {
+            state: mem::replace(&mut self.state, state),
+            highlighter: mem::replace(&mut self.highlighter, next.highlighter),
+            history: mem::replace(&mut self.history, next.history),
+        };
+        // Its message has been shown, and went on to the next buffer.
+        prev.state.message = None;
+        self.buffers[self.current_buffer] = Some(prev);
+        self.current_buffer = i;
+    }
+
+    // Whether the buffer was written.
+    fn write_buffer(&mut self, path: &OsStr) -> bool {
+        if self.state.read_only {
+            let message = "E45: 'readonly' option is set".to_string();
+            self.action(ActionKind::Fail(message).once());
+            return false;
+        }
+        let written = File::create(path).and_then(|f| {
+            let mut w = BufWriter::new(f);
+            write!(w, "{}", self.state.buffer.as_str())?;
+            w.flush()
+        });
+        if let Err(e) = written {
+            let message = format!("E212: Can't open file for writing: {}", e);
+            self.action(ActionKind::Fail(message).once());
+            return false;
+        }
+        true
+    }
+
+    fn save(&mut self) -> bool {
+        let path = match self.state.path.clone() {
+            Some(path) => path,
+            None => {
+                let message = "E32: No file name".to_string();
+                self.action(ActionKind::Fail(message).once());
+                return false;
+            }
+        };
+        if !self.write_buffer(&path) {
+            return false;
+        }
+        self.lsp().sync();
+        self.lsp().did_save();
+        true
+    }
+
+    // A language server formats when no formatter is set.
+    fn format(&mut self) {
+        let formatter = match self.state.language_config().formatter {
+            Some(formatter) => formatter,
+            None if self.lsp().attached() => {
+                self.lsp().action(LspKind::Format);
+                return;
+            }
+            None => {
+                let filetype = self.state.filetype();
+                let message = format!("No formatter for filetype '{}'", filetype);
+                self.action(ActionKind::Fail(message).once());
+                return;
+            }
+        };
+        let input = self.state.buffer.as_str().to_string();
+        match run_formatter(&formatter, input.clone()) {
+            Ok(output) if output != input => self.edit().replace_all(&output),
+            Ok(_) => {}
+            Err(e) => {
+                let message = format!("{}: {}", formatter, e);
+                self.action(ActionKind::Fail(message).once());
+            }
+        }
+    }
+
+    // How far it scrolls depends on how the lines wrap, which is worked out for the screen.
     fn scroll(&mut self) {
-        let state = &mut self.state;
-        let textarea_row = (state.size.1 - 2) as usize;
+        self.reactor.load_state(self.state.clone());
+        self.state.row_offset = self.reactor.compute::<RowOffsetView>().0;
+    }
 
-        state.row_offset = max(
-            min(state.cursor.row, state.row_offset),
-            (state.cursor.row + 1).saturating_sub(textarea_row),
-        );
+    // The cursor sits on the first row of a closed fold, unless it is typing in there.
+    fn coerce_row(&mut self) {
+        if self.state.mode.is_insert() {
+            self.state.open_folds_at_cursor();
+            return;
+        }
+        let closed = self.state.closed_folds();
+        self.state.cursor.row = fold::line(&closed, self.state.cursor.row).start;
     }
 
     fn coerce_col(&mut self) {
@@ -124,63 +488,286 @@ impl RootStore {
         self.state.cursor.col = min(self.state.cursor.col, max_col);
     }
 
+    // Leaves insert mode, the insert being what `.` repeats from then on.
+    fn finish_insert(&mut self) -> bool {
+        let mode = mem::replace(&mut self.state.mode, Mode::Normal(String::new()));
+        let (k, s) = match mode {
+            Mode::Insert(k, s) => (k, s),
+            _ => return false,
+        };
+        let edit = match k {
+            InsertKind::Insert(p) => EditKind::InsertString(p, s),
+            InsertKind::Edit(selection) => EditKind::Edit(selection, s),
+        };
+        self.state.prev_edit = Some((edit, 1));
+        true
+    }
+
+    // Goes back to insert mode once the command after <C-o> is done.
+    fn resume_insert(&mut self) {
+        let cursor = match self.state.one_shot {
+            Some(cursor) => cursor,
+            None => return,
+        };
+        match &self.state.mode {
+            Mode::Normal(cmd) if cmd.is_empty() => {}
+            Mode::Insert(_, _) => {
+                self.state.one_shot = None;
+                return;
+            }
+            _ => return,
+        }
+        self.state.one_shot = None;
+        // Past the end of the line is where the cursor was if the command left it there.
+        let len = self.state.buffer.row_len(cursor.row);
+        let stayed = Position {
+            row: cursor.row,
+            col: len.saturating_sub(1),
+        };
+        if cursor.col == len && self.state.cursor == stayed {
+            self.state.cursor = cursor;
+        }
+        self.history.push(self.create_record());
+        self.state.mode = Mode::Insert(InsertKind::Insert(None), Inserted::default());
+    }
+
+    // The highlighter, with the 'maxhighlight' of the current buffer.
+    fn highlighter_mut(&mut self) -> &mut Highlighter {
+        let limit = self.state.options().number("maxhighlight");
+        self.highlighter.set_limit(limit);
+        &mut self.highlighter
+    }
+
     pub(crate) fn create_record(&self) -> Record {
         Record {
             buffer: self.state.buffer.clone(),
             cursor: self.state.cursor,
+            folds: self.state.folds.clone(),
             tree: self.highlighter.tree().cloned(),
         }
     }
 
     fn refresh(&mut self) {
+        self.coerce_row();
         self.scroll();
         self.coerce_col();
+        let at = self.state.buffer.get_byte_by_position(self.state.cursor);
+        let tags = self.highlighter.tags_at(&self.state.buffer, at);
+        self.state.symbol_path = tags.iter().map(Tag::label).collect();
+        self.lsp().attach();
+        self.lsp().sync();
+        let mut diagnostics = self.highlighter.diagnostics(&self.state.buffer);
+        diagnostics.extend(self.lsp().diagnostics());
+        diagnostics.sort_by_key(|d| (d.start.row, d.start.col));
+        self.state.diagnostics = diagnostics;
+        if self.state.tree_view.is_some() {
+            self.state.tree_view = Some(self.highlighter.tree_view(at).unwrap_or_default());
+        }
         self.reactor.load_state(self.state.clone());
         let highlights = self.highlighter.update(&mut self.reactor);
         self.renderer.render(&mut self.reactor, highlights);
     }
 
-    fn edit(&mut self) -> EditStore {
+    // Motions are run to see where they go, the rest is measured by the state.
+    pub(crate) fn measure_selection(&mut self, selection: Selection) -> Range<usize> {
+        let movement = match selection.kind {
+            SelectionKind::Motion(movement) => movement,
+            _ => return self.state.measure_selection(selection),
+        };
+        let (cursor, max_column) = (self.state.cursor, self.state.max_column);
+        let start = self.state.get_cursor_offset();
+        self.movement().action(movement.clone(), selection.count);
+        let end = self.state.get_cursor_offset();
+        self.state.cursor = cursor;
+        self.state.max_column = max_column;
+
+        let len = self.state.buffer.0.len_chars();
+        match movement.is_inclusive() {
+            true => min(start, end)..min(max(start, end) + 1, len),
+            false => min(start, end)..max(start, end),
+        }
+    }
+
+    // Lists the definitions of the buffer, nested ones indented under the others.
+    fn symbols(&mut self) {
+        let buffer = self.state.buffer.clone();
+        let tags = self.highlighter_mut().tags(&buffer);
+        if tags.is_empty() {
+            self.action(ActionKind::Fail("No symbols found".to_string()).once());
+            return;
+        }
+        let items = tags
+            .into_iter()
+            .map(|tag| Item {
+                label: format!("{}{}", "  ".repeat(tag.depth), tag.label()),
+                choice: Choice::Jump(None, buffer.get_position_by_byte(tag.name_range.start)),
+                text: tag.name,
+            })
+            .collect();
+        self.state.picker = Picker::new("symbols", items);
+        self.state.mode = Mode::Picker;
+    }
+
+    // Lists the nodes and the highlight captures at the cursor, marking the captures the
+    // theme has no style for.
+    fn inspect(&mut self) {
+        let buffer = self.state.buffer.clone();
+        let at = buffer.get_byte_by_position(self.state.cursor);
+        let (kinds, captures) = match self.highlighter_mut().inspect(&buffer, at) {
+            Some(inspected) => inspected,
+            None => {
+                let message = format!("No syntax tree for filetype '{}'", self.state.filetype());
+                self.action(ActionKind::Fail(message).once());
+                return;
+            }
+        };
+        let captures: Vec<_> = captures
+            .iter()
+            .map(
+                |name| match self.state.theme.get(name) == Style::default() {
+                    true => format!("@{} (no style)", name),
+                    false => format!("@{}", name),
+                },
+            )
+            .collect();
+        let captures = match captures.is_empty() {
+            true => "no captures".to_string(),
+            false => captures.join(" "),
+        };
+        self.state.message = Some(format!("{}  {}", kinds.join(" > "), captures));
+    }
+
+    fn pick(&mut self) {
+        self.state.mode = Mode::Normal(String::new());
+        let item = match self.state.picker.current() {
+            Some(item) => item.clone(),
+            None => return,
+        };
+        match item.choice {
+            Choice::Jump(path, position) => self.jump_to_location(path.as_deref(), position),
+            Choice::CodeAction(action) => {
+                let language = self.state.filetype();
+                self.lsp().run_code_action(&language, &action, false);
+            }
+        }
+    }
+
+    // Jumps within the buffer are remembered, the file at `path` is switched to.
+    pub(crate) fn jump_to_location(&mut self, path: Option<&Path>, position: Position) {
+        if let Some(path) = path {
+            let i = self.open_buffer(path);
+            self.switch_buffer(i);
+        }
+        let cursor = self.state.cursor;
+        self.movement().jump_to(position);
+        if path.is_none() && self.state.cursor != cursor {
+            self.state.jumps.push(cursor);
+        }
+        self.state.open_folds_at_cursor();
+    }
+
+    pub(crate) fn completion(&mut self) -> CompletionStore<'_> {
+        CompletionStore::new(self)
+    }
+
+    pub(crate) fn edit(&mut self) -> EditStore<'_> {
         EditStore::new(self)
     }
 
-    pub(crate) fn movement(&mut self) -> MovementStore {
+    pub(crate) fn lsp(&mut self) -> LspStore<'_> {
+        LspStore::new(self)
+    }
+
+    fn fold(&mut self) -> FoldStore<'_> {
+        FoldStore::new(self)
+    }
+
+    pub(crate) fn snippet(&mut self) -> SnippetStore<'_> {
+        SnippetStore::new(self)
+    }
+
+    pub(crate) fn movement(&mut self) -> MovementStore<'_> {
         MovementStore::new(self)
     }
 
     pub(crate) fn action(&mut self, action: Action) -> bool {
         use ActionKind::*;
         match action.kind {
-            Movement(m) => self.movement().action(m, action.count),
+            Movement(MovementKind::Definition) if self.lsp().attached() => {
+                self.lsp().action(LspKind::Definition)
+            }
+            Movement(m) => {
+                let cursor = self.state.cursor;
+                let jump = m.is_jump();
+                self.movement().action(m, action.count);
+                if jump && self.state.cursor != cursor {
+                    self.state.jumps.push(cursor);
+                    self.state.open_folds_at_cursor();
+                }
+            }
+            Fold(f) => self.fold().action(f),
+            Lsp(l) => self.lsp().action(l),
+            Completion(c) => self.completion().action(c, action.count),
+            Snippet(s) => self.snippet().action(s),
+            Edit(EditKind::InsertChar(c)) if self.state.mode.is_insert() => {
+                self.snippet().typing();
+                self.edit().action(EditKind::InsertChar(c), action.count);
+                self.completion().typed(c);
+            }
             Edit(e) => self.edit().action(e, action.count),
             IntoNormalMode => {
-                let mode = mem::replace(&mut self.state.mode, Mode::Normal(String::new()));
-
-                if let Mode::Insert(k, s) = mode {
+                if self.finish_insert() {
                     self.movement().left(1);
-                    let edit = match k {
-                        InsertKind::Insert(p) => EditKind::InsertString(p, s),
-                        InsertKind::Edit(selection) => EditKind::Edit(selection, s),
-                    };
-                    self.state.prev_edit = Some((edit, 1));
                 }
             }
+            IntoOneShotMode => {
+                let cursor = self.state.cursor;
+                if self.finish_insert() {
+                    self.state.one_shot = Some(cursor);
+                }
+            }
+            InsertMovement(m) => {
+                let untouched = matches!(
+                    &self.state.mode,
+                    Mode::Insert(InsertKind::Insert(_), s) if s.is_empty()
+                );
+                // What was typed before moving is an undo step and what `.` repeats.
+                if self.state.mode.is_insert() && !untouched {
+                    self.finish_insert();
+                    self.history.push(self.create_record());
+                    self.state.mode = Mode::Insert(InsertKind::Insert(None), Inserted::default());
+                }
+                self.movement().action(m, action.count);
+            }
             IntoInsertMode(m) => {
+                self.state.message = None;
                 if let Some(m) = &m {
                     self.movement().action(m.clone(), 1);
                 }
                 self.history.push(self.create_record());
-                self.state.mode = Mode::Insert(InsertKind::Insert(m), String::new());
+                self.state.mode = Mode::Insert(InsertKind::Insert(m), Inserted::default());
             }
             IntoEditMode(selection) => {
+                self.state.message = None;
                 self.history.push(self.create_record());
                 self.edit().remove_selection(&selection, 1);
-                self.state.mode = Mode::Insert(InsertKind::Edit(selection), String::new());
+                self.state.mode = Mode::Insert(InsertKind::Edit(selection), Inserted::default());
+            }
+            IntoVisualMode => {
+                self.state.message = None;
+                self.state.anchor = self.state.cursor;
+                self.state.expanded.clear();
+                self.state.mode = Mode::Visual(String::new());
             }
+            SelectNode => self.movement().select_node(),
+            ExpandSelection => self.movement().expand_selection(),
+            ShrinkSelection => self.movement().shrink_selection(),
             IntoCmdLineMode => {
+                self.state.message = None;
                 self.state.mode = Mode::CmdLine(String::new());
             }
             IntoSearchMode(d) => {
+                self.state.message = None;
                 self.action(ClearSearch.once());
                 self.state.search_direction = d;
                 self.state.mode = Mode::Search;
@@ -189,33 +776,38 @@ impl RootStore {
                 self.state.yanked = b;
             }
             ClearCmd => match &mut self.state.mode {
-                Mode::Normal(cmd) | Mode::CmdLine(cmd) => {
+                Mode::Normal(cmd) | Mode::Visual(cmd) | Mode::CmdLine(cmd) => {
                     cmd.clear();
                 }
                 _ => (),
             },
             PushCmd(c) => match &mut self.state.mode {
-                Mode::Normal(cmd) | Mode::CmdLine(cmd) => {
+                Mode::Normal(cmd) | Mode::Visual(cmd) | Mode::CmdLine(cmd) => {
                     cmd.push(c);
                 }
                 _ => (),
             },
             PushCmdStr(s) => match &mut self.state.mode {
-                Mode::Normal(cmd) | Mode::CmdLine(cmd) => {
+                Mode::Normal(cmd) | Mode::Visual(cmd) | Mode::CmdLine(cmd) => {
                     cmd.push_str(&s);
                 }
                 _ => (),
             },
             PopCmd => match &mut self.state.mode {
-                Mode::Normal(cmd) | Mode::CmdLine(cmd) => {
+                Mode::Normal(cmd) | Mode::Visual(cmd) | Mode::CmdLine(cmd) => {
                     cmd.pop();
                 }
                 _ => (),
             },
             Yank(selection) => {
-                let range = self.state.measure_selection(selection);
-                let yank = self.state.buffer.slice(range).as_str().to_string();
+                let visual = selection.kind == SelectionKind::Visual;
+                let range = self.measure_selection(selection);
+                let yank = self.state.buffer.slice(range.clone()).as_str().to_string();
                 self.action(SetYank(yank).once());
+                if visual {
+                    self.movement().offset(range.start);
+                    self.state.mode = Mode::Normal(String::new());
+                }
             }
             Repeat => {
                 if let Some((edit, count)) = self.state.prev_edit.clone() {
@@ -225,25 +817,144 @@ impl RootStore {
             Quit => {
                 return false;
             }
-            Save => {
-                if let Some(p) = &self.state.path {
-                    let f = File::create(p).unwrap();
-                    let mut w = BufWriter::new(f);
-                    write!(w, "{}", self.state.buffer.as_str()).unwrap();
+            WriteQuit => {
+                if self.save() {
+                    return false;
                 }
             }
+            Exit(status) => {
+                self.status = status;
+                return false;
+            }
+            Fail(message) => {
+                if self.headless {
+                    self.status = 1;
+                }
+                self.state.message = Some(message);
+            }
+            Substitute(range, pattern, replacement, global) => {
+                let rows = match range {
+                    CmdRange::Current => self.state.cursor.row..self.state.cursor.row + 1,
+                    CmdRange::All => 0..self.state.buffer.count_lines(),
+                    CmdRange::Lines(start, end) => {
+                        start.saturating_sub(1)..min(end, self.state.buffer.count_lines())
+                    }
+                };
+                match Regex::new(&pattern) {
+                    Ok(re) => {
+                        if !self.edit().substitute(rows, &re, &replacement, global) {
+                            self.action(
+                                Fail(format!("E486: Pattern not found: {}", pattern)).once(),
+                            );
+                        }
+                    }
+                    Err(_) => {
+                        self.action(
+                            Fail(format!("E383: Invalid search string: {}", pattern)).once(),
+                        );
+                    }
+                }
+            }
+            Save => {
+                self.save();
+            }
             WriteOut(filename) => {
-                let f = File::create(filename).unwrap();
-                let mut w = BufWriter::new(f);
-                write!(w, "{}", self.state.buffer.as_str()).unwrap();
+                self.write_buffer(OsStr::new(&filename));
+            }
+            NextBuffer => {
+                let len = self.buffers.len();
+                self.switch_buffer((self.current_buffer + action.count) % len);
+            }
+            PrevBuffer => {
+                let len = self.buffers.len();
+                let count = action.count % len;
+                self.switch_buffer((self.current_buffer + len - count) % len);
+            }
+            SelectBuffer(n) => {
+                self.switch_buffer(n.saturating_sub(1));
             }
             GetState(tx) => {
                 tx.send(self.state.clone()).unwrap();
             }
+            Resize(width, height) => {
+                self.state.size = (width, height);
+            }
+            Map(modes, lhs, rhs, remap) => {
+                let keymap = Arc::make_mut(&mut self.state.keymap);
+                if let Err(e) = keymap.map(&modes, &lhs, &rhs, remap) {
+                    self.action(Fail(format!("E474: Invalid argument: {}", e)).once());
+                }
+            }
+            Unmap(modes, lhs) => {
+                let keymap = Arc::make_mut(&mut self.state.keymap);
+                if keymap.unmap(&modes, &lhs).is_err() {
+                    self.action(Fail(format!("E31: No such mapping: {}", lhs)).once());
+                }
+            }
+            ListMaps(modes) => {
+                let maps = self.state.keymap.list(&modes);
+                self.state.message = match maps.is_empty() {
+                    true => Some("No mapping found".to_string()),
+                    false => Some(maps.join("  ")),
+                };
+            }
+            Set(args, local) => {
+                let filetype = self.state.filetype();
+                let mut shown = Vec::new();
+                if args.is_empty() {
+                    shown = self.state.options().changed();
+                }
+                for arg in &args {
+                    match options::parse_arg(arg) {
+                        Ok(SetArg::Show(def)) => {
+                            shown.push(def.display(&self.state.options().get(def.name)))
+                        }
+                        Ok(SetArg::Assign(def, value)) => self.state.set_option(def, value, local),
+                        Ok(SetArg::Toggle(def)) => {
+                            let value = OptionValue::Bool(!self.state.options().bool(def.name));
+                            self.state.set_option(def, value, local);
+                        }
+                        Err(e) => {
+                            self.action(Fail(e.to_string()).once());
+                            break;
+                        }
+                    }
+                }
+                if !shown.is_empty() {
+                    self.state.message = Some(shown.join("  "));
+                }
+                if self.state.filetype() != filetype {
+                    self.highlighter = Highlighter::new(&self.state.filetype());
+                }
+            }
+            Format => self.format(),
+            Grammars => {
+                self.state.message = Some(grammar::list().join("  "));
+            }
+            Rename(name) if self.lsp().attached() => self.lsp().action(LspKind::Rename(name)),
+            Rename(name) => self.edit().rename(&name),
+            ColorScheme(None) => {
+                self.state.message = Some(self.state.theme.name.clone());
+            }
+            ColorScheme(Some(name)) => match Theme::load(&name) {
+                Ok(theme) => self.state.theme = Arc::new(theme),
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    let message = match e.downcast_ref::<io::Error>() {
+                        Some(e) if e.kind() == ErrorKind::NotFound => {
+                            format!("E185: Cannot find color scheme '{}'", name)
+                        }
+                        _ => format!("theme {}: {}", name, e),
+                    };
+                    self.action(Fail(message).once());
+                }
+            },
             Undo => {
                 if let Some(record) = self.history.undo(self.create_record(), action.count) {
+                    self.lsp().record_change(None, "");
                     self.state.cursor = record.cursor;
                     self.state.buffer = record.buffer;
+                    self.state.folds = record.folds;
                     if let Some(tree) = record.tree {
                         self.highlighter.set_tree(tree);
                     }
@@ -251,8 +962,10 @@ impl RootStore {
             }
             Redo => {
                 if let Some(record) = self.history.redo(self.create_record(), action.count) {
+                    self.lsp().record_change(None, "");
                     self.state.cursor = record.cursor;
                     self.state.buffer = record.buffer;
+                    self.state.folds = record.folds;
                     if let Some(tree) = record.tree {
                         self.highlighter.set_tree(tree);
                     }
@@ -267,7 +980,705 @@ impl RootStore {
             ClearSearch => {
                 self.state.search_pattern.clear();
             }
+            Symbols => {
+                self.state.message = None;
+                self.symbols();
+            }
+            PushPicker(c) => self.state.picker.push(c),
+            PopPicker => self.state.picker.pop(),
+            SelectPickerItem(forward) => self.state.picker.select(forward, action.count),
+            Pick => self.pick(),
+            InspectTree if self.state.tree_view.is_some() => self.state.tree_view = None,
+            InspectTree => {
+                let buffer = self.state.buffer.clone();
+                match self.highlighter_mut().current_tree(&buffer) {
+                    Some(_) => self.state.tree_view = Some(Default::default()),
+                    None => {
+                        let filetype = self.state.filetype();
+                        let message = format!("No syntax tree for filetype '{}'", filetype);
+                        self.action(Fail(message).once());
+                    }
+                }
+            }
+            Inspect => self.inspect(),
         };
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Color, CursorShape, MemoryBackend, Screen};
+    use crate::editor::Editor;
+    use crate::language::LanguageConfig;
+    use crate::target;
+    use std::env;
+    use std::io::{Cursor, Read};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+
+    // A new file in the temporary directory named like `name`.
+    fn temp_file(name: &str, text: &str) -> String {
+        let i = FILES.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("neu-{}-{}-{}", process::id(), i, name));
+        fs::write(&path, text).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn open_store(args: &[String]) -> (RootStore, MemoryBackend) {
+        let backend = MemoryBackend::new(40, 8);
+        let renderer = Renderer::with_backend(Box::new(backend.clone()));
+        let (_, rx) = flume::unbounded();
+        let store = RootStore::open(&target::parse(args), false, rx, renderer);
+        (store, backend)
+    }
+
+    // Types `keys` until they run out, then waits for the highlighting to catch up.
+    fn type_keys(store: &mut RootStore, keys: &str) -> i32 {
+        type_input(store, Cursor::new(keys.as_bytes().to_vec()))
+    }
+
+    fn type_input(store: &mut RootStore, input: impl Read + Send + 'static) -> i32 {
+        let (tx, rx) = flume::unbounded();
+        store.rx = rx;
+        let editor = Editor::new(tx);
+        let status = smol::block_on(async {
+            smol::spawn(async move {
+                editor.run(input).await;
+                editor.quit();
+            })
+            .detach();
+            store.run().await
+        });
+        let buffer = store.state.buffer.clone();
+        store.highlighter_mut().current_tree(&buffer);
+        store.refresh();
+        status
+    }
+
+    // Opens `args` like the command line does, types `keys` and returns the store and the
+    // screen.
+    fn open(args: &[String], keys: &str) -> (RootStore, Screen) {
+        let (mut store, backend) = open_store(args);
+        type_keys(&mut store, keys);
+        (store, backend.screen())
+    }
+
+    // Opens `text` as a file named like `name`, types `keys` and returns what the file
+    // holds afterwards along with the screen.
+    fn run(name: &str, text: &str, keys: &str) -> (String, Screen) {
+        let path = temp_file(name, text);
+        let (_, screen) = open(std::slice::from_ref(&path), keys);
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        (text, screen)
+    }
+
+    fn lines(screen: &Screen) -> Vec<String> {
+        (0..screen.grid.size().1)
+            .map(|y| screen.grid.line(y).trim_end().to_string())
+            .collect()
+    }
+
+    // The style of the char at `x` on line `y`.
+    fn style(screen: &Screen, x: u16, y: u16) -> Style {
+        screen.grid.get(x, y).unwrap().style
+    }
+
+    #[test]
+    fn shows_lines_with_numbers_and_the_status_line() {
+        let (_, screen) = run("a.txt", "one\ntwo\n", "");
+        let lines = lines(&screen);
+        assert_eq!(lines[0], "1 one");
+        assert_eq!(lines[1], "2 two");
+        assert_eq!(lines[2], "");
+        assert!(lines[6].starts_with("NORMAL "), "{:?}", lines[6]);
+        assert!(lines[6].ends_with("a.txt"), "{:?}", lines[6]);
+        assert_eq!(style(&screen, 0, 0), Theme::default().get("ui.linenr"));
+    }
+
+    #[test]
+    fn redraws_only_what_changed() {
+        let path = temp_file("a.txt", "one\ntwo\n");
+        let (mut store, backend) = open_store(std::slice::from_ref(&path));
+        type_keys(&mut store, "");
+        let drawn = backend.screen().drawn;
+        assert!(drawn > 0);
+
+        store.refresh();
+        assert_eq!(backend.screen().drawn, drawn);
+
+        // `x` shifts what follows on its line.
+        type_keys(&mut store, "x");
+        fs::remove_file(&path).unwrap();
+        let screen = backend.screen();
+        assert_eq!(lines(&screen)[0], "1 ne");
+        assert!(screen.drawn - drawn <= 3, "{}", screen.drawn - drawn);
+    }
+
+    #[test]
+    fn moves_the_cursor() {
+        let (_, screen) = run("a.txt", "one\ntwo\n", "jl");
+        assert_eq!(screen.cursor, (3, 1));
+        assert_eq!(screen.cursor_shape, CursorShape::Block);
+    }
+
+    #[test]
+    fn types_in_insert_mode() {
+        let (text, screen) = run("a.txt", "one\n", "A two\x1b:w\n");
+        assert_eq!(text, "one two\n");
+        assert_eq!(lines(&screen)[0], "1 one two");
+        assert_eq!(screen.cursor, (8, 0));
+    }
+
+    #[test]
+    fn shows_insert_mode_with_a_bar_cursor() {
+        let (_, screen) = run("a.txt", "one\n", "A");
+        assert!(lines(&screen)[6].starts_with("INSERT "));
+        assert_eq!(screen.cursor_shape, CursorShape::Bar);
+        assert_eq!(screen.cursor, (5, 0));
+    }
+
+    #[test]
+    fn stops_a_count_at_the_first_and_last_line() {
+        let text = "one\ntwo\nthree\nfour\n";
+        let (text, _) = run("a.txt", text, "99999999jx99999999kx:w\n");
+        assert_eq!(text, "ne\ntwo\nthree\nour\n");
+        let (text, _) = run("a.txt", &text, "jd99999999j:w\n");
+        assert_eq!(text, "ne\n");
+
+        // Over a closed fold.
+        let (text, _) = run("a.txt", "1\n2\n3\n4\n5\n", "jzfjjd99999999k:w\n");
+        assert_eq!(text, "5\n");
+    }
+
+    #[test]
+    fn backspaces_over_the_indent_and_the_insert_start() {
+        // In the indent a backspace goes back by 'shiftwidth', and `.` repeats what it
+        // removed.
+        let text = "a\n    b\n    c\n";
+        let (text, _) = run("a.txt", text, ":set et sw=4\njI\x7f\x7f\x1bj.:w\n");
+        assert_eq!(text, "abc\n");
+        let (text, _) = run("a.txt", "x\n", ":set et sw=4 ts=8\nI\t\x7fy\x1b:w\n");
+        assert_eq!(text, "    yx\n");
+
+        // A backspace goes on over the insert start, <C-w> and <C-u> stop there once.
+        let (text, _) = run("a.txt", "foo bar\n", "A\x7f\x7f\x1b:w\n");
+        assert_eq!(text, "foo b\n");
+        let (text, _) = run("a.txt", "foo bar\n", "A baz\x17\x17\x1b:w\n");
+        assert_eq!(text, "foo bar\n");
+        let (text, _) = run("a.txt", "foo bar\n", "A baz\x17\x17\x17\x1b:w\n");
+        assert_eq!(text, "foo \n");
+        let (text, _) = run("a.txt", "  foo bar\n", "A baz\x15\x15\x1b:w\n");
+        assert_eq!(text, "  \n");
+    }
+
+    #[test]
+    fn breaks_the_insert_at_arrow_keys() {
+        // Only what was typed after the arrow is undone, and what `.` repeats.
+        let (text, _) = run("a.txt", "one\n", "Atwo\x1b[Dx\x1bu0.:w\n");
+        assert_eq!(text, "xonetwo\n");
+        let (text, _) = run("a.txt", "one\n", "Atwo\x1b[Dx\x1buu:w\n");
+        assert_eq!(text, "one\n");
+        // Moving before typing anything is no break.
+        let (text, _) = run("a.txt", "one\n", "A\x1b[D\x1b[Dx\x1bu:w\n");
+        assert_eq!(text, "one\n");
+    }
+
+    #[test]
+    fn inserts_registers_as_they_are() {
+        let (text, _) = run("a.txt", "x\x7fy\x08\n", "yyA\n\x12\"\x1b:w\n");
+        assert_eq!(text, "x\x7fy\x08\nx\x7fy\x08\n\n");
+        // `.` is the text left of the last insert.
+        let (text, _) = run("a.txt", "\n", "Aab\x7fc\x1bA\n\x12.\x1b:w\n");
+        assert_eq!(text, "ac\nac\n");
+    }
+
+    #[test]
+    fn scrolls_to_keep_the_cursor_visible() {
+        let text: String = (1..=20).map(|i| format!("line{}\n", i)).collect();
+        let (_, screen) = run("a.txt", &text, "15j");
+        // Six rows of text, the last of them the cursor line.
+        assert_eq!(lines(&screen)[0], "11 line11");
+        assert_eq!(lines(&screen)[5], "16 line16");
+        assert_eq!(screen.cursor, (3, 5));
+
+        let (_, screen) = run("a.txt", &text, "Gk");
+        assert_eq!(lines(&screen)[5], "20 line20");
+        assert_eq!(screen.cursor, (3, 4));
+    }
+
+    #[test]
+    fn reflows_the_screen_when_resized() {
+        let text: String = (1..=20).map(|i| format!("line{}\n", i)).collect();
+        let path = temp_file("a.txt", &text);
+        let (mut store, backend) = open_store(std::slice::from_ref(&path));
+        type_keys(&mut store, "5j");
+        fs::remove_file(&path).unwrap();
+
+        // Three rows of text are left, and the cursor line stays on screen.
+        store.action(ActionKind::Resize(20, 5).once());
+        store.refresh();
+        let screen = backend.screen();
+        assert_eq!(screen.grid.size(), (20, 5));
+        let lines = lines(&screen);
+        assert_eq!(lines[..3], [" 4 line4", " 5 line5", " 6 line6"]);
+        assert!(lines[3].starts_with("NORMAL "), "{:?}", lines[3]);
+        assert_eq!(lines[3].chars().count(), 20);
+        assert_eq!(screen.cursor, (3, 2));
+
+        store.action(ActionKind::Resize(40, 10).once());
+        store.refresh();
+        let screen = backend.screen();
+        assert_eq!(screen.grid.size(), (40, 10));
+        let lines: Vec<_> = (0..10).map(|y| screen.grid.line(y)).collect();
+        assert!(lines[0].starts_with(" 4 line4 "));
+        assert!(lines[7].starts_with("11 line11 "));
+        assert!(lines[8].starts_with("NORMAL "), "{:?}", lines[8]);
+        assert_eq!(screen.cursor, (3, 2));
+    }
+
+    #[test]
+    fn shows_the_command_line_while_typing() {
+        let (_, screen) = run("a.txt", "one\n", ":set nu");
+        let lines = lines(&screen);
+        assert!(lines[6].starts_with("COMMAND "));
+        assert_eq!(lines[7], ":set nu");
+    }
+
+    // Sends each chunk after a pause.
+    struct Pauses(Vec<&'static str>);
+
+    impl Read for Pauses {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            std::thread::sleep(Duration::from_millis(200));
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_mapping_until_the_timeout() {
+        let path = temp_file("a.txt", "\n");
+        let (mut store, _) = open_store(std::slice::from_ref(&path));
+        fs::remove_file(&path).unwrap();
+        let config = "[keymap]\ntimeout = 50\ninsert = { jk = \"<Esc>\" }";
+        store.configure(&toml::from_str(config).unwrap());
+
+        type_input(&mut store, Pauses(vec!["ij", "k"]));
+        assert_eq!(store.state.buffer.as_str(), "jk\n");
+        assert!(store.state.mode.is_insert());
+
+        type_input(&mut store, Pauses(vec!["jk"]));
+        assert_eq!(store.state.buffer.as_str(), "jk\n");
+        assert!(!store.state.mode.is_insert());
+    }
+
+    #[test]
+    fn highlights_search_matches() {
+        let (_, screen) = run("a.txt", "foo bar foo\n", "/foo\nn");
+        let search = Theme::default().get("ui.search");
+        for x in [2, 3, 4, 10, 11, 12] {
+            assert_eq!(style(&screen, x, 0).bg, search.bg, "at {}", x);
+        }
+        assert_eq!(style(&screen, 6, 0).bg, Color::Reset);
+        assert_eq!(screen.cursor, (10, 0));
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        // 38 columns are left after the line numbers.
+        let long = format!("{}foo{}", "a".repeat(36), "b".repeat(12));
+        let text = format!("{}\ntwo\n", long);
+        let (_, screen) = run("a.txt", &text, "/foo\n$");
+        let rows = lines(&screen);
+        assert_eq!(rows[0], format!("1 {}fo", "a".repeat(36)));
+        assert_eq!(rows[1], format!("  o{}", "b".repeat(12)));
+        assert_eq!(rows[2], "2 two");
+        assert_eq!(screen.cursor, (14, 1));
+        // The match is marked on both rows.
+        let search = Theme::default().get("ui.search");
+        for (x, y) in [(38, 0), (39, 0), (2, 1)] {
+            assert_eq!(style(&screen, x, y).bg, search.bg, "at {:?}", (x, y));
+        }
+        assert_eq!(style(&screen, 3, 1).bg, Color::Reset);
+
+        let (_, screen) = run("a.txt", &text, ":set nowrap\n");
+        let rows = lines(&screen);
+        assert_eq!(rows[0], format!("1 {}fo", "a".repeat(36)));
+        assert_eq!(rows[1], "2 two");
+    }
+
+    #[test]
+    fn wraps_tabs_and_wide_chars() {
+        // A wide char that does not fit starts the next row, a tab is cut where it wraps.
+        let text = format!("{}日\n\t{}\tx\n", "a".repeat(37), "b".repeat(35));
+        let (_, screen) = run("a.txt", &text, "");
+        let rows = lines(&screen);
+        assert_eq!(rows[0], format!("1 {}", "a".repeat(37)));
+        assert_eq!(rows[1], "  日");
+        assert_eq!(rows[2], format!("2         {}", "b".repeat(30)));
+        assert_eq!(rows[3], format!("  {}     x", "b".repeat(5)));
+    }
+
+    #[test]
+    fn scrolls_over_wrapped_lines() {
+        // Six rows of text, two for each line.
+        let text: String = (1..=9)
+            .map(|i| format!("{}{}\n", i, "x".repeat(40)))
+            .collect();
+        let (_, screen) = run("a.txt", &text, "4j");
+        let rows = lines(&screen);
+        assert!(rows[0].starts_with("3 3x"), "{:?}", rows[0]);
+        assert!(rows[4].starts_with("5 5x"), "{:?}", rows[4]);
+        assert_eq!(screen.cursor, (2, 4));
+
+        let (_, screen) = run("a.txt", &text, "Gk");
+        assert!(lines(&screen)[0].starts_with("7 7x"));
+        assert_eq!(screen.cursor, (2, 2));
+    }
+
+    #[test]
+    fn sets_options_over_the_language_settings() {
+        let rs = temp_file("a.rs", "fn main() {}\n");
+        let txt = temp_file("a.txt", "one\n");
+        let (mut store, _) = open_store(&[rs.clone(), txt.clone()]);
+        store.configure(&toml::from_str("[options]\nshiftwidth = 3\nnumber = false").unwrap());
+        fs::remove_file(&rs).unwrap();
+        fs::remove_file(&txt).unwrap();
+        let shiftwidth = |store: &RootStore| store.state.options().number("shiftwidth");
+
+        // global < language
+        assert_eq!(shiftwidth(&store), 4);
+        assert!(!store.state.options().bool("number"));
+        type_keys(&mut store, ":bn\n");
+        assert_eq!(shiftwidth(&store), 3);
+
+        // language < :setlocal, which stays with its buffer
+        type_keys(&mut store, ":bn\n:setlocal sw=2\n");
+        assert_eq!(shiftwidth(&store), 2);
+        type_keys(&mut store, ":bn\n");
+        assert_eq!(shiftwidth(&store), 3);
+
+        // :set changes the default too, which a local value still goes over.
+        type_keys(&mut store, ":set sw=5\n");
+        assert_eq!(shiftwidth(&store), 5);
+        type_keys(&mut store, ":bn\n");
+        assert_eq!(shiftwidth(&store), 2);
+    }
+
+    #[test]
+    fn keeps_the_filetype_to_its_buffer() {
+        let a = temp_file("a.txt", "one\n");
+        let b = temp_file("b.txt", "two\n");
+        let (store, _) = open(&[a.clone(), b.clone()], ":set ft=python\n:bn\n");
+        fs::remove_file(&a).unwrap();
+        fs::remove_file(&b).unwrap();
+        assert_eq!(store.state.filetype(), "");
+        assert_eq!(store.state.options().string("filetype"), "");
+    }
+
+    #[test]
+    fn highlights_syntax() {
+        let (_, screen) = run("a.rs", "fn main() {}\n", "");
+        assert_eq!(style(&screen, 2, 0), Theme::default().get("keyword"));
+    }
+
+    #[test]
+    fn highlights_injected_languages_after_edits() {
+        let text = "# Title\n\n```rust\nfn f() {}\n```\n";
+        let (_, screen) = run("a.md", text, "3jIpub \x1b");
+        assert_eq!(lines(&screen)[3], "4 pub fn f() {}");
+        let keyword = Theme::default().get("keyword");
+        assert_eq!(style(&screen, 2, 3), keyword);
+        assert_eq!(style(&screen, 6, 3), keyword);
+        assert_ne!(style(&screen, 2, 2), keyword);
+    }
+
+    // `cargo test` builds the examples next to the test binary, `cargo test --bin` does not.
+    fn mock_lsp() -> String {
+        let exe = env::current_exe().unwrap();
+        let path = exe.parent().unwrap().parent().unwrap();
+        let path = path.join("examples").join("mock_lsp");
+        if !path.exists() {
+            let status = Command::new(env!("CARGO"))
+                .args(["build", "--example", "mock_lsp"])
+                .current_dir(env!("CARGO_MANIFEST_DIR"))
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        path.to_string_lossy().to_string()
+    }
+
+    // Handles the messages of the servers until `done` holds.
+    fn wait(store: &mut RootStore, done: impl Fn(&RootStore) -> bool) {
+        let events = store.clients.events();
+        while !done(store) {
+            let event = events.recv_timeout(Duration::from_secs(10)).unwrap();
+            store.lsp().receive(event);
+            store.refresh();
+        }
+    }
+
+    fn has_diagnostic(store: &RootStore, row: usize, message: &str) -> bool {
+        store
+            .state
+            .diagnostics
+            .iter()
+            .any(|d| d.start.row == row && d.message == message)
+    }
+
+    // A Rust file opened with the mock server, once the server has seen it.
+    fn open_with_server(text: &str) -> (RootStore, String) {
+        let path = temp_file("a.rs", text);
+        let (mut store, _) = open_store(std::slice::from_ref(&path));
+        let mut config = NeuConfig::default();
+        let language = LanguageConfig {
+            lsp: Some(mock_lsp()),
+            ..LanguageConfig::default()
+        };
+        config.language.insert("rust".to_string(), language);
+        store.configure(&config);
+        wait(&mut store, |s| {
+            s.clients.diagnostics.values().next().is_some()
+        });
+        (store, path)
+    }
+
+    fn version(store: &RootStore) -> i64 {
+        let client = store.clients.get("rust").unwrap();
+        *client.documents.values().next().unwrap()
+    }
+
+    fn insert(store: &mut RootStore, s: &str) {
+        store.action(ActionKind::from(EditKind::InsertString(None, s.into())).once());
+    }
+
+    #[test]
+    fn moves_over_syntax_nodes() {
+        let text = concat!(
+            "// one\n// two\nstruct S;\n\n",
+            "impl S {\n    fn f() {}\n\n    fn g() {\n        f();\n    }\n}\n",
+            "fn main() {}\n",
+        );
+        let path = temp_file("a.rs", text);
+        let cursor = |keys: &str| {
+            let (store, _) = open(std::slice::from_ref(&path), keys);
+            (store.state.cursor.row, store.state.cursor.col)
+        };
+        assert_eq!(cursor("]m"), (5, 4));
+        assert_eq!(cursor("2]m"), (7, 4));
+        // As far as there are any.
+        assert_eq!(cursor("9]m"), (11, 0));
+        assert_eq!(cursor("G[m"), (7, 4));
+        assert_eq!(cursor("G2[m"), (5, 4));
+        assert_eq!(cursor("]M"), (5, 12));
+        assert_eq!(cursor("G[M"), (9, 4));
+        // Items are the outermost functions and classes.
+        assert_eq!(cursor("]]"), (2, 0));
+        assert_eq!(cursor("2]]"), (4, 0));
+        assert_eq!(cursor("3]]"), (11, 0));
+        assert_eq!(cursor("G[["), (4, 0));
+        // Comments on consecutive lines are one.
+        assert_eq!(cursor("G[c"), (0, 0));
+        assert_eq!(cursor("G2[c"), (0, 0));
+
+        let (text, _) = run("a.rs", text, "]md]M:w\n");
+        assert_eq!(text.lines().nth(5), Some("    "));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn inspects_the_tree_in_a_split() {
+        let text = "fn main() { let long_name = 1; }\n";
+        let (_, screen) = run("a.rs", text, "w:InspectTree\n");
+        // The text wraps before the split.
+        assert_eq!(lines(&screen)[0], "1 fn main() { let lo│(source_file");
+        assert_eq!(lines(&screen)[1], "  ng_name = 1; }    │  (function_item");
+        assert_eq!(
+            lines(&screen)[2],
+            "                    │    name: (identifi"
+        );
+        // The node at the cursor is the current one.
+        assert_ne!(style(&screen, 25, 2), Style::default());
+        assert_eq!(style(&screen, 25, 1), Style::default());
+        assert_eq!(screen.cursor, (5, 0));
+
+        let (_, screen) = run("a.rs", text, "w:InspectTree\n:InspectTree\n");
+        assert_eq!(lines(&screen)[0], "1 fn main() { let long_name = 1; }");
+        assert_eq!(lines(&screen)[1], "");
+    }
+
+    #[test]
+    fn goes_through_the_tabstops_of_a_snippet() {
+        let path = temp_file("a.rs", "    \n");
+        let (mut store, _) = open_store(std::slice::from_ref(&path));
+        type_keys(&mut store, "A");
+        store
+            .snippet()
+            .expand(4..4, "fn ${1:name}($2) -> ${1} {\n\t$0\n}");
+        assert_eq!(
+            store.state.buffer.as_str(),
+            "    fn name() -> name {\n        \n    }\n"
+        );
+        assert_eq!(store.state.cursor, Position { row: 0, col: 11 });
+
+        // Typing replaces the placeholder, and the mirror follows.
+        type_keys(&mut store, "foo\tx: u8");
+        assert_eq!(
+            store.state.buffer.as_str(),
+            "    fn foo(x: u8) -> foo {\n        \n    }\n"
+        );
+        type_keys(&mut store, "\x1b[Zbar\t\tbody");
+        assert_eq!(
+            store.state.buffer.as_str(),
+            "    fn bar(x: u8) -> bar {\n        body\n    }\n"
+        );
+        // The session is over at `$0`, and a tab is typed as usual.
+        assert!(store.state.snippet.is_none());
+        type_keys(&mut store, "\t");
+        assert_eq!(store.state.buffer.line(1).as_str(), "        body    \n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn talks_to_the_language_server() {
+        let (mut store, path) =
+            open_with_server("fn todo() {}\nfn main() {\n    todo(); // TODO\n}\n");
+
+        // didOpen
+        wait(&mut store, |s| {
+            has_diagnostic(s, 2, "mock: TODO left in the code")
+        });
+
+        // didChange
+        insert(&mut store, "// FIXME\n");
+        store.refresh();
+        wait(&mut store, |s| {
+            has_diagnostic(s, 0, "mock: FIXME left in the code")
+                && has_diagnostic(s, 3, "mock: TODO left in the code")
+        });
+
+        store.state.cursor = Position { row: 3, col: 5 };
+        store.action(ActionKind::from(LspKind::Hover).once());
+        wait(&mut store, |s| {
+            s.state.popup.iter().any(|l| l == "`todo` appears 2 times")
+        });
+
+        store.action(ActionKind::from(LspKind::Definition).once());
+        wait(&mut store, |s| {
+            s.state.cursor == Position { row: 1, col: 3 }
+        });
+
+        store.action(ActionKind::from(LspKind::Rename("done".into())).once());
+        wait(&mut store, |s| {
+            s.state.buffer.as_str().contains("fn done()")
+        });
+        assert_eq!(
+            store.state.buffer.as_str(),
+            "// FIXME\nfn done() {}\nfn main() {\n    done(); // TODO\n}\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sends_a_new_version_for_each_sync() {
+        let (mut store, path) = open_with_server("fn main() {}\n");
+        assert_eq!(version(&store), 0);
+
+        // The edits between two syncs go in one didChange.
+        insert(&mut store, "// TODO\n");
+        insert(&mut store, "// FIXME\n");
+        store.refresh();
+        assert_eq!(version(&store), 1);
+        store.refresh();
+        assert_eq!(version(&store), 1);
+
+        store.state.cursor = Position { row: 2, col: 0 };
+        insert(&mut store, "// TODO\n");
+        store.refresh();
+        assert_eq!(version(&store), 2);
+        wait(&mut store, |s| {
+            has_diagnostic(s, 0, "mock: TODO left in the code")
+                && has_diagnostic(s, 1, "mock: FIXME left in the code")
+                && has_diagnostic(s, 2, "mock: TODO left in the code")
+        });
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn drops_responses_about_an_older_version() {
+        let (mut store, path) = open_with_server("fn main() {}\n");
+        store.state.cursor = Position { row: 0, col: 3 };
+        store.action(ActionKind::from(LspKind::Hover).once());
+        insert(&mut store, "// TODO\n");
+        store.refresh();
+
+        // The server answers the hover before it publishes the diagnostics of the change.
+        wait(&mut store, |s| {
+            has_diagnostic(s, 0, "mock: TODO left in the code")
+        });
+        assert!(store.state.popup.is_empty(), "{:?}", store.state.popup);
+
+        store.state.cursor = Position { row: 1, col: 3 };
+        store.action(ActionKind::from(LspKind::Hover).once());
+        wait(&mut store, |s| !s.state.popup.is_empty());
+        assert_eq!(store.state.popup, ["main", "", "`main` appears 1 times"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn forgets_a_server_that_exits() {
+        let (mut store, path) = open_with_server("// TODO\n");
+        assert!(has_diagnostic(&store, 0, "mock: TODO left in the code"));
+        let client = store.clients.get_mut("rust").unwrap();
+        client.notify("exit", serde_json::Value::Null);
+        wait(&mut store, |s| s.clients.get("rust").is_none());
+
+        let message = store.state.message.clone().unwrap();
+        assert!(message.ends_with("mock_lsp exited"), "{:?}", message);
+        assert!(store.state.diagnostics.is_empty());
+        // It is not restarted, and requests tell there is no server.
+        insert(&mut store, "x");
+        store.refresh();
+        assert!(store.clients.get("rust").is_none());
+        store.action(ActionKind::from(LspKind::Hover).once());
+        let message = store.state.message.clone().unwrap();
+        assert_eq!(message, "No language server for filetype 'rust'");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shows_why_a_buffer_opened_read_only_when_switching_to_it() {
+        let path = temp_file("a.txt", "one\n");
+        let dir = env::temp_dir().to_string_lossy().to_string();
+        let (store, screen) = open(&[path.clone(), dir], ":bn\n");
+        fs::remove_file(&path).unwrap();
+        assert!(store.state.read_only);
+        let status = &lines(&screen)[6];
+        assert!(status.contains("[RO]"), "{:?}", status);
+        assert!(status.contains("Is a directory"), "{:?}", status);
+    }
+
+    #[test]
+    fn fails_the_exit_status_only_when_headless() {
+        let path = temp_file("a.txt", "one\n");
+        let (mut store, _) = open_store(std::slice::from_ref(&path));
+        assert_eq!(type_keys(&mut store, ":w /nonexistent/a.txt\n"), 0);
+        assert!(store.state.message.as_ref().unwrap().starts_with("E212"));
+
+        let (mut store, _) = open_store(std::slice::from_ref(&path));
+        store.set_headless();
+        assert_eq!(type_keys(&mut store, ":w /nonexistent/a.txt\n"), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use crate::buffer::Buffer;
+use core::cmp::Reverse;
+use core::ops::Range;
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+// The largest named node that spans the same bytes as the smallest named node around
+// `bytes`, so that e.g. an identifier that is a whole expression counts as the expression.
+pub(super) fn node_at(tree: &Tree, bytes: Range<usize>) -> Option<Node<'_>> {
+    let mut node = tree
+        .root_node()
+        .named_descendant_for_byte_range(bytes.start, bytes.end)?;
+    while let Some(parent) = node.parent() {
+        if parent.byte_range() != node.byte_range() {
+            break;
+        }
+        node = parent;
+    }
+    Some(node)
+}
+
+// The smallest named node that is larger than `bytes`.
+pub(super) fn expand(tree: &Tree, bytes: Range<usize>) -> Option<Range<usize>> {
+    let mut node = tree
+        .root_node()
+        .named_descendant_for_byte_range(bytes.start, bytes.end)?;
+    while bytes.start <= node.start_byte() && node.end_byte() <= bytes.end {
+        node = node.parent()?;
+    }
+    Some(node.byte_range())
+}
+
+pub(super) fn sibling(node: Node, forward: bool) -> Option<Node> {
+    match forward {
+        true => node.next_named_sibling(),
+        false => node.prev_named_sibling(),
+    }
+}
+
+// Byte ranges of the captures of `query` named one of `names`, in order.
+pub(super) fn captures(query: &Query, root: Node, b: &Buffer, names: &[&str]) -> Vec<Range<usize>> {
+    let mut cursor = QueryCursor::new();
+    let captures = cursor.captures(query, root, |node: Node| {
+        let start = node.start_byte();
+        if let Some((chunks, _, _, _)) = b.get_chunks_at_byte(start) {
+            return chunks.map(|s| s.as_bytes());
+        }
+        unreachable!()
+    });
+
+    let mut result = Vec::new();
+    for (matched, index) in captures {
+        let capture = matched.captures[index];
+        let name = &query.capture_names()[capture.index as usize];
+        if names.contains(&name.as_str()) {
+            result.push(capture.node.byte_range());
+        }
+    }
+    result.sort_by_key(|r| (r.start, Reverse(r.end)));
+    result.dedup();
+    result
+}
+
+// Drops the ranges inside another one.
+pub(super) fn outermost(ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    let mut result: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match result.last() {
+            Some(last) if range.end <= last.end => {}
+            _ => result.push(range),
+        }
+    }
+    result
+}
+
+// Joins ranges that start on the line after the previous one ends, like a run of line
+// comments.
+pub(super) fn join_lines(ranges: Vec<Range<usize>>, b: &Buffer) -> Vec<Range<usize>> {
+    let mut result: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match result.last_mut() {
+            Some(last)
+                if b.0.byte_to_line(range.start)
+                    <= b.0.byte_to_line(last.end.saturating_sub(1)) + 1 =>
+            {
+                last.end = range.end;
+            }
+            _ => result.push(range),
+        }
+    }
+    result
+}
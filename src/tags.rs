@@ -0,0 +1,83 @@
+use crate::buffer::Buffer;
+use core::cmp::Reverse;
+use core::ops::Range;
+use tree_sitter::{Node, Query, QueryCursor};
+
+// A definition captured by a `tags.scm` query, like a function or a struct.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Tag {
+    // What follows `definition.` in the capture name.
+    pub(super) kind: String,
+    pub(super) name: String,
+    pub(super) definition: Range<usize>,
+    pub(super) name_range: Range<usize>,
+    // The number of tags around it.
+    pub(super) depth: usize,
+}
+
+impl Tag {
+    // `fn action`, `impl RootStore`.
+    pub(super) fn label(&self) -> String {
+        let keyword = match self.kind.as_str() {
+            "function" | "method" => "fn",
+            "module" => "mod",
+            kind => kind,
+        };
+        format!("{} {}", keyword, self.name)
+    }
+}
+
+// The name on one line, as in `impl<T>\n    Foo<T>`.
+fn text(b: &Buffer, bytes: Range<usize>) -> String {
+    let bytes: Vec<_> = b.bytes_at(bytes.start).take(bytes.len()).collect();
+    let text = String::from_utf8_lossy(&bytes);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// The `@definition.*` captures of a `tags.scm` query with their `@name`, in order. Only
+// the definitions overlapping `bytes` are looked at.
+pub(super) fn find(query: &Query, root: Node, b: &Buffer, bytes: Range<usize>) -> Vec<Tag> {
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(bytes);
+    let matches = cursor.matches(query, root, |node: Node| {
+        let start = node.start_byte();
+        if let Some((chunks, _, _, _)) = b.get_chunks_at_byte(start) {
+            return chunks.map(|s| s.as_bytes());
+        }
+        unreachable!()
+    });
+
+    let mut tags = Vec::new();
+    for matched in matches {
+        let (mut definition, mut name) = (None, None);
+        for capture in matched.captures {
+            let capture_name = &query.capture_names()[capture.index as usize];
+            match capture_name.strip_prefix("definition.") {
+                Some(kind) => definition = Some((kind.to_string(), capture.node.byte_range())),
+                None if capture_name == "name" => name = Some(capture.node.byte_range()),
+                None => {}
+            }
+        }
+        if let (Some((kind, definition)), Some(name_range)) = (definition, name) {
+            tags.push(Tag {
+                kind,
+                name: text(b, name_range.clone()),
+                definition,
+                name_range,
+                depth: 0,
+            });
+        }
+    }
+    tags.sort_by_key(|tag| (tag.definition.start, Reverse(tag.definition.end)));
+    tags.dedup_by(|a, b| a.definition == b.definition);
+
+    let mut ends: Vec<usize> = Vec::new();
+    for tag in &mut tags {
+        while ends.last().is_some_and(|&end| end <= tag.definition.start) {
+            ends.pop();
+        }
+        tag.depth = ends.len();
+        ends.push(tag.definition.end);
+    }
+    tags
+}
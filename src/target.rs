@@ -0,0 +1,92 @@
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Jump {
+    Line(usize),
+    LineCol(usize, usize),
+    Search(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Source {
+    Path(String),
+    Stdin,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Target {
+    pub(super) source: Source,
+    pub(super) jump: Option<Jump>,
+}
+
+fn parse_jump(arg: &str) -> Option<Jump> {
+    let arg = arg.strip_prefix('+')?;
+    if arg.is_empty() {
+        return Some(Jump::Line(usize::MAX));
+    }
+    if let Some(pattern) = arg.strip_prefix('/') {
+        return Some(Jump::Search(pattern.to_string()));
+    }
+    arg.parse().ok().map(Jump::Line)
+}
+
+// Accepts `file`, `file:line` and `file:line:col` as emitted by compilers,
+// unless a file with the literal name exists.
+fn parse_location(arg: &str) -> Target {
+    let location = || {
+        let arg = arg.strip_suffix(':').unwrap_or(arg);
+        let (rest, last) = arg.rsplit_once(':')?;
+        let last = last.parse().ok()?;
+        if let Some((path, line)) = rest.rsplit_once(':') {
+            if let Ok(line) = line.parse() {
+                return Some((path, Jump::LineCol(line, last)));
+            }
+        }
+        Some((rest, Jump::Line(last)))
+    };
+
+    if !Path::new(arg).exists() {
+        if let Some((path, jump)) = location() {
+            return Target {
+                source: Source::Path(path.to_string()),
+                jump: Some(jump),
+            };
+        }
+    }
+
+    Target {
+        source: Source::Path(arg.to_string()),
+        jump: None,
+    }
+}
+
+pub(super) fn parse(args: &[String]) -> Vec<Target> {
+    let mut targets: Vec<Target> = Vec::new();
+    let mut pending = None;
+    for arg in args {
+        if let Some(jump) = parse_jump(arg) {
+            pending = Some(jump);
+            continue;
+        }
+
+        let mut target = if arg == "-" {
+            Target {
+                source: Source::Stdin,
+                jump: None,
+            }
+        } else {
+            parse_location(arg)
+        };
+        if let Some(jump) = pending.take() {
+            target.jump = Some(jump);
+        }
+        targets.push(target);
+    }
+
+    if let Some(jump) = pending {
+        if let Some(target) = targets.first_mut() {
+            target.jump = Some(jump);
+        }
+    }
+    targets
+}
@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use dirs::home_dir;
+use hashbrown::HashMap;
+use std::fs;
+use toml::Value;
+
+use crate::backend::{Color, Style};
+
+const DEFAULT_NAME: &str = "default";
+
+const DEFAULT: &str = r#"
+"keyword" = "magenta"
+"attribute" = "red"
+"constant.builtin" = "red"
+"property" = "red"
+"function" = "blue"
+"function.macro" = "red"
+"type" = "yellow"
+"string" = "green"
+"variable" = "white"
+"variable.parameter" = "red"
+"variable.builtin" = "cyan"
+"punctuation" = "light_cyan"
+"operator" = "white"
+"label" = "yellow"
+"comment" = "light_black"
+"escape" = "magenta"
+"number" = "magenta"
+"boolean" = "magenta"
+"text.title" = { fg = "blue", bold = true }
+"text.literal" = "green"
+"text.emphasis" = { italic = true }
+"text.strong" = { bold = true }
+"text.uri" = { fg = "cyan", underline = true }
+"text.reference" = "cyan"
+
+"ui.linenr" = { fg = "light_blue", bg = "light_green" }
+"ui.search" = { bg = "green" }
+"ui.selection" = { bg = "blue" }
+"ui.reference" = { underline = true }
+"ui.fold" = { fg = "cyan", bg = "light_black" }
+"ui.split" = "light_black"
+"ui.diagnostic" = { underline = true }
+"ui.sign.error" = "red"
+"ui.sign.warning" = "yellow"
+"ui.sign.info" = "blue"
+"ui.sign.hint" = "cyan"
+"ui.popup" = { bg = "light_black" }
+"ui.menu" = { bg = "light_black" }
+"ui.menu.selected" = { fg = "black", bg = "cyan" }
+"ui.picker" = { bg = "light_black" }
+"ui.picker.selected" = { fg = "black", bg = "cyan" }
+"ui.statusline" = {}
+"#;
+
+const COLOR_NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "light_black",
+    "light_red",
+    "light_green",
+    "light_yellow",
+    "light_blue",
+    "light_magenta",
+    "light_cyan",
+    "light_white",
+];
+
+fn parse_color(v: &Value) -> Result<Color> {
+    match v {
+        Value::Integer(n) if (0..=255).contains(n) => Ok(Color::Ansi(*n as u8)),
+        Value::String(s) if s.starts_with('#') && s.len() == 7 && s.is_ascii() => {
+            let channel = |i: usize| u8::from_str_radix(&s[i..i + 2], 16);
+            Ok(Color::Rgb(channel(1)?, channel(3)?, channel(5)?))
+        }
+        Value::String(s) if s == "reset" || s == "default" => Ok(Color::Reset),
+        Value::String(s) => COLOR_NAMES
+            .iter()
+            .position(|name| name == s)
+            .map(|i| Color::Ansi(i as u8))
+            .ok_or_else(|| anyhow!("unknown color: {}", s)),
+        v => Err(anyhow!("invalid color: {}", v)),
+    }
+}
+
+fn parse_style(v: &Value) -> Result<Style> {
+    let table = match v {
+        Value::Table(table) => table,
+        v => return Ok(Style::fg(parse_color(v)?)),
+    };
+
+    let mut style = Style::default();
+    for (key, v) in table {
+        match key.as_str() {
+            "fg" => style.fg = parse_color(v)?,
+            "bg" => style.bg = parse_color(v)?,
+            "bold" => style.bold = v.as_bool().unwrap_or(false),
+            "italic" => style.italic = v.as_bool().unwrap_or(false),
+            "underline" => style.underline = v.as_bool().unwrap_or(false),
+            key => return Err(anyhow!("unknown style attribute: {}", key)),
+        }
+    }
+    Ok(style)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Theme {
+    pub(super) name: String,
+    styles: HashMap<String, Style>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::parse(DEFAULT_NAME, DEFAULT).unwrap()
+    }
+}
+
+impl Theme {
+    fn parse(name: &str, s: &str) -> Result<Self> {
+        let table: toml::value::Table = toml::from_str(s)?;
+        let mut styles = HashMap::new();
+        for (key, v) in table {
+            let style = parse_style(&v).map_err(|e| anyhow!("{}: {}", key, e))?;
+            styles.insert(key, style);
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            styles,
+        })
+    }
+
+    pub(super) fn load(name: &str) -> Result<Self> {
+        if name == DEFAULT_NAME {
+            return Ok(Self::default());
+        }
+        let path = home_dir()
+            .ok_or_else(|| anyhow!("no home directory"))?
+            .join(".config/neu/themes")
+            .join(format!("{}.toml", name));
+        let s = fs::read_to_string(path)?;
+        Self::parse(name, &s)
+    }
+
+    // Looks up `function.method`, then `function`.
+    pub(super) fn get(&self, scope: &str) -> Style {
+        let mut scope = scope;
+        loop {
+            if let Some(style) = self.styles.get(scope) {
+                return *style;
+            }
+            match scope.rfind('.') {
+                Some(i) => scope = &scope[..i],
+                None => {
+                    tracing::debug!("no style for {}", scope);
+                    return Style::default();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_the_parent_scope() {
+        let theme = Theme::parse(
+            "test",
+            r##""function" = "blue"
+"function.macro" = { fg = "#ff0000", bold = true }"##,
+        )
+        .unwrap();
+        let blue = Style::fg(Color::Ansi(4));
+        assert_eq!(theme.get("function"), blue);
+        assert_eq!(theme.get("function.method"), blue);
+        assert_eq!(theme.get("function.method.call"), blue);
+        let red = Style {
+            bold: true,
+            ..Style::fg(Color::Rgb(255, 0, 0))
+        };
+        assert_eq!(theme.get("function.macro"), red);
+        assert_eq!(theme.get("function.macro.builtin"), red);
+        // A scope only matches whole parts.
+        assert_eq!(theme.get("functions"), Style::default());
+        assert_eq!(theme.get("type.builtin"), Style::default());
+    }
+
+    #[test]
+    fn rejects_invalid_styles() {
+        for (s, error) in [
+            (r##""a" = "#12345g""##, "a: invalid digit found in string"),
+            (r##""a" = "#ééé""##, "a: unknown color: #ééé"),
+            (r#""a" = "mauve""#, "a: unknown color: mauve"),
+            (r#""a" = 256"#, "a: invalid color: 256"),
+            (
+                r#""a" = { blink = true }"#,
+                "a: unknown style attribute: blink",
+            ),
+        ] {
+            let e = Theme::parse("test", s).unwrap_err();
+            assert_eq!(e.to_string(), error, "{}", s);
+        }
+    }
+}